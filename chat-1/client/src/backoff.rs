@@ -0,0 +1,43 @@
+use std::time::Duration;
+
+/// Backoff produces the delays between reconnect attempts. Each delay doubles the last one up to
+/// a maximum, and is then jittered so that clients dropped at the same time do not all come back
+/// at the same time.
+#[derive(Default)]
+pub struct Backoff {
+    attempt: u32,
+}
+
+impl Backoff {
+    const BASE: Duration = Duration::from_millis(100);
+    const MAX: Duration = Duration::from_secs(10);
+
+    /// Returns a delay between half and all of the current exponential delay.
+    pub fn next_delay(&mut self) -> Duration {
+        let delay = Self::BASE
+            .saturating_mul(1 << self.attempt.min(16))
+            .min(Self::MAX);
+        self.attempt += 1;
+        let half = delay / 2;
+        half + half.mul_f64(rand::random::<f64>())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backoff() {
+        let mut backoff = Backoff::default();
+        let mut max = Backoff::BASE;
+        for _ in 0..20 {
+            let delay = backoff.next_delay();
+            assert!(
+                delay >= max / 2 && delay <= max,
+                "{delay:?} not within {max:?}"
+            );
+            max = (max * 2).min(Backoff::MAX);
+        }
+    }
+}
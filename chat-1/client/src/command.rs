@@ -0,0 +1,96 @@
+use protocol::{AdminCommand, ClientEvent, Message, Timestamp, User};
+
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+pub enum CommandError {
+    #[error("unknown command: /{0}")]
+    Unknown(String),
+
+    #[error("usage: {0}")]
+    Usage(&'static str),
+}
+
+/// Returns true if the line is a command rather than a message. Lines starting with `//` are
+/// messages that start with `/`.
+pub fn is_command(line: &str) -> bool {
+    line.starts_with('/') && !line.starts_with("//")
+}
+
+/// Turns a line the user typed into the event to send. Operators started with
+/// `--operator-secret` moderate with:
+///
+/// ```text
+/// /kick <name>
+/// /mute <name> <seconds>
+/// /ban <name>
+/// ```
+pub fn parse(user: &User, line: String) -> Result<ClientEvent, CommandError> {
+    if !is_command(&line) {
+        let text = match line.strip_prefix('/') {
+            Some(text) => text.to_string(),
+            None => line,
+        };
+        return Ok(ClientEvent::Message(Message {
+            from: user.clone(),
+            text,
+            time: Timestamp::default(),
+        }));
+    }
+    let mut words = line[1..].split_whitespace();
+    let command = words.next().unwrap_or_default();
+    let args: Vec<&str> = words.collect();
+    let command = match (command, args.as_slice()) {
+        ("kick", [name]) => AdminCommand::Kick(name.to_string()),
+        ("kick", _) => return Err(CommandError::Usage("/kick <name>")),
+        ("mute", [name, secs]) => match secs.parse() {
+            Ok(secs) => AdminCommand::Mute(name.to_string(), secs),
+            Err(_) => return Err(CommandError::Usage("/mute <name> <seconds>")),
+        },
+        ("mute", _) => return Err(CommandError::Usage("/mute <name> <seconds>")),
+        ("ban", [name]) => AdminCommand::Ban(name.to_string()),
+        ("ban", _) => return Err(CommandError::Usage("/ban <name>")),
+        (other, _) => return Err(CommandError::Unknown(other.to_string())),
+    };
+    Ok(ClientEvent::Admin(command))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn user() -> User {
+        User {
+            name: String::from("me"),
+        }
+    }
+
+    fn parse_text(line: &str) -> String {
+        match parse(&user(), line.to_string()) {
+            Ok(ClientEvent::Message(message)) => message.text,
+            other => panic!("expected a message for {line}, got: {other:?}"),
+        }
+    }
+
+    fn parse_admin(line: &str) -> AdminCommand {
+        match parse(&user(), line.to_string()) {
+            Ok(ClientEvent::Admin(command)) => command,
+            other => panic!("expected a command for {line}, got: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(parse_text("hello"), "hello");
+        assert_eq!(parse_text("//not a command"), "/not a command");
+        assert!(matches!(parse_admin("/kick bob"), AdminCommand::Kick(n) if n == "bob"));
+        assert!(matches!(parse_admin("/mute bob 30"), AdminCommand::Mute(n, 30) if n == "bob"));
+        assert!(matches!(parse_admin("/ban  bob "), AdminCommand::Ban(n) if n == "bob"));
+
+        let err = |line: &str| parse(&user(), line.to_string()).unwrap_err();
+        assert_eq!(
+            err("/mute bob soon"),
+            CommandError::Usage("/mute <name> <seconds>")
+        );
+        assert_eq!(err("/kick"), CommandError::Usage("/kick <name>"));
+        assert_eq!(err("/dance"), CommandError::Unknown(String::from("dance")));
+    }
+}
@@ -0,0 +1,90 @@
+use std::io::Write;
+
+use anyhow::Result;
+use protocol::{Message, Stdout, User, STATUS_PREFIX};
+
+/// Frontend is how the client shows things to the user.
+pub trait Frontend: Send + Sync {
+    /// Shows a chat message from another user.
+    fn message(&mut self, message: &Message) -> Result<()>;
+
+    /// Shows an update about the client itself, such as the connection state.
+    fn status(&mut self, status: &str) -> Result<()>;
+
+    /// Shows that a user is now in the chat.
+    fn joined(&mut self, user: &User) -> Result<()>;
+
+    /// Shows that a user has left the chat.
+    fn left(&mut self, user: &User) -> Result<()>;
+
+    /// Called when the connection is lost and we no longer know who is in the chat.
+    fn disconnected(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Shows a message the user typed. Later calls to `delivery` with the same `id` say how far
+    /// it got. The server does not send our own messages back to us, so this is the only place
+    /// they show up.
+    fn typed(&mut self, _id: u64, _message: &Message, _delivery: Delivery) -> Result<()> {
+        Ok(())
+    }
+
+    /// Shows how far a message the user typed has got.
+    fn delivery(&mut self, _id: u64, _delivery: Delivery) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Delivery is how far a message the user typed has got.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Delivery {
+    /// We are not connected, so it waits until we are.
+    Queued,
+
+    /// It has been sent and the server has not answered yet.
+    Sent,
+
+    /// The server has relayed it to everyone else.
+    Accepted,
+
+    /// The server refused it, and the reason has been shown as a status.
+    Refused,
+
+    /// The connection was lost before the server answered, so it may or may not have been
+    /// relayed.
+    Unknown,
+}
+
+/// Lines is the plain line mode, which writes to `Stdout` and is what the verifier checks.
+pub struct Lines {
+    stdout: Stdout,
+}
+
+impl Lines {
+    pub fn new(stdout: Stdout) -> Self {
+        Self { stdout }
+    }
+}
+
+impl Frontend for Lines {
+    fn message(&mut self, message: &Message) -> Result<()> {
+        let name = &message.from.name;
+        let text = &message.text;
+        let out = format!("{name}: {text}\n");
+        write!(&mut self.stdout, "{out}")?;
+        Ok(())
+    }
+
+    fn status(&mut self, status: &str) -> Result<()> {
+        writeln!(&mut self.stdout, "{STATUS_PREFIX}{status}")?;
+        Ok(())
+    }
+
+    fn joined(&mut self, user: &User) -> Result<()> {
+        self.status(&format!("{} joined", user.name))
+    }
+
+    fn left(&mut self, user: &User) -> Result<()> {
+        self.status(&format!("{} left", user.name))
+    }
+}
@@ -1,23 +1,56 @@
+mod backoff;
+mod command;
+mod frontend;
+mod tui;
+
 use anyhow::Result;
-use protocol::{prelude::*, ClientEvent, Message, ServerEvent};
+use protocol::{
+    prelude::*,
+    tls::{self, BoxedStream},
+    ClientEvent, ServerEvent, Stdin,
+};
 use std::{
+    collections::VecDeque,
     io::{self, Write},
     net::ToSocketAddrs,
     process, thread,
 };
 use tokio::{
-    io::{AsyncBufReadExt, AsyncWriteExt, BufReader, BufWriter},
-    net::{
-        tcp::{OwnedReadHalf, OwnedWriteHalf},
-        TcpSocket,
-    },
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader, BufWriter, ReadHalf, WriteHalf},
+    net::TcpSocket,
     sync::mpsc::{self, Receiver, Sender},
+    time,
 };
 
+use backoff::Backoff;
+use frontend::{Delivery, Frontend, Lines};
+use tui::Tui;
+
+#[derive(Parser)]
+struct Args {
+    /// use the terminal UI instead of reading and writing plain lines.
+    #[arg(long)]
+    tui: bool,
+
+    #[command(flatten)]
+    config: protocol::ClientConfig,
+}
+
+fn main() {
+    // the local offset can only be looked up before the runtime starts its threads.
+    protocol::Timestamp::init_local_offset();
+    run();
+}
+
 #[tokio::main]
-async fn main() {
-    let config = protocol::ClientConfig::parse();
-    if let Err(err) = Client::start(config).await {
+async fn run() {
+    let args = Args::parse();
+    let res = if args.tui {
+        Client::start_tui(args.config).await
+    } else {
+        Client::start(args.config).await
+    };
+    if let Err(err) = res {
         eprintln!("{err:?}");
         process::exit(1);
     }
@@ -37,82 +70,336 @@ enum ClientError {
     #[error("Could not create socket: {0}")]
     SocketCreate(io::Error),
 
+    #[error("TLS handshake with {0} failed: {1}")]
+    TlsHandshake(String, io::Error),
+
     #[error("Empty name not allowed")]
     EmptyName,
+
+    #[error("could not reconnect after {0} attempts")]
+    ReconnectFailed(u32),
 }
 
 struct Client {
     config: protocol::ClientConfig,
+    frontend: Box<dyn Frontend>,
+    /// Ids for the messages the user types.
+    next_id: u64,
+    /// What we have sent this session that the server has not answered yet, oldest first. The
+    /// server answers everything in order, so each answer is for the front of this.
+    unanswered: VecDeque<Option<u64>>,
+}
+
+/// Something the user typed, on its way to the server.
+struct Outgoing {
+    event: ClientEvent,
+    /// The id the frontend knows a message by. Commands are not shown, so they have none.
+    id: Option<u64>,
+}
+
+/// A live connection to the server.
+struct Connection {
+    rx: Receiver<String>,
+    tx: BufWriter<WriteHalf<BoxedStream>>,
+}
+
+/// Why a session with the server ended.
+enum SessionEnd {
+    /// The user has no more input.
+    Quit,
+
+    /// The server went away or could not be written to.
+    Disconnected,
+
+    /// The server kicked or banned us, so there is no point reconnecting.
+    Removed,
 }
 
 impl Client {
+    /// Starts the client in line mode, reading from `config.stdin` and writing to
+    /// `config.stdout`.
     async fn start(config: protocol::ClientConfig) -> Result<()> {
-        let mut client = Self::new(config);
-        client.run().await
-    }
-
-    fn new(config: protocol::ClientConfig) -> Self {
-        Self { config }
+        let name = match config.name.clone() {
+            Some(name) => name,
+            None => get_name()?,
+        };
+        let user_rx = read_user_input(config.stdin.clone());
+        let frontend = Box::new(Lines::new(config.stdout.clone()));
+        let mut client = Self::new(config, frontend);
+        client.run(name, user_rx).await
     }
 
-    async fn run(&mut self) -> Result<()> {
-        let name = match self.config.name.clone() {
+    /// Starts the client with the terminal UI.
+    async fn start_tui(config: protocol::ClientConfig) -> Result<()> {
+        let name = match config.name.clone() {
             Some(name) => name,
             None => get_name()?,
         };
+        let (tui, frontend, user_rx) = Tui::start(&name)?;
+        let mut client = Self::new(config, Box::new(frontend));
+        let res = client.run(name, user_rx).await;
+        // dropping the client drops the frontend, which tells the UI to exit.
+        drop(client);
+        tui.join()?;
+        res
+    }
+
+    fn new(config: protocol::ClientConfig, frontend: Box<dyn Frontend>) -> Self {
+        Self {
+            config,
+            frontend,
+            next_id: 0,
+            unanswered: VecDeque::new(),
+        }
+    }
+
+    async fn run(&mut self, name: String, mut user_rx: Receiver<String>) -> Result<()> {
+        let user = protocol::User { name };
+        // messages typed while we are not connected wait here until we are.
+        let mut outbox = VecDeque::new();
+        let mut conn = self.connect().await?;
+        loop {
+            let end = self.session(conn, &user, &mut user_rx, &mut outbox).await?;
+            // whatever the server did not answer may or may not have been relayed.
+            while let Some(id) = self.unanswered.pop_front() {
+                if let Some(id) = id {
+                    self.frontend.delivery(id, Delivery::Unknown)?;
+                }
+            }
+            if let SessionEnd::Quit | SessionEnd::Removed = end {
+                return Ok(());
+            }
+            self.frontend.disconnected()?;
+            self.status("disconnected from server")?;
+            if self.config.reconnect_attempts == 0 {
+                return Ok(());
+            }
+            conn = match self.reconnect(&user, &mut user_rx, &mut outbox).await? {
+                Some(conn) => conn,
+                None => return Ok(()),
+            };
+            self.status("reconnected to server")?;
+        }
+    }
+
+    async fn connect(&self) -> Result<Connection> {
         let addr = &self.config.addr;
         let socket_addr = addr
             .to_socket_addrs()
             .map_err(|e| ClientError::AddrParseFailure(addr.to_string(), e))?
-            .filter(|f| f.is_ipv4())
-            .next()
+            .find(|f| f.is_ipv4())
             .ok_or(ClientError::NoIpV4Addrs)?;
         let socket = TcpSocket::new_v4().map_err(ClientError::SocketCreate)?;
         let tcp_stream = socket
             .connect(socket_addr)
             .await
             .map_err(|e| ClientError::CouldNotConnect(addr.into(), e))?;
-        let mut user_rx = read_user_input();
-        let (server_rx, server_tx) = tcp_stream.into_split();
-        let server_rx = BufReader::new(server_rx);
-        let mut server_tx = BufWriter::new(server_tx);
-        send_server(ClientEvent::Ident(protocol::User { name }), &mut server_tx).await?;
-        let mut server_rx = read_server(server_rx).await;
+        let stream: BoxedStream = if self.config.use_tls() {
+            let connector = tls::connector(self.config.tls_ca.as_deref())?;
+            let domain = tls::server_name(addr)?;
+            let tls_stream = connector
+                .connect(domain, tcp_stream)
+                .await
+                .map_err(|e| ClientError::TlsHandshake(addr.into(), e))?;
+            Box::new(tls_stream)
+        } else {
+            Box::new(tcp_stream)
+        };
+        let (server_rx, server_tx) = tokio::io::split(stream);
+        let rx = read_server(BufReader::new(server_rx)).await;
+        let tx = BufWriter::new(server_tx);
+        Ok(Connection { rx, tx })
+    }
+
+    /// Tries to connect again with exponential backoff. Input typed while waiting is queued in
+    /// the outbox. Returns `None` if the user quit while we were waiting.
+    async fn reconnect(
+        &mut self,
+        user: &protocol::User,
+        user_rx: &mut Receiver<String>,
+        outbox: &mut VecDeque<Outgoing>,
+    ) -> Result<Option<Connection>> {
+        let attempts = self.config.reconnect_attempts;
+        let mut backoff = Backoff::default();
+        for attempt in 1..=attempts {
+            let delay = backoff.next_delay();
+            self.status(&format!(
+                "reconnecting in {}ms (attempt {attempt} of {attempts})",
+                delay.as_millis()
+            ))?;
+            let sleep = time::sleep(delay);
+            tokio::pin!(sleep);
+            loop {
+                tokio::select! {
+                    () = &mut sleep => break,
+                    input = user_rx.recv() => {
+                        let Some(text) = input else { return Ok(None) };
+                        self.queue(user, text, outbox)?;
+                    }
+                }
+            }
+            if let Ok(conn) = self.connect().await {
+                return Ok(Some(conn));
+            }
+        }
+        self.status("could not reconnect, giving up")?;
+        Err(ClientError::ReconnectFailed(attempts).into())
+    }
+
+    /// Identifies with the server, proves we are an operator if we have a secret, sends anything
+    /// waiting in the outbox and then relays events until the user quits or the connection is
+    /// lost.
+    async fn session(
+        &mut self,
+        mut conn: Connection,
+        user: &protocol::User,
+        user_rx: &mut Receiver<String>,
+        outbox: &mut VecDeque<Outgoing>,
+    ) -> Result<SessionEnd> {
+        if send_server(ClientEvent::Ident(user.clone()), &mut conn.tx)
+            .await
+            .is_err()
+        {
+            return Ok(SessionEnd::Disconnected);
+        }
+        if let Some(secret) = self.config.operator_secret.clone() {
+            if send_server(ClientEvent::Operator(secret), &mut conn.tx)
+                .await
+                .is_err()
+            {
+                return Ok(SessionEnd::Disconnected);
+            }
+            self.unanswered.push_back(None);
+        }
+        while let Some(outgoing) = outbox.front() {
+            if send_server(outgoing.event.clone(), &mut conn.tx)
+                .await
+                .is_err()
+            {
+                return Ok(SessionEnd::Disconnected);
+            }
+            if let Some(id) = outgoing.id {
+                self.frontend.delivery(id, Delivery::Sent)?;
+            }
+            self.unanswered.push_back(outgoing.id);
+            outbox.pop_front();
+        }
         loop {
             tokio::select! {
-                event = server_rx.recv() => {
-                    let Some(event) = event else { break };
-                    self.handle_server_event(&event).await?;
+                event = conn.rx.recv() => {
+                    let Some(event) = event else { return Ok(SessionEnd::Disconnected) };
+                    if let Some(end) = self.handle_server_event(&event).await? {
+                        return Ok(end);
+                    }
                 }
                 input = user_rx.recv() => {
-                    let Some(input) = input else { break };
-                    println!("User input: {input}");
+                    let Some(text) = input else {
+                        // the reader task still holds the other half of the stream, so the
+                        // connection has to be closed explicitly.
+                        let _ = conn.tx.shutdown().await;
+                        return Ok(SessionEnd::Quit);
+                    };
+                    let Some(outgoing) = self.typed(user, text, Delivery::Sent)? else {
+                        continue;
+                    };
+                    if send_server(outgoing.event.clone(), &mut conn.tx).await.is_err() {
+                        if let Some(id) = outgoing.id {
+                            self.frontend.delivery(id, Delivery::Queued)?;
+                        }
+                        outbox.push_back(outgoing);
+                        return Ok(SessionEnd::Disconnected);
+                    }
+                    self.unanswered.push_back(outgoing.id);
                 }
-                else => break,
             }
         }
-        Ok(())
     }
 
-    async fn handle_server_event(&mut self, input: &str) -> Result<()> {
-        let event = serde_json::from_str::<ServerEvent>(input)?;
+    /// Queues a message to be sent once we are connected again.
+    fn queue(
+        &mut self,
+        user: &protocol::User,
+        text: String,
+        outbox: &mut VecDeque<Outgoing>,
+    ) -> Result<()> {
+        let Some(outgoing) = self.typed(user, text, Delivery::Queued)? else {
+            return Ok(());
+        };
+        outbox.push_back(outgoing);
+        self.status(&format!(
+            "not connected, {} message(s) queued",
+            outbox.len()
+        ))
+    }
+
+    /// Parses what the user typed and shows it if it is a message. Returns `None` if it is not
+    /// something to send, which has already been explained to the user.
+    fn typed(
+        &mut self,
+        user: &protocol::User,
+        text: String,
+        delivery: Delivery,
+    ) -> Result<Option<Outgoing>> {
+        let event = match command::parse(user, text) {
+            Ok(event) => event,
+            Err(err) => {
+                self.status(&err.to_string())?;
+                return Ok(None);
+            }
+        };
+        let id = match &event {
+            ClientEvent::Message(message) => {
+                let id = self.next_id;
+                self.next_id += 1;
+                self.frontend.typed(id, message, delivery)?;
+                Some(id)
+            }
+            _ => None,
+        };
+        Ok(Some(Outgoing { event, id }))
+    }
+
+    /// Passes the server's answer on for the oldest thing it had not answered yet.
+    fn answered(&mut self, delivery: Delivery) -> Result<()> {
+        match self.unanswered.pop_front() {
+            Some(Some(id)) => self.frontend.delivery(id, delivery),
+            _ => Ok(()),
+        }
+    }
+
+    /// Tells the user about the client rather than the chat.
+    fn status(&mut self, status: &str) -> Result<()> {
+        self.frontend.status(status)
+    }
+
+    /// Shows the event to the user. Returns how the session ends if the event ends it.
+    async fn handle_server_event(&mut self, input: &str) -> Result<Option<SessionEnd>> {
+        // a bad frame from the server is not worth dropping the connection over.
+        let Ok(event) = serde_json::from_str::<ServerEvent>(input) else {
+            self.status("ignoring malformed event from server")?;
+            return Ok(None);
+        };
         match event {
-            ServerEvent::Message(message) => {
-                let Message {
-                    from,
-                    text,
-                    time: _time,
-                } = message;
-                let name = from.name;
-                let out = format!("{name}: {text}\n");
-                write!(&mut self.config.stdout, "{out}")?;
+            ServerEvent::Message(message) => self.frontend.message(&message)?,
+            ServerEvent::Joined(user) => self.frontend.joined(&user)?,
+            ServerEvent::Left(user) => self.frontend.left(&user)?,
+            ServerEvent::Error(err) => {
+                self.status(&err.to_string())?;
+                if err.is_removal() {
+                    return Ok(Some(SessionEnd::Removed));
+                }
+                self.answered(Delivery::Refused)?;
             }
+            ServerEvent::Accepted => self.answered(Delivery::Accepted)?,
         }
-        Ok(())
+        Ok(None)
     }
 }
 
-async fn send_server(event: ClientEvent, writer: &mut BufWriter<OwnedWriteHalf>) -> Result<()> {
+async fn send_server(
+    event: ClientEvent,
+    writer: &mut BufWriter<WriteHalf<BoxedStream>>,
+) -> Result<()> {
     let event = serde_json::to_string(&event)?;
     writer.write_all(event.as_bytes()).await?;
     writer.write_all("\n".as_bytes()).await?;
@@ -120,7 +407,7 @@ async fn send_server(event: ClientEvent, writer: &mut BufWriter<OwnedWriteHalf>)
     Ok(())
 }
 
-async fn read_server(mut reader: BufReader<OwnedReadHalf>) -> Receiver<String> {
+async fn read_server(mut reader: BufReader<ReadHalf<BoxedStream>>) -> Receiver<String> {
     let (tx, rx) = mpsc::channel(1024);
     tokio::spawn(async move {
         loop {
@@ -140,23 +427,27 @@ async fn read_server(mut reader: BufReader<OwnedReadHalf>) -> Receiver<String> {
     rx
 }
 
-fn read_user_input() -> Receiver<String> {
+fn read_user_input(stdin: Stdin) -> Receiver<String> {
     let (tx, rx) = mpsc::channel(1024);
     thread::spawn(move || {
-        let _ = read_stdin_lines(tx);
+        let _ = read_stdin_lines(stdin, tx);
     });
     rx
 }
 
-fn read_stdin_lines(tx: Sender<String>) -> Result<()> {
-    let rt = tokio::runtime::Builder::new_current_thread()
-        .enable_all()
-        .build()?;
+/// Sends each non-empty line to the channel. Returning drops the sender, which tells the client
+/// that the user is done.
+fn read_stdin_lines(mut stdin: Stdin, tx: Sender<String>) -> Result<()> {
     loop {
         let mut buf = String::new();
-        io::stdin().read_line(&mut buf)?;
+        if stdin.read_line(&mut buf)? == 0 {
+            return Ok(());
+        }
         let buf = buf.trim().to_string();
-        let _ = rt.block_on(tx.send(buf));
+        if buf.is_empty() {
+            continue;
+        }
+        tx.blocking_send(buf)?;
     }
 }
 
@@ -180,4 +471,9 @@ mod tests {
     async fn test_harness() {
         protocol::verify_client(Client::start).await;
     }
+
+    #[tokio::test]
+    async fn test_harness_tls() {
+        protocol::verify_client_tls(Client::start).await;
+    }
 }
@@ -0,0 +1,428 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    io::{self, Stdout},
+    sync::mpsc::{self, TryRecvError},
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use anyhow::{anyhow, Result};
+use crossterm::{
+    event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers},
+    execute,
+    terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
+};
+use protocol::{Message, Timestamp, User, STATUS_PREFIX};
+use ratatui::{
+    prelude::*,
+    widgets::{Block, Borders, List, ListItem, Paragraph},
+};
+use tokio::sync::mpsc::{channel, Receiver, Sender};
+
+use crate::frontend::{Delivery, Frontend};
+
+/// Updates sent from the client to the UI thread.
+enum Update {
+    Message(Message),
+    Status(String),
+    Joined(User),
+    Left(User),
+    Disconnected,
+    Typed(u64, Message, Delivery),
+    Delivery(u64, Delivery),
+}
+
+/// The client's half of the terminal UI. Everything is forwarded to the UI thread, which owns
+/// the terminal, so incoming messages never clobber what the user is typing.
+pub struct TuiFrontend {
+    tx: mpsc::Sender<Update>,
+}
+
+impl TuiFrontend {
+    fn send(&self, update: Update) -> Result<()> {
+        self.tx
+            .send(update)
+            .map_err(|_| anyhow!("the terminal UI has exited"))
+    }
+}
+
+impl Frontend for TuiFrontend {
+    fn message(&mut self, message: &Message) -> Result<()> {
+        self.send(Update::Message(message.clone()))
+    }
+
+    fn status(&mut self, status: &str) -> Result<()> {
+        self.send(Update::Status(status.to_string()))
+    }
+
+    fn joined(&mut self, user: &User) -> Result<()> {
+        self.send(Update::Joined(user.clone()))
+    }
+
+    fn left(&mut self, user: &User) -> Result<()> {
+        self.send(Update::Left(user.clone()))
+    }
+
+    fn disconnected(&mut self) -> Result<()> {
+        self.send(Update::Disconnected)
+    }
+
+    fn typed(&mut self, id: u64, message: &Message, delivery: Delivery) -> Result<()> {
+        self.send(Update::Typed(id, message.clone(), delivery))
+    }
+
+    fn delivery(&mut self, id: u64, delivery: Delivery) -> Result<()> {
+        self.send(Update::Delivery(id, delivery))
+    }
+}
+
+/// Tui runs the terminal UI on its own thread. The UI exits when the user presses Esc or Ctrl-C,
+/// which ends the input, or when the client drops its `TuiFrontend`.
+pub struct Tui {
+    thread: JoinHandle<Result<()>>,
+}
+
+impl Tui {
+    /// Takes over the terminal. Returns the frontend for the client and the lines the user
+    /// enters.
+    pub fn start(name: &str) -> Result<(Self, TuiFrontend, Receiver<String>)> {
+        let (update_tx, update_rx) = mpsc::channel();
+        let (input_tx, input_rx) = channel(1024);
+        let app = App::new(name);
+        let mut terminal = setup_terminal()?;
+        let thread = thread::spawn(move || {
+            let res = app.run(&mut terminal, &update_rx, &input_tx);
+            restore_terminal(&mut terminal)?;
+            res
+        });
+        let frontend = TuiFrontend { tx: update_tx };
+        Ok((Self { thread }, frontend, input_rx))
+    }
+
+    /// Waits for the UI to exit and give the terminal back.
+    pub fn join(self) -> Result<()> {
+        self.thread
+            .join()
+            .map_err(|_| anyhow!("the terminal UI panicked"))?
+    }
+}
+
+fn setup_terminal() -> Result<Terminal<CrosstermBackend<Stdout>>> {
+    enable_raw_mode()?;
+    let mut stdout = io::stdout();
+    execute!(stdout, EnterAlternateScreen)?;
+    Ok(Terminal::new(CrosstermBackend::new(stdout))?)
+}
+
+fn restore_terminal(terminal: &mut Terminal<CrosstermBackend<Stdout>>) -> Result<()> {
+    disable_raw_mode()?;
+    execute!(terminal.backend_mut(), LeaveAlternateScreen)?;
+    terminal.show_cursor()?;
+    Ok(())
+}
+
+/// App is the state of the UI.
+struct App {
+    name: String,
+    lines: Vec<Line<'static>>,
+    /// The user's own messages that the server has not accepted yet, by id, with the line each
+    /// is shown on.
+    pending: HashMap<u64, (usize, Message)>,
+    users: BTreeSet<String>,
+    input: String,
+    /// How many lines the message pane is scrolled up from the bottom.
+    scroll: usize,
+}
+
+impl App {
+    fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            lines: vec![],
+            pending: HashMap::new(),
+            users: BTreeSet::from([name.to_string()]),
+            input: String::new(),
+            scroll: 0,
+        }
+    }
+
+    fn run<B: Backend>(
+        mut self,
+        terminal: &mut Terminal<B>,
+        updates: &mpsc::Receiver<Update>,
+        input: &Sender<String>,
+    ) -> Result<()> {
+        loop {
+            terminal.draw(|f| self.render(f))?;
+            loop {
+                match updates.try_recv() {
+                    Ok(update) => self.apply(update),
+                    Err(TryRecvError::Empty) => break,
+                    Err(TryRecvError::Disconnected) => return Ok(()),
+                }
+            }
+            if event::poll(Duration::from_millis(50))? {
+                if let Event::Key(key) = event::read()? {
+                    if !self.key(key, input) {
+                        return Ok(());
+                    }
+                }
+            }
+        }
+    }
+
+    fn apply(&mut self, update: Update) {
+        match update {
+            Update::Message(message) => {
+                self.lines.push(message_line(&message, Delivery::Accepted));
+            }
+            Update::Status(status) => self.push_status(status),
+            Update::Joined(user) => {
+                self.push_status(format!("{} joined", user.name));
+                self.users.insert(user.name);
+            }
+            Update::Left(user) => {
+                self.push_status(format!("{} left", user.name));
+                self.users.remove(&user.name);
+            }
+            Update::Disconnected => {
+                self.users.clear();
+                self.users.insert(self.name.clone());
+            }
+            Update::Typed(id, message, delivery) => {
+                self.lines.push(message_line(&message, delivery));
+                self.pending.insert(id, (self.lines.len() - 1, message));
+            }
+            Update::Delivery(id, delivery) => {
+                let Some((index, message)) = self.pending.get(&id) else {
+                    return;
+                };
+                self.lines[*index] = message_line(message, delivery);
+                if delivery != Delivery::Queued && delivery != Delivery::Sent {
+                    self.pending.remove(&id);
+                }
+            }
+        }
+    }
+
+    fn push_status(&mut self, status: String) {
+        self.lines.push(Line::from(vec![
+            Span::styled(
+                format!("{} ", Timestamp::default().time_of_day()),
+                Style::default().fg(Color::DarkGray),
+            ),
+            Span::styled(
+                format!("{STATUS_PREFIX}{status}"),
+                Style::default().fg(Color::Yellow),
+            ),
+        ]));
+    }
+
+    /// Handles a key press. Returns false when the user wants to quit.
+    fn key(&mut self, key: KeyEvent, input: &Sender<String>) -> bool {
+        if key.kind != KeyEventKind::Press {
+            return true;
+        }
+        match key.code {
+            KeyCode::Esc => return false,
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => return false,
+            KeyCode::Enter => {
+                let text = self.input.trim().to_string();
+                self.input.clear();
+                if text.is_empty() {
+                    return true;
+                }
+                self.scroll = 0;
+                if input.blocking_send(text).is_err() {
+                    return false;
+                }
+            }
+            KeyCode::Char(c) => self.input.push(c),
+            KeyCode::Backspace => {
+                self.input.pop();
+            }
+            KeyCode::Up => self.scroll_up(1),
+            KeyCode::Down => self.scroll_down(1),
+            KeyCode::PageUp => self.scroll_up(10),
+            KeyCode::PageDown => self.scroll_down(10),
+            _ => {}
+        }
+        true
+    }
+
+    fn scroll_up(&mut self, n: usize) {
+        self.scroll = (self.scroll + n).min(self.lines.len().saturating_sub(1));
+    }
+
+    fn scroll_down(&mut self, n: usize) {
+        self.scroll = self.scroll.saturating_sub(n);
+    }
+
+    /// ┌ messages ───────────────┐┌ users ─┐
+    /// │12:00:00 alice: hi       ││alice   │
+    /// └─────────────────────────┘│bob     │
+    /// ┌ bob ────────────────────┐│        │
+    /// │hello_                   ││        │
+    /// └─────────────────────────┘└────────┘
+    fn render(&self, f: &mut Frame) {
+        let columns = Layout::default()
+            .direction(Direction::Horizontal)
+            .constraints([Constraint::Min(20), Constraint::Length(20)])
+            .split(f.size());
+        let rows = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([Constraint::Min(3), Constraint::Length(3)])
+            .split(columns[0]);
+        let (messages, input, users) = (rows[0], rows[1], columns[1]);
+
+        let height = usize::from(messages.height.saturating_sub(2));
+        let end = self.lines.len().saturating_sub(self.scroll);
+        let start = end.saturating_sub(height);
+        let title = if self.scroll > 0 {
+            format!("messages (+{})", self.scroll)
+        } else {
+            String::from("messages")
+        };
+        let pane = Paragraph::new(self.lines[start..end].to_vec())
+            .block(Block::default().borders(Borders::ALL).title(title));
+        f.render_widget(pane, messages);
+
+        let list: Vec<ListItem> = self
+            .users
+            .iter()
+            .map(|name| ListItem::new(name.as_str()))
+            .collect();
+        let list = List::new(list).block(Block::default().borders(Borders::ALL).title("users"));
+        f.render_widget(list, users);
+
+        let line = Paragraph::new(self.input.as_str()).block(
+            Block::default()
+                .borders(Borders::ALL)
+                .title(self.name.as_str()),
+        );
+        f.render_widget(line, input);
+        let typed = u16::try_from(self.input.chars().count()).unwrap_or(u16::MAX);
+        let x = (input.x + 1 + typed).min(input.right().saturating_sub(2));
+        f.set_cursor(x, input.y + 1);
+    }
+}
+
+/// Shows a message, marking the user's own until the server has accepted them.
+fn message_line(message: &Message, delivery: Delivery) -> Line<'static> {
+    let (text, note) = match delivery {
+        Delivery::Accepted => (Style::default(), None),
+        Delivery::Sent => (Style::default().fg(Color::DarkGray), None),
+        Delivery::Queued => (Style::default().fg(Color::DarkGray), Some("queued")),
+        Delivery::Refused => (
+            Style::default().add_modifier(Modifier::CROSSED_OUT),
+            Some("not sent"),
+        ),
+        Delivery::Unknown => (Style::default(), Some("maybe not sent")),
+    };
+    let mut spans = vec![
+        Span::styled(
+            format!("{} ", message.time.time_of_day()),
+            Style::default().fg(Color::DarkGray),
+        ),
+        Span::styled(
+            format!("{}: ", message.from.name),
+            Style::default()
+                .fg(Color::Cyan)
+                .add_modifier(Modifier::BOLD),
+        ),
+        Span::styled(message.text.clone(), text),
+    ];
+    if let Some(note) = note {
+        spans.push(Span::styled(
+            format!(" ({note})"),
+            Style::default().fg(Color::Red),
+        ));
+    }
+    Line::from(spans)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ratatui::backend::TestBackend;
+
+    fn rendered(app: &App) -> String {
+        let mut terminal = Terminal::new(TestBackend::new(60, 10)).unwrap();
+        terminal.draw(|f| app.render(f)).unwrap();
+        let buffer = terminal.backend().buffer();
+        buffer
+            .content
+            .chunks(usize::from(buffer.area.width))
+            .map(|row| row.iter().map(|c| c.symbol.as_str()).collect::<String>() + "\n")
+            .collect()
+    }
+
+    #[test]
+    fn test_render() {
+        let mut app = App::new("me");
+        app.apply(Update::Joined(User {
+            name: String::from("alice"),
+        }));
+        app.apply(Update::Message(Message {
+            from: User {
+                name: String::from("alice"),
+            },
+            text: String::from("hi there"),
+            time: Timestamp::default(),
+        }));
+        app.input = String::from("typing");
+        let out = rendered(&app);
+        assert!(out.contains("alice: hi there"), "{out}");
+        assert!(out.contains("*** alice joined"), "{out}");
+        assert!(out.contains("typing"), "{out}");
+        assert!(out.contains("│alice"), "{out}");
+
+        app.apply(Update::Left(User {
+            name: String::from("alice"),
+        }));
+        assert!(!rendered(&app).contains("│alice "));
+    }
+
+    #[test]
+    fn test_own_messages() {
+        let mut app = App::new("me");
+        let message = |text: &str| Message {
+            from: User {
+                name: String::from("me"),
+            },
+            text: text.to_string(),
+            time: Timestamp::default(),
+        };
+        app.apply(Update::Typed(0, message("first"), Delivery::Sent));
+        app.apply(Update::Typed(1, message("second"), Delivery::Sent));
+        app.apply(Update::Typed(2, message("third"), Delivery::Queued));
+        let out = rendered(&app);
+        assert!(out.contains("me: third (queued)"), "{out}");
+
+        app.apply(Update::Delivery(0, Delivery::Accepted));
+        app.apply(Update::Delivery(1, Delivery::Refused));
+        app.apply(Update::Delivery(2, Delivery::Sent));
+        let out = rendered(&app);
+        assert!(out.contains("me: first "), "{out}");
+        assert!(out.contains("me: second (not sent)"), "{out}");
+        assert!(out.contains("me: third "), "{out}");
+        assert!(!out.contains("queued"), "{out}");
+
+        app.apply(Update::Delivery(2, Delivery::Unknown));
+        assert!(rendered(&app).contains("me: third (maybe not"));
+    }
+
+    #[test]
+    fn test_scroll() {
+        let mut app = App::new("me");
+        for i in 0..20 {
+            app.apply(Update::Status(format!("line {i}")));
+        }
+        assert!(rendered(&app).contains("line 19"));
+        app.scroll_up(5);
+        let out = rendered(&app);
+        assert!(out.contains("line 14") && !out.contains("line 15"), "{out}");
+        app.scroll_down(100);
+        assert!(rendered(&app).contains("line 19"));
+    }
+}
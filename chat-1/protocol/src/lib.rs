@@ -1,5 +1,6 @@
+pub mod tls;
 pub mod verify;
-pub use verify::verify_client;
+pub use verify::{verify_client, verify_client_tls, verify_server, verify_server_tls};
 pub mod prelude {
     pub use async_trait::async_trait;
     pub use clap::Parser;
@@ -9,16 +10,67 @@ pub mod prelude {
     pub use time::OffsetDateTime;
 }
 use std::{
-    io::{self, Write},
-    sync::{Arc, Mutex},
+    io::{self, BufRead, Write},
+    path::PathBuf,
+    str::FromStr,
+    sync::{Arc, Mutex, OnceLock},
 };
 
 use anyhow::Result;
 use prelude::*;
+use time::UtcOffset;
 
 #[derive(Serialize, Deserialize, Clone, Parser)]
 pub struct ServerConfig {
+    /// the address on which to listen (e.g. 0.0.0.0:8000).
     pub addr: String,
+
+    /// a PEM file with the certificate chain to present to clients. setting this and `tls_key`
+    /// makes the server only accept TLS connections.
+    #[arg(long, requires = "tls_key")]
+    pub tls_cert: Option<PathBuf>,
+
+    /// a PEM file with the private key for `tls_cert`.
+    #[arg(long, requires = "tls_cert")]
+    pub tls_key: Option<PathBuf>,
+
+    /// how many messages and commands per second each user may send on average.
+    #[arg(long, default_value_t = 2.0)]
+    pub rate_limit: f64,
+
+    /// how many messages and commands each user may send at once before the rate limit applies.
+    #[arg(long, default_value_t = 10)]
+    pub rate_burst: u32,
+
+    /// the longest message, in bytes, the server will relay.
+    #[arg(long, default_value_t = 4096)]
+    pub max_message_len: usize,
+
+    /// a user who may send `ClientEvent::Admin` commands, as `name:secret`. a connection with
+    /// that name has to send the secret in a `ClientEvent::Operator` first. may be repeated.
+    #[arg(long = "operator")]
+    pub operators: Vec<Operator>,
+}
+
+/// Operator is a user who may moderate, once they have proven who they are with their secret.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Operator {
+    pub name: String,
+    pub secret: String,
+}
+
+impl FromStr for Operator {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            Some((name, secret)) if !name.is_empty() && !secret.is_empty() => Ok(Self {
+                name: name.to_string(),
+                secret: secret.to_string(),
+            }),
+            _ => Err(format!("expected name:secret, got {s}")),
+        }
+    }
 }
 
 #[derive(Parser)]
@@ -30,10 +82,46 @@ pub struct ClientConfig {
     /// the address to which to connect (e.g. localhost:8000).
     pub addr: String,
 
+    /// connect to the server using TLS.
+    #[arg(long)]
+    pub tls: bool,
+
+    /// a PEM file of CA certificates to trust instead of the default roots. useful for
+    /// self-signed certificates. implies `--tls`.
+    #[arg(long)]
+    pub tls_ca: Option<PathBuf>,
+
+    /// the secret that proves to the server that this user is one of its operators.
+    #[arg(long)]
+    pub operator_secret: Option<String>,
+
+    /// how many times to try reconnecting after losing the connection to the server. 0 disables
+    /// reconnecting, so the client exits when the server goes away.
+    #[arg(long, default_value_t = 10)]
+    pub reconnect_attempts: u32,
+
     /// clients should write to Stdout. you can use the write! macro to do this. the verifier will
     /// look at the output written to this to verify the output.
+    ///
+    /// lines starting with `STATUS_PREFIX` report on the client itself (e.g. the connection
+    /// state) and are ignored by the verifier when comparing output.
     #[clap(skip)]
     pub stdout: Stdout,
+
+    /// clients should read what the user types from Stdin, one line per message. reaching the end
+    /// of the input means the user is done and the client should exit.
+    #[clap(skip)]
+    pub stdin: Stdin,
+}
+
+/// The prefix of lines written to `Stdout` that are status updates rather than chat output.
+pub const STATUS_PREFIX: &str = "*** ";
+
+impl ClientConfig {
+    /// Returns true if the client should connect to the server over TLS.
+    pub fn use_tls(&self) -> bool {
+        self.tls || self.tls_ca.is_some()
+    }
 }
 
 /// Stdout is something that will be supplied to your code in the verification module to capture
@@ -41,7 +129,7 @@ pub struct ClientConfig {
 ///
 /// It implements Write, so the expected usage is something like:
 ///
-/// ```no_run
+/// ```ignore
 /// let out = "foobar";
 /// write!(&mut self.config.stdout, "{out}")?;
 /// ```
@@ -90,6 +178,31 @@ impl DerefMut for Stdout {
     }
 }
 
+/// Stdin is the counterpart of `Stdout`. The verification harness supplies its own reader so
+/// that it can type as the user. If the `Option` is `None` then lines are read from the program's
+/// standard in.
+#[derive(Default, Clone)]
+pub struct Stdin(Arc<Mutex<Option<Box<dyn BufRead + Send>>>>);
+
+impl From<Box<dyn BufRead + Send>> for Stdin {
+    fn from(value: Box<dyn BufRead + Send>) -> Self {
+        Stdin(Arc::new(Mutex::new(Some(value))))
+    }
+}
+
+impl Stdin {
+    /// Reads a line into `buf` from the reader, or if it's none, from the program stdin. Returns
+    /// the number of bytes read, which is 0 at the end of the input.
+    pub fn read_line(&mut self, buf: &mut String) -> io::Result<usize> {
+        let mut opt = self.0.lock().expect("lock fail");
+        if let Some(opt) = opt.as_mut() {
+            opt.read_line(buf)
+        } else {
+            io::stdin().read_line(buf)
+        }
+    }
+}
+
 /// The client/server protocol consists of sending events
 #[derive(Serialize, Deserialize, Clone)]
 pub enum Event {
@@ -106,13 +219,90 @@ pub enum ClientEvent {
 
     /// The client has sent a message
     Message(Message),
+
+    /// The identified user is an operator, and this is the secret the server has for them.
+    Operator(String),
+
+    /// An operator wants to moderate another user. Only users listed as operators in the
+    /// server's config may send these, after proving it with an `Operator`.
+    Admin(AdminCommand),
+}
+
+/// AdminCommand is a moderation action taken by an operator against a user, by name
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub enum AdminCommand {
+    /// Disconnects the user. They may come back.
+    Kick(String),
+
+    /// Stops the user's messages from being relayed for this many seconds.
+    Mute(String, u64),
+
+    /// Disconnects the user and refuses them from then on.
+    Ban(String),
 }
 
 /// ServerEvent is sent by the server
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Clone, Debug)]
 pub enum ServerEvent {
     /// Someone else sent a message
     Message(Message),
+
+    /// A user has identified themselves. When a client identifies, the server also sends one of
+    /// these for every user that is already connected.
+    Joined(User),
+
+    /// A user has disconnected.
+    Left(User),
+
+    /// The server refused something this client did.
+    Error(ChatError),
+
+    /// The server has relayed or carried out the client's oldest message or admin command that it
+    /// had not answered yet. Each of those gets either this or an `Error`, in the order they were
+    /// sent.
+    Accepted,
+}
+
+/// ChatError is why the server refused something a client did
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, thiserror::Error)]
+pub enum ChatError {
+    #[error("you are sending messages too quickly")]
+    RateLimited,
+
+    #[error("message is longer than {0} bytes")]
+    MessageTooLong(usize),
+
+    #[error("you are muted for another {0}s")]
+    Muted(u64),
+
+    #[error("you were kicked by {0}")]
+    Kicked(String),
+
+    #[error("you are banned from this server")]
+    Banned,
+
+    #[error("you are not an operator")]
+    NotOperator,
+
+    #[error("no such user: {0}")]
+    NoSuchUser(String),
+
+    #[error("someone called {0} is already here")]
+    NameTaken(String),
+
+    #[error("you have already identified yourself")]
+    AlreadyIdentified,
+}
+
+impl ChatError {
+    /// Returns true if the server has disconnected the client and will not take it back right
+    /// away, so reconnecting is pointless.
+    pub fn is_removal(&self) -> bool {
+        matches!(
+            self,
+            ChatError::Kicked(_) | ChatError::Banned | ChatError::NameTaken(_)
+        )
+    }
 }
 
 /// Represents a message in the chat
@@ -140,6 +330,38 @@ impl Default for Timestamp {
     }
 }
 
+/// The local UTC offset, looked up once.
+static LOCAL_OFFSET: OnceLock<Option<UtcOffset>> = OnceLock::new();
+
+impl Timestamp {
+    /// Looks up the local UTC offset for `time_of_day`. On unix this can only be done while the
+    /// process has a single thread, so call this at the start of `main` before starting a
+    /// runtime. Otherwise times are shown in UTC.
+    pub fn init_local_offset() {
+        LOCAL_OFFSET.get_or_init(|| UtcOffset::current_local_offset().ok());
+    }
+
+    /// Formats the time of day in local time, e.g. `14:02:59`, or in UTC with a label if the
+    /// local offset is unknown, e.g. `12:02:59 UTC`.
+    pub fn time_of_day(&self) -> String {
+        Self::init_local_offset();
+        self.time_of_day_at(LOCAL_OFFSET.get().copied().flatten())
+    }
+
+    fn time_of_day_at(&self, offset: Option<UtcOffset>) -> String {
+        let (time, label) = match offset {
+            Some(offset) => (self.to_offset(offset), ""),
+            None => (self.to_offset(UtcOffset::UTC), " UTC"),
+        };
+        format!(
+            "{:02}:{:02}:{:02}{label}",
+            time.hour(),
+            time.minute(),
+            time.second()
+        )
+    }
+}
+
 impl Deref for Timestamp {
     type Target = OffsetDateTime;
     fn deref(&self) -> &Self::Target {
@@ -152,3 +374,16 @@ impl DerefMut for Timestamp {
         &mut self.0
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_time_of_day() {
+        let time = Timestamp(OffsetDateTime::from_unix_timestamp(12 * 3600 + 2 * 60 + 59).unwrap());
+        let offset = UtcOffset::from_hms(2, 0, 0).unwrap();
+        assert_eq!(time.time_of_day_at(Some(offset)), "14:02:59");
+        assert_eq!(time.time_of_day_at(None), "12:02:59 UTC");
+    }
+}
@@ -0,0 +1,91 @@
+use std::{fs::File, io::BufReader, path::Path, sync::Arc};
+
+use anyhow::{anyhow, Context, Result};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_rustls::rustls::{
+    self, Certificate, OwnedTrustAnchor, PrivateKey, RootCertStore, ServerName,
+};
+pub use tokio_rustls::{TlsAcceptor, TlsConnector};
+
+/// Stream is a connection between a client and a server. It is either a plain TCP stream or a
+/// TLS stream wrapping one, so that the rest of the code does not need to care which.
+pub trait Stream: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T> Stream for T where T: AsyncRead + AsyncWrite + Unpin + Send {}
+
+pub type BoxedStream = Box<dyn Stream>;
+
+/// Creates a connector for clients. If `ca` is supplied, only the certificates in that PEM file
+/// are trusted, which is how self-signed test certificates are used. Otherwise the webpki roots
+/// are trusted.
+pub fn connector(ca: Option<&Path>) -> Result<TlsConnector> {
+    let mut roots = RootCertStore::empty();
+    match ca {
+        Some(ca) => {
+            for cert in load_certs(ca)? {
+                roots
+                    .add(&cert)
+                    .with_context(|| format!("invalid CA certificate in {}", ca.display()))?;
+            }
+        }
+        None => {
+            roots.add_trust_anchors(webpki_roots::TLS_SERVER_ROOTS.iter().map(|ta| {
+                OwnedTrustAnchor::from_subject_spki_name_constraints(
+                    ta.subject,
+                    ta.spki,
+                    ta.name_constraints,
+                )
+            }));
+        }
+    }
+    let config = rustls::ClientConfig::builder()
+        .with_safe_defaults()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    Ok(TlsConnector::from(Arc::new(config)))
+}
+
+/// Creates an acceptor for servers from a PEM certificate chain and a PEM private key.
+pub fn acceptor(cert: &Path, key: &Path) -> Result<TlsAcceptor> {
+    let certs = load_certs(cert)?;
+    let key = load_key(key)?;
+    let config = rustls::ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_single_cert(certs, key)?;
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
+
+/// Returns the name the server certificate is expected to have for an address like
+/// `localhost:8000`.
+pub fn server_name(addr: &str) -> Result<ServerName> {
+    let host = match addr.rsplit_once(':') {
+        Some((host, _port)) => host,
+        None => addr,
+    };
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    ServerName::try_from(host).map_err(|_| anyhow!("invalid server name: {host}"))
+}
+
+fn load_certs(path: &Path) -> Result<Vec<Certificate>> {
+    let file = File::open(path).with_context(|| format!("could not open {}", path.display()))?;
+    let certs = rustls_pemfile::certs(&mut BufReader::new(file))?;
+    if certs.is_empty() {
+        return Err(anyhow!("no certificates found in {}", path.display()));
+    }
+    Ok(certs.into_iter().map(Certificate).collect())
+}
+
+fn load_key(path: &Path) -> Result<PrivateKey> {
+    use rustls_pemfile::Item;
+    let file = File::open(path).with_context(|| format!("could not open {}", path.display()))?;
+    for item in rustls_pemfile::read_all(&mut BufReader::new(file))? {
+        match item {
+            Item::PKCS8Key(key) | Item::RSAKey(key) | Item::ECKey(key) => {
+                return Ok(PrivateKey(key))
+            }
+            _ => continue,
+        }
+    }
+    Err(anyhow!("no private key found in {}", path.display()))
+}
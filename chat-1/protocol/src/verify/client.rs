@@ -0,0 +1,348 @@
+use super::harness::{message, user, within, Conn, FakeServer, Input, Output};
+use super::*;
+use tokio::task::JoinHandle;
+
+/// ClientHarness starts the client under test against a fake server that the scenario scripts.
+pub struct ClientHarness {
+    client: ClientFn,
+    transport: Transport,
+}
+
+impl ClientHarness {
+    pub(super) fn new(client: ClientFn, transport: Transport) -> Self {
+        Self { client, transport }
+    }
+
+    fn start(&self) -> Running {
+        self.start_with(|_| {})
+    }
+
+    /// Starts the client after letting the scenario adjust its config.
+    fn start_with(&self, f: impl FnOnce(&mut ClientConfig)) -> Running {
+        let server = FakeServer::new(self.transport);
+        let output = Output::default();
+        let (input, stdin) = Input::pipe();
+        let mut config = server.client_config(output.clone(), stdin);
+        f(&mut config);
+        let client = tokio::spawn((self.client)(config));
+        Running {
+            server,
+            output,
+            input,
+            client,
+        }
+    }
+}
+
+/// A running client and the fake server it talks to.
+struct Running {
+    server: FakeServer,
+    output: Output,
+    input: Input,
+    client: JoinHandle<Result<()>>,
+}
+
+impl Running {
+    /// Accepts the client's connection and checks that it identifies itself.
+    async fn connected(&self) -> Conn {
+        let mut conn = self.server.accept().await;
+        conn.expect_ident("test-name").await;
+        conn
+    }
+
+    /// Waits for the client to exit without an error and returns what it wrote.
+    async fn exited(self) -> Output {
+        let res = within("the client to exit", self.client).await;
+        res.expect("client panicked")
+            .expect("client returned an error");
+        drop(self.input);
+        self.output
+    }
+}
+
+pub(super) fn scenarios() -> Vec<Scenario<ClientHarness>> {
+    vec![
+        Scenario {
+            name: "basic",
+            description: "identifies, prints one message and exits when the server closes",
+            run: |h| Box::pin(basic(h)),
+        },
+        Scenario {
+            name: "multiple_messages",
+            description: "prints several messages from several users in order",
+            run: |h| Box::pin(multiple_messages(h)),
+        },
+        Scenario {
+            name: "unicode",
+            description: "prints names and text outside of ascii unchanged",
+            run: |h| Box::pin(unicode(h)),
+        },
+        Scenario {
+            name: "long_text",
+            description: "prints a message much larger than a socket buffer",
+            run: |h| Box::pin(long_text(h)),
+        },
+        Scenario {
+            name: "malformed_frames",
+            description: "ignores frames that are not server events and keeps going",
+            run: |h| Box::pin(malformed_frames(h)),
+        },
+        Scenario {
+            name: "server_disconnect",
+            description: "exits cleanly when the server closes right after the ident",
+            run: |h| Box::pin(server_disconnect(h)),
+        },
+        Scenario {
+            name: "disconnect_mid_frame",
+            description: "exits cleanly when the server closes in the middle of a frame",
+            run: |h| Box::pin(disconnect_mid_frame(h)),
+        },
+        Scenario {
+            name: "slow_reads",
+            description: "reassembles frames that arrive a few bytes at a time",
+            run: |h| Box::pin(slow_reads(h)),
+        },
+        Scenario {
+            name: "interleaved",
+            description: "keeps the order of server events while the user is typing",
+            run: |h| Box::pin(interleaved(h)),
+        },
+        Scenario {
+            name: "send_and_quit",
+            description: "sends what the user types and exits at the end of the input",
+            run: |h| Box::pin(send_and_quit(h)),
+        },
+        Scenario {
+            name: "reconnect",
+            description: "reconnects after the server restarts and sends what was typed offline",
+            run: |h| Box::pin(reconnect(h)),
+        },
+        Scenario {
+            name: "server_errors",
+            description: "shows errors from the server as status lines and carries on",
+            run: |h| Box::pin(server_errors(h)),
+        },
+        Scenario {
+            name: "kicked",
+            description: "exits without reconnecting when the server kicks it",
+            run: |h| Box::pin(kicked(h)),
+        },
+    ]
+}
+
+async fn basic(h: ClientHarness) {
+    let running = h.start();
+    let mut conn = running.connected().await;
+    conn.send(&message("other-user", "hi there")).await;
+    conn.close().await;
+    let output = running.exited().await;
+    assert_eq!(output.chat_output(), "other-user: hi there\n");
+}
+
+async fn multiple_messages(h: ClientHarness) {
+    let running = h.start();
+    let mut conn = running.connected().await;
+    let mut expected = String::new();
+    for i in 0..10 {
+        let from = if i % 2 == 0 { "alice" } else { "bob" };
+        let text = format!("message {i}");
+        conn.send(&message(from, &text)).await;
+        expected.push_str(&format!("{from}: {text}\n"));
+    }
+    conn.close().await;
+    let output = running.exited().await;
+    assert_eq!(output.chat_output(), expected);
+}
+
+async fn unicode(h: ClientHarness) {
+    let running = h.start();
+    let mut conn = running.connected().await;
+    let texts = [
+        "héllo wörld",
+        "こんにちは 世界",
+        "🦀🚀 ferris",
+        "مرحبا بالعالم",
+        "a\ttab",
+    ];
+    let mut expected = String::new();
+    for text in texts {
+        conn.send(&message("zoë", text)).await;
+        expected.push_str(&format!("zoë: {text}\n"));
+    }
+    conn.close().await;
+    let output = running.exited().await;
+    assert_eq!(output.chat_output(), expected);
+}
+
+async fn long_text(h: ClientHarness) {
+    let running = h.start();
+    let mut conn = running.connected().await;
+    let text = "0123456789abcdef🦀".repeat(8 * 1024);
+    conn.send(&message("other-user", &text)).await;
+    conn.close().await;
+    let output = running.exited().await;
+    assert_eq!(output.chat_output(), format!("other-user: {text}\n"));
+}
+
+async fn malformed_frames(h: ClientHarness) {
+    let running = h.start();
+    let mut conn = running.connected().await;
+    conn.send_raw(b"this is not json\n").await;
+    conn.send_raw(b"{\"NoSuchEvent\":{}}\n").await;
+    conn.send_raw(b"[1, 2, 3]\n").await;
+    conn.send_raw(b"{\"Message\":{\"text\":\"missing fields\"}}\n")
+        .await;
+    conn.send(&message("other-user", "still here")).await;
+    conn.close().await;
+    let output = running.exited().await;
+    assert_eq!(output.chat_output(), "other-user: still here\n");
+}
+
+async fn server_disconnect(h: ClientHarness) {
+    let running = h.start();
+    let conn = running.connected().await;
+    conn.close().await;
+    let output = running.exited().await;
+    assert_eq!(output.chat_output(), "");
+}
+
+async fn disconnect_mid_frame(h: ClientHarness) {
+    let running = h.start();
+    let mut conn = running.connected().await;
+    conn.send(&message("other-user", "complete")).await;
+    conn.send_raw(b"{\"Message\":{\"from\":{\"na").await;
+    conn.close().await;
+    let output = running.exited().await;
+    assert_eq!(output.chat_output(), "other-user: complete\n");
+}
+
+async fn slow_reads(h: ClientHarness) {
+    let running = h.start();
+    let mut conn = running.connected().await;
+    let mut frames = String::new();
+    for text in ["first", "second"] {
+        let event = serde_json::to_string(&message("other-user", text)).unwrap();
+        frames.push_str(&event);
+        frames.push('\n');
+    }
+    for chunk in frames.as_bytes().chunks(3) {
+        conn.send_raw(chunk).await;
+        tokio::time::sleep(Duration::from_millis(1)).await;
+    }
+    conn.close().await;
+    let output = running.exited().await;
+    assert_eq!(
+        output.chat_output(),
+        "other-user: first\nother-user: second\n"
+    );
+}
+
+async fn interleaved(h: ClientHarness) {
+    let running = h.start();
+    let mut conn = running.connected().await;
+    conn.send(&ServerEvent::Joined(user("alice"))).await;
+    conn.send(&message("alice", "one")).await;
+    running.input.type_line("mine 1");
+    conn.expect_message("mine 1").await;
+    conn.send(&ServerEvent::Joined(user("bob"))).await;
+    conn.send(&message("bob", "two")).await;
+    running.input.type_line("mine 2");
+    running.input.type_line("mine 3");
+    conn.send(&message("alice", "three")).await;
+    conn.expect_message("mine 2").await;
+    conn.expect_message("mine 3").await;
+    conn.send(&ServerEvent::Left(user("alice"))).await;
+    conn.send(&message("bob", "four")).await;
+    conn.close().await;
+    let output = running.exited().await;
+    assert_eq!(
+        output.chat_output(),
+        "alice: one\nbob: two\nalice: three\nbob: four\n"
+    );
+}
+
+async fn send_and_quit(h: ClientHarness) {
+    let running = h.start();
+    let mut conn = running.connected().await;
+    running.input.type_line("hello");
+    running.input.type_line("");
+    running.input.type_line("  spaced out  ");
+    conn.expect_message("hello").await;
+    conn.expect_message("spaced out").await;
+    let Running { input, client, .. } = running;
+    drop(input);
+    let res = within("the client to exit", client).await;
+    res.expect("client panicked")
+        .expect("client returned an error");
+    assert!(conn.read_event().await.is_none());
+}
+
+/// The server sends a message, goes down and comes back on the same address. While it is down
+/// the harness types a message, which the client must send after it has identified itself again.
+async fn reconnect(h: ClientHarness) {
+    let mut running = h.start_with(|config| config.reconnect_attempts = 20);
+    let mut conn = running.connected().await;
+    conn.send(&message("other-user", "before")).await;
+    conn.close().await;
+    running.server.stop();
+    running
+        .output
+        .wait_for(|out| out.contains(&format!("{STATUS_PREFIX}disconnected")))
+        .await;
+
+    running.input.type_line("while offline");
+    running.server.restart();
+    let mut conn = running.connected().await;
+    conn.expect_message("while offline").await;
+    conn.send(&message("other-user", "after")).await;
+    running
+        .output
+        .wait_for(|out| out.contains("other-user: after"))
+        .await;
+
+    let Running {
+        input,
+        client,
+        output,
+        ..
+    } = running;
+    drop(input);
+    let res = within("the client to exit", client).await;
+    res.expect("client panicked")
+        .expect("client returned an error");
+    assert!(output
+        .output()
+        .contains(&format!("{STATUS_PREFIX}reconnected")));
+    assert_eq!(
+        output.chat_output(),
+        "other-user: before\nother-user: after\n"
+    );
+}
+
+async fn server_errors(h: ClientHarness) {
+    let running = h.start();
+    let mut conn = running.connected().await;
+    conn.send(&ServerEvent::Error(ChatError::RateLimited)).await;
+    conn.send(&message("other-user", "after")).await;
+    conn.close().await;
+    let output = running.exited().await;
+    assert_eq!(output.chat_output(), "other-user: after\n");
+    let out = output.output();
+    let before = out.lines().take_while(|line| *line != "other-user: after");
+    assert!(
+        before
+            .into_iter()
+            .any(|line| line.starts_with(STATUS_PREFIX)),
+        "the error was not shown: {out}"
+    );
+}
+
+async fn kicked(h: ClientHarness) {
+    let running = h.start_with(|config| config.reconnect_attempts = 20);
+    let mut conn = running.connected().await;
+    let kicked = ChatError::Kicked(String::from("op"));
+    conn.send(&ServerEvent::Error(kicked)).await;
+    conn.close().await;
+    // the server is still listening, so a client that reconnects would never exit.
+    running.exited().await;
+}
@@ -0,0 +1,283 @@
+use super::*;
+use crate::tls::{self, BoxedStream, TlsAcceptor};
+use rcgen::{BasicConstraints, Certificate, CertificateParams, DnType, IsCa};
+use std::collections::VecDeque;
+use std::fs;
+use std::net::SocketAddr;
+use std::sync::mpsc;
+use tempfile::TempDir;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader, ReadHalf, WriteHalf};
+use tokio::net::{TcpListener, TcpSocket};
+
+/// How long the harness waits for the other side before failing the scenario.
+pub(super) const WAIT: Duration = Duration::from_secs(5);
+
+/// Awaits `fut`, panicking with `what` if it takes longer than `WAIT`.
+pub(super) async fn within<T>(what: &str, fut: impl Future<Output = T>) -> T {
+    match tokio::time::timeout(WAIT, fut).await {
+        Ok(res) => res,
+        Err(_) => panic!("timed out waiting for {what}"),
+    }
+}
+
+pub(super) fn message(from: &str, text: &str) -> ServerEvent {
+    ServerEvent::Message(Message {
+        from: user(from),
+        text: String::from(text),
+        time: Timestamp::default(),
+    })
+}
+
+pub(super) fn user(name: &str) -> User {
+    User {
+        name: String::from(name),
+    }
+}
+
+/// FakeServer is what the client under test connects to.
+pub(super) struct FakeServer {
+    listener: Option<TcpListener>,
+    addr: SocketAddr,
+    tls: Option<(TlsAcceptor, TestCerts)>,
+}
+
+impl FakeServer {
+    pub(super) fn new(transport: Transport) -> Self {
+        let listener = listen("0.0.0.0:0".parse().unwrap());
+        let addr = listener.local_addr().unwrap();
+        let tls = match transport {
+            Transport::Plain => None,
+            Transport::Tls => {
+                let certs = TestCerts::generate().unwrap();
+                let acceptor = tls::acceptor(&certs.cert, &certs.key).unwrap();
+                Some((acceptor, certs))
+            }
+        };
+        Self {
+            listener: Some(listener),
+            addr,
+            tls,
+        }
+    }
+
+    /// Returns the config a client needs to connect to this server.
+    pub(super) fn client_config(&self, stdout: Output, stdin: super::Stdin) -> ClientConfig {
+        let addr = format!("localhost:{}", self.addr.port());
+        let tls_ca = self.tls.as_ref().map(|(_, certs)| certs.ca.clone());
+        let stdout: Box<dyn io::Write + Send> = Box::new(stdout);
+        ClientConfig {
+            name: Some(String::from("test-name")),
+            addr,
+            tls: tls_ca.is_some(),
+            tls_ca,
+            operator_secret: None,
+            reconnect_attempts: 0,
+            stdout: super::Stdout::from(stdout),
+            stdin,
+        }
+    }
+
+    /// Stops listening, so connection attempts are refused.
+    pub(super) fn stop(&mut self) {
+        self.listener = None;
+    }
+
+    /// Listens again on the same address.
+    pub(super) fn restart(&mut self) {
+        self.listener = Some(listen(self.addr));
+    }
+
+    /// Accepts the next connection, completing the TLS handshake if needed.
+    pub(super) async fn accept(&self) -> Conn {
+        let listener = self.listener.as_ref().expect("server is stopped");
+        let (stream, _) = within("the client to connect", listener.accept())
+            .await
+            .unwrap();
+        let stream: BoxedStream = match &self.tls {
+            Some((acceptor, _)) => Box::new(acceptor.accept(stream).await.unwrap()),
+            None => Box::new(stream),
+        };
+        let (rx, tx) = tokio::io::split(stream);
+        Conn {
+            rx: BufReader::new(rx),
+            tx,
+        }
+    }
+}
+
+fn listen(addr: SocketAddr) -> TcpListener {
+    let socket = TcpSocket::new_v4().unwrap();
+    socket.set_reuseaddr(true).unwrap();
+    socket.bind(addr).unwrap();
+    socket.listen(1024).unwrap()
+}
+
+/// The server side of a connection to the client under test.
+pub(super) struct Conn {
+    rx: BufReader<ReadHalf<BoxedStream>>,
+    tx: WriteHalf<BoxedStream>,
+}
+
+impl Conn {
+    /// Reads the next event from the client, or `None` if the client closed the connection.
+    pub(super) async fn read_event(&mut self) -> Option<ClientEvent> {
+        let mut buf = String::new();
+        let read = within("an event from the client", self.rx.read_line(&mut buf)).await;
+        if read.unwrap() == 0 {
+            return None;
+        }
+        Some(serde_json::from_str::<ClientEvent>(&buf).unwrap())
+    }
+
+    pub(super) async fn expect_ident(&mut self, expected: &str) {
+        match self.read_event().await {
+            Some(ClientEvent::Ident(User { name })) => assert_eq!(name, expected),
+            event => panic!("expected ident, got: {event:?}"),
+        }
+    }
+
+    pub(super) async fn expect_message(&mut self, expected: &str) {
+        match self.read_event().await {
+            Some(ClientEvent::Message(Message { from, text, .. })) => {
+                assert_eq!(from.name, "test-name");
+                assert_eq!(text, expected);
+            }
+            event => panic!("expected message {expected:?}, got: {event:?}"),
+        }
+    }
+
+    pub(super) async fn send(&mut self, event: &ServerEvent) {
+        let event = serde_json::to_string(event).unwrap();
+        self.send_raw(format!("{event}\n").as_bytes()).await;
+    }
+
+    /// Writes bytes as they are, which need not be a valid frame.
+    pub(super) async fn send_raw(&mut self, bytes: &[u8]) {
+        self.tx.write_all(bytes).await.unwrap();
+        self.tx.flush().await.unwrap();
+    }
+
+    /// Closes the connection from the server side.
+    pub(super) async fn close(mut self) {
+        self.tx.shutdown().await.unwrap();
+    }
+}
+
+/// Input lets the harness type lines as the user. Dropping it ends the input.
+pub(super) struct Input(mpsc::Sender<String>);
+
+impl Input {
+    pub(super) fn pipe() -> (Self, super::Stdin) {
+        let (tx, rx) = mpsc::channel();
+        let reader = InputReader {
+            rx,
+            pending: VecDeque::new(),
+        };
+        let reader: Box<dyn BufRead + Send> = Box::new(io::BufReader::new(reader));
+        (Self(tx), super::Stdin::from(reader))
+    }
+
+    pub(super) fn type_line(&self, line: &str) {
+        self.0.send(format!("{line}\n")).unwrap();
+    }
+}
+
+struct InputReader {
+    rx: mpsc::Receiver<String>,
+    pending: VecDeque<u8>,
+}
+
+impl io::Read for InputReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pending.is_empty() {
+            match self.rx.recv() {
+                Ok(line) => self.pending.extend(line.as_bytes()),
+                Err(_) => return Ok(0),
+            }
+        }
+        self.pending.read(buf)
+    }
+}
+
+/// A throwaway CA and a `localhost` certificate signed by it, written as PEM files to a
+/// temporary directory that is removed on drop.
+pub(super) struct TestCerts {
+    _dir: TempDir,
+    pub(super) ca: PathBuf,
+    pub(super) cert: PathBuf,
+    pub(super) key: PathBuf,
+}
+
+impl TestCerts {
+    pub(super) fn generate() -> Result<Self> {
+        let mut ca = CertificateParams::new(vec![]);
+        ca.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        ca.distinguished_name
+            .push(DnType::CommonName, "chat-1 test CA");
+        let ca = Certificate::from_params(ca)?;
+
+        let mut cert = CertificateParams::new(vec![String::from("localhost")]);
+        cert.distinguished_name
+            .push(DnType::CommonName, "localhost");
+        let cert = Certificate::from_params(cert)?;
+
+        let dir = tempfile::tempdir()?;
+        let certs = Self {
+            ca: dir.path().join("ca.pem"),
+            cert: dir.path().join("cert.pem"),
+            key: dir.path().join("key.pem"),
+            _dir: dir,
+        };
+        fs::write(&certs.ca, ca.serialize_pem()?)?;
+        fs::write(&certs.cert, cert.serialize_pem_with_signer(&ca)?)?;
+        fs::write(&certs.key, cert.serialize_private_key_pem())?;
+        Ok(certs)
+    }
+}
+
+/// Output captures what the client under test writes to its `Stdout`.
+#[derive(Default, Clone)]
+pub(super) struct Output {
+    buf: Arc<Mutex<Vec<u8>>>,
+}
+
+impl Output {
+    pub(super) fn output(&self) -> String {
+        let b = self.buf.lock().expect("lock fail");
+        let b = b.clone();
+        use std::str;
+        str::from_utf8(&b).expect("not valid utf8").to_string()
+    }
+
+    /// Returns the output without status lines.
+    pub(super) fn chat_output(&self) -> String {
+        self.output()
+            .lines()
+            .filter(|line| !line.starts_with(STATUS_PREFIX))
+            .map(|line| format!("{line}\n"))
+            .collect()
+    }
+
+    /// Waits for the output to satisfy `f`.
+    pub(super) async fn wait_for(&self, f: impl Fn(&str) -> bool) {
+        let wait = async {
+            while !f(&self.output()) {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        };
+        if tokio::time::timeout(WAIT, wait).await.is_err() {
+            panic!("timed out waiting for output, got: {:?}", self.output());
+        }
+    }
+}
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut b = self.buf.lock().unwrap();
+        std::io::Write::write(&mut *b, buf)
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        let mut b = self.buf.lock().unwrap();
+        std::io::Write::flush(&mut *b)
+    }
+}
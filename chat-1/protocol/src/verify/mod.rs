@@ -0,0 +1,242 @@
+//! The verification harness checks implementations of the protocol by running them through a
+//! library of named scenarios. Client scenarios put the client under test in front of a scripted
+//! server, and server scenarios put the server under test in front of scripted clients.
+//!
+//! The `verify_*` functions run every scenario and panic with a report if any of them fail. Use
+//! `Runner` directly to pick scenarios or to inspect the `Report` yourself.
+mod client;
+mod harness;
+mod server;
+
+use super::*;
+use std::{fmt::Display, future::Future, pin::Pin, time::Duration};
+
+pub use client::ClientHarness;
+pub use server::ServerHarness;
+
+type BoxFuture<T> = Pin<Box<dyn Future<Output = T> + Send>>;
+type ClientFn = Arc<dyn Fn(ClientConfig) -> BoxFuture<Result<()>> + Send + Sync>;
+type ServerFn = Arc<dyn Fn(ServerConfig) -> BoxFuture<Result<()>> + Send + Sync>;
+
+/// Verifies that the supplied client implements the protocol correctly.
+///
+/// Expected usage:
+///
+/// ```ignore
+/// struct Client;
+///
+/// impl Client {
+///     async fn start(config: ClientConfig) -> Result<()> {
+///         todo!()
+///     }
+/// }
+///
+/// #[tokio::test]
+/// async fn verify_client() {
+///     protocol::verify_client(Client::start).await
+/// }
+/// ```
+pub async fn verify_client<F, Fut>(client: F)
+where
+    F: Fn(ClientConfig) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Result<()>> + Send + 'static,
+{
+    Runner::new().run_client(client).await.assert_passed();
+}
+
+/// Same as `verify_client`, but the client must connect over TLS. A CA and a server certificate
+/// for `localhost` are generated for the run and the client is pointed at the CA with `tls_ca`.
+pub async fn verify_client_tls<F, Fut>(client: F)
+where
+    F: Fn(ClientConfig) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Result<()>> + Send + 'static,
+{
+    Runner::new()
+        .transport(Transport::Tls)
+        .run_client(client)
+        .await
+        .assert_passed();
+}
+
+/// Verifies that the supplied server implements the protocol correctly. Each scenario starts a
+/// fresh server listening on `config.addr` and connects scripted clients to it.
+pub async fn verify_server<F, Fut>(server: F)
+where
+    F: Fn(ServerConfig) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Result<()>> + Send + 'static,
+{
+    Runner::new().run_server(server).await.assert_passed();
+}
+
+/// Same as `verify_server`, but the server is given a generated certificate with `tls_cert` and
+/// `tls_key` and the scripted clients connect over TLS.
+pub async fn verify_server_tls<F, Fut>(server: F)
+where
+    F: Fn(ServerConfig) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Result<()>> + Send + 'static,
+{
+    Runner::new()
+        .transport(Transport::Tls)
+        .run_server(server)
+        .await
+        .assert_passed();
+}
+
+/// How the harness and the implementation under test talk to each other.
+#[derive(Clone, Copy, Debug, Default)]
+pub enum Transport {
+    #[default]
+    Plain,
+    Tls,
+}
+
+/// A named check of one behavior. `H` is the harness the scenario drives.
+pub struct Scenario<H> {
+    pub name: &'static str,
+    pub description: &'static str,
+    run: fn(H) -> BoxFuture<()>,
+}
+
+/// Returns every client scenario.
+pub fn client_scenarios() -> Vec<Scenario<ClientHarness>> {
+    client::scenarios()
+}
+
+/// Returns every server scenario.
+pub fn server_scenarios() -> Vec<Scenario<ServerHarness>> {
+    server::scenarios()
+}
+
+/// Runner runs scenarios and collects their results into a `Report`. A scenario fails if it
+/// panics or takes longer than the timeout.
+pub struct Runner {
+    transport: Transport,
+    only: Vec<String>,
+    timeout: Duration,
+}
+
+impl Default for Runner {
+    fn default() -> Self {
+        Self {
+            transport: Transport::default(),
+            only: vec![],
+            timeout: Duration::from_secs(20),
+        }
+    }
+}
+
+impl Runner {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn transport(mut self, transport: Transport) -> Self {
+        self.transport = transport;
+        self
+    }
+
+    /// Only runs the scenarios with these names.
+    pub fn only(mut self, names: &[&str]) -> Self {
+        self.only = names.iter().map(ToString::to_string).collect();
+        self
+    }
+
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    pub async fn run_client<F, Fut>(&self, client: F) -> Report
+    where
+        F: Fn(ClientConfig) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<()>> + Send + 'static,
+    {
+        let client: ClientFn = Arc::new(move |config| Box::pin(client(config)));
+        let harness = || ClientHarness::new(client.clone(), self.transport);
+        self.run(client::scenarios(), harness).await
+    }
+
+    pub async fn run_server<F, Fut>(&self, server: F) -> Report
+    where
+        F: Fn(ServerConfig) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<()>> + Send + 'static,
+    {
+        let server: ServerFn = Arc::new(move |config| Box::pin(server(config)));
+        let harness = || ServerHarness::new(server.clone(), self.transport);
+        self.run(server::scenarios(), harness).await
+    }
+
+    async fn run<H>(&self, scenarios: Vec<Scenario<H>>, harness: impl Fn() -> H) -> Report {
+        let mut report = Report::default();
+        let selected = scenarios
+            .into_iter()
+            .filter(|s| self.only.is_empty() || self.only.iter().any(|name| name == s.name));
+        for scenario in selected {
+            let run = (scenario.run)(harness());
+            let outcome = match tokio::time::timeout(self.timeout, tokio::spawn(run)).await {
+                Ok(Ok(())) => Outcome::Passed,
+                Ok(Err(err)) if err.is_panic() => Outcome::Failed(panic_message(err.into_panic())),
+                Ok(Err(err)) => Outcome::Failed(err.to_string()),
+                Err(_) => Outcome::Failed(format!("timed out after {:?}", self.timeout)),
+            };
+            report.results.push(ScenarioResult {
+                name: scenario.name,
+                outcome,
+            });
+        }
+        report
+    }
+}
+
+fn panic_message(panic: Box<dyn std::any::Any + Send>) -> String {
+    match panic.downcast::<String>() {
+        Ok(msg) => *msg,
+        Err(panic) => match panic.downcast::<&str>() {
+            Ok(msg) => msg.to_string(),
+            Err(_) => String::from("panicked"),
+        },
+    }
+}
+
+/// The results of a run, in the order the scenarios ran.
+#[derive(Default, Debug)]
+pub struct Report {
+    pub results: Vec<ScenarioResult>,
+}
+
+#[derive(Debug)]
+pub struct ScenarioResult {
+    pub name: &'static str,
+    pub outcome: Outcome,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Outcome {
+    Passed,
+    Failed(String),
+}
+
+impl Report {
+    pub fn passed(&self) -> bool {
+        self.results.iter().all(|r| r.outcome == Outcome::Passed)
+    }
+
+    /// Panics with the whole report if any scenario failed.
+    pub fn assert_passed(&self) {
+        assert!(self.passed(), "conformance failures:\n{self}");
+    }
+}
+
+/// PASS basic
+/// FAIL slow_reads: timed out after 20s
+impl Display for Report {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for result in &self.results {
+            match &result.outcome {
+                Outcome::Passed => writeln!(f, "PASS {}", result.name)?,
+                Outcome::Failed(why) => writeln!(f, "FAIL {}: {why}", result.name)?,
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,755 @@
+use super::harness::{user, within, TestCerts, WAIT};
+use super::*;
+use crate::tls::{self, BoxedStream, TlsConnector};
+use std::net::SocketAddr;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader, ReadHalf, WriteHalf};
+use tokio::net::TcpStream;
+use tokio::task::JoinHandle;
+
+/// ServerHarness starts the server under test on a free port and connects scripted clients to
+/// it.
+pub struct ServerHarness {
+    server: ServerFn,
+    transport: Transport,
+}
+
+impl ServerHarness {
+    pub(super) fn new(server: ServerFn, transport: Transport) -> Self {
+        Self { server, transport }
+    }
+
+    fn start(&self) -> Running {
+        self.start_with(|_| {})
+    }
+
+    /// Starts the server after letting the scenario adjust its config. By default the limits are
+    /// loose enough not to get in the way, and `op` is the only operator.
+    fn start_with(&self, f: impl FnOnce(&mut ServerConfig)) -> Running {
+        let addr = free_addr();
+        let (certs, connector) = match self.transport {
+            Transport::Plain => (None, None),
+            Transport::Tls => {
+                let certs = TestCerts::generate().unwrap();
+                let connector = tls::connector(Some(&certs.ca)).unwrap();
+                (Some(certs), Some(connector))
+            }
+        };
+        let mut config = ServerConfig {
+            addr: addr.to_string(),
+            tls_cert: certs.as_ref().map(|c| c.cert.clone()),
+            tls_key: certs.as_ref().map(|c| c.key.clone()),
+            rate_limit: 1000.0,
+            rate_burst: 1000,
+            max_message_len: 1 << 20,
+            operators: vec![Operator {
+                name: String::from("op"),
+                secret: String::from(OP_SECRET),
+            }],
+        };
+        f(&mut config);
+        let server = tokio::spawn((self.server)(config));
+        Running {
+            addr,
+            _certs: certs,
+            connector,
+            server,
+        }
+    }
+}
+
+/// The secret that `op` proves it is an operator with.
+const OP_SECRET: &str = "let me moderate";
+
+/// Finds a port that nothing is listening on by binding to port 0 and letting it go.
+fn free_addr() -> SocketAddr {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    listener.local_addr().unwrap()
+}
+
+/// A running server. It is aborted when this is dropped.
+struct Running {
+    addr: SocketAddr,
+    _certs: Option<TestCerts>,
+    connector: Option<TlsConnector>,
+    server: JoinHandle<Result<()>>,
+}
+
+impl Drop for Running {
+    fn drop(&mut self) {
+        self.server.abort();
+    }
+}
+
+impl Running {
+    /// Connects a client that has not identified itself yet. The server may still be starting,
+    /// so refused connections are retried for a while.
+    async fn connect(&self) -> ScriptedClient {
+        let connect = async {
+            loop {
+                assert!(!self.server.is_finished(), "the server exited");
+                match TcpStream::connect(self.addr).await {
+                    Ok(stream) => return stream,
+                    Err(_) => tokio::time::sleep(Duration::from_millis(10)).await,
+                }
+            }
+        };
+        let stream = within("the server to accept connections", connect).await;
+        let stream: BoxedStream = match &self.connector {
+            Some(connector) => {
+                let domain = tls::server_name("localhost").unwrap();
+                Box::new(connector.connect(domain, stream).await.unwrap())
+            }
+            None => Box::new(stream),
+        };
+        let (rx, tx) = tokio::io::split(stream);
+        ScriptedClient {
+            name: String::new(),
+            rx: BufReader::new(rx),
+            tx,
+        }
+    }
+
+    /// Connects a client and identifies it as `name`. `op` also sends its secret.
+    async fn join(&self, name: &str) -> ScriptedClient {
+        let mut client = self.connect().await;
+        client.name = name.to_string();
+        client.send(&ClientEvent::Ident(user(name))).await;
+        if name == "op" {
+            let secret = ClientEvent::Operator(String::from(OP_SECRET));
+            client.send(&secret).await;
+        }
+        client
+    }
+
+    /// Joins everyone and waits until each of them has seen all the others join, so that the
+    /// server is known to have processed every ident.
+    async fn join_all<const N: usize>(&self, names: [&str; N]) -> [ScriptedClient; N] {
+        let mut clients = vec![];
+        for name in names {
+            clients.push(self.join(name).await);
+        }
+        // the order in which each client hears about the others is up to the server.
+        for client in &mut clients {
+            let mut waiting: Vec<&str> = names.into_iter().filter(|n| *n != client.name).collect();
+            while !waiting.is_empty() {
+                match client.next_event().await {
+                    Some(ServerEvent::Joined(user)) => waiting.retain(|n| *n != user.name),
+                    event => panic!("{} expected users to join, got: {event:?}", client.name),
+                }
+            }
+        }
+        clients.try_into().unwrap_or_else(|_| unreachable!())
+    }
+}
+
+/// A client whose every move is scripted by the scenario.
+struct ScriptedClient {
+    name: String,
+    rx: BufReader<ReadHalf<BoxedStream>>,
+    tx: WriteHalf<BoxedStream>,
+}
+
+impl ScriptedClient {
+    async fn send(&mut self, event: &ClientEvent) {
+        let event = serde_json::to_string(event).unwrap();
+        self.send_raw(format!("{event}\n").as_bytes()).await;
+    }
+
+    /// Writes bytes as they are, which need not be a valid frame.
+    async fn send_raw(&mut self, bytes: &[u8]) {
+        self.tx.write_all(bytes).await.unwrap();
+        self.tx.flush().await.unwrap();
+    }
+
+    /// Sends a chat message as this client.
+    async fn say(&mut self, text: &str) {
+        let event = ClientEvent::Message(Message {
+            from: user(&self.name),
+            text: text.to_string(),
+            time: Timestamp::default(),
+        });
+        self.send(&event).await;
+    }
+
+    /// Reads the next event, skipping over the server accepting what this client sent. Returns
+    /// `None` if the server closed the connection.
+    async fn next_event(&mut self) -> Option<ServerEvent> {
+        loop {
+            match self.read_event().await {
+                Some(ServerEvent::Accepted) => continue,
+                event => return event,
+            }
+        }
+    }
+
+    /// Reads the next event as it is, or `None` if the server closed the connection.
+    async fn read_event(&mut self) -> Option<ServerEvent> {
+        let mut buf = String::new();
+        let read = within(
+            &format!("an event for {}", self.name),
+            self.rx.read_line(&mut buf),
+        )
+        .await;
+        if read.unwrap() == 0 {
+            return None;
+        }
+        Some(serde_json::from_str::<ServerEvent>(&buf).unwrap())
+    }
+
+    /// Returns the next message, skipping over presence events.
+    async fn next_message(&mut self) -> Message {
+        loop {
+            match self.next_event().await {
+                Some(ServerEvent::Message(message)) => return message,
+                Some(ServerEvent::Joined(_) | ServerEvent::Left(_) | ServerEvent::Accepted) => {
+                    continue
+                }
+                Some(ServerEvent::Error(err)) => panic!("{} got an error: {err}", self.name),
+                None => panic!("{} was disconnected", self.name),
+            }
+        }
+    }
+
+    async fn expect_message(&mut self, from: &str, text: &str) {
+        let message = self.next_message().await;
+        assert_eq!(message.from.name, from, "wrong sender for {}", self.name);
+        assert_eq!(message.text, text, "wrong text for {}", self.name);
+    }
+
+    async fn expect_joined(&mut self, name: &str) {
+        match self.next_event().await {
+            Some(ServerEvent::Joined(user)) => assert_eq!(user.name, name),
+            event => panic!("{} expected {name} to join, got: {event:?}", self.name),
+        }
+    }
+
+    async fn expect_left(&mut self, name: &str) {
+        match self.next_event().await {
+            Some(ServerEvent::Left(user)) => assert_eq!(user.name, name),
+            event => panic!("{} expected {name} to leave, got: {event:?}", self.name),
+        }
+    }
+
+    /// Sends a moderation command as this client.
+    async fn admin(&mut self, command: AdminCommand) {
+        self.send(&ClientEvent::Admin(command)).await;
+    }
+
+    async fn expect_error(&mut self, expected: ChatError) {
+        match self.next_event().await {
+            Some(ServerEvent::Error(err)) => assert_eq!(err, expected, "for {}", self.name),
+            event => panic!("{} expected {expected:?}, got: {event:?}", self.name),
+        }
+    }
+
+    /// Checks that the server hangs up on this client.
+    async fn expect_disconnected(&mut self) {
+        if let Some(event) = self.next_event().await {
+            panic!("{} expected to be disconnected, got: {event:?}", self.name);
+        }
+    }
+
+    /// Checks that nothing arrives for a little while.
+    async fn expect_silence(&mut self) {
+        let mut buf = String::new();
+        let read = tokio::time::timeout(WAIT / 20, self.rx.read_line(&mut buf)).await;
+        if let Ok(Ok(n)) = read {
+            assert_eq!(n, 0, "{} expected nothing, got: {buf}", self.name);
+        }
+    }
+
+    async fn close(mut self) {
+        self.tx.shutdown().await.unwrap();
+    }
+}
+
+pub(super) fn scenarios() -> Vec<Scenario<ServerHarness>> {
+    vec![
+        Scenario {
+            name: "broadcast",
+            description: "relays a message to everyone except the sender",
+            run: |h| Box::pin(broadcast(h)),
+        },
+        Scenario {
+            name: "presence",
+            description: "announces users joining and leaving",
+            run: |h| Box::pin(presence(h)),
+        },
+        Scenario {
+            name: "sender_is_ident",
+            description: "sends messages from the identified user, whatever the client claims",
+            run: |h| Box::pin(sender_is_ident(h)),
+        },
+        Scenario {
+            name: "accepted",
+            description: "answers each message and command with either an accept or an error",
+            run: |h| Box::pin(accepted(h)),
+        },
+        Scenario {
+            name: "requires_ident",
+            description: "neither relays messages from nor sends messages to unidentified clients",
+            run: |h| Box::pin(requires_ident(h)),
+        },
+        Scenario {
+            name: "unique_names",
+            description: "refuses a name that someone connected already has",
+            run: |h| Box::pin(unique_names(h)),
+        },
+        Scenario {
+            name: "single_ident",
+            description: "refuses a second ident on the same connection",
+            run: |h| Box::pin(single_ident(h)),
+        },
+        Scenario {
+            name: "ordering",
+            description: "relays a burst of messages in the order they were sent",
+            run: |h| Box::pin(ordering(h)),
+        },
+        Scenario {
+            name: "unicode_and_long_text",
+            description: "relays text outside of ascii and larger than a socket buffer intact",
+            run: |h| Box::pin(unicode_and_long_text(h)),
+        },
+        Scenario {
+            name: "malformed_frames",
+            description: "keeps serving everyone else when a client sends garbage",
+            run: |h| Box::pin(malformed_frames(h)),
+        },
+        Scenario {
+            name: "disconnect_mid_frame",
+            description: "keeps serving everyone else when a client goes away mid frame",
+            run: |h| Box::pin(disconnect_mid_frame(h)),
+        },
+        Scenario {
+            name: "slow_writes",
+            description: "reassembles frames that arrive a few bytes at a time",
+            run: |h| Box::pin(slow_writes(h)),
+        },
+        Scenario {
+            name: "stalled_reader",
+            description: "hangs up on a client that stops reading instead of waiting for it",
+            run: |h| Box::pin(stalled_reader(h)),
+        },
+        Scenario {
+            name: "unterminated_line",
+            description: "hangs up on a client that sends a line longer than any valid frame",
+            run: |h| Box::pin(unterminated_line(h)),
+        },
+        Scenario {
+            name: "rate_limit",
+            description: "refuses messages from a user who sends too many too quickly",
+            run: |h| Box::pin(rate_limit(h)),
+        },
+        Scenario {
+            name: "message_too_long",
+            description: "refuses messages longer than the maximum",
+            run: |h| Box::pin(message_too_long(h)),
+        },
+        Scenario {
+            name: "operators_only",
+            description: "only takes moderation commands from operators",
+            run: |h| Box::pin(operators_only(h)),
+        },
+        Scenario {
+            name: "operator_secret",
+            description: "needs an operator's secret as well as their name",
+            run: |h| Box::pin(operator_secret(h)),
+        },
+        Scenario {
+            name: "admin_rate_limit",
+            description: "counts secrets and moderation commands against the rate limit",
+            run: |h| Box::pin(admin_rate_limit(h)),
+        },
+        Scenario {
+            name: "rate_limit_by_name",
+            description: "keeps a user's rate limit when they reconnect",
+            run: |h| Box::pin(rate_limit_by_name(h)),
+        },
+        Scenario {
+            name: "kick",
+            description: "disconnects a kicked user, who may come back",
+            run: |h| Box::pin(kick(h)),
+        },
+        Scenario {
+            name: "mute",
+            description: "stops relaying a muted user's messages",
+            run: |h| Box::pin(mute(h)),
+        },
+        Scenario {
+            name: "endless_mute",
+            description: "takes a mute too long to represent without falling over",
+            run: |h| Box::pin(endless_mute(h)),
+        },
+        Scenario {
+            name: "ban",
+            description: "disconnects a banned user and refuses them when they come back",
+            run: |h| Box::pin(ban(h)),
+        },
+    ]
+}
+
+async fn broadcast(h: ServerHarness) {
+    let running = h.start();
+    let [mut alice, mut bob, mut carol] = running.join_all(["alice", "bob", "carol"]).await;
+    alice.say("hello").await;
+    bob.expect_message("alice", "hello").await;
+    carol.expect_message("alice", "hello").await;
+    // if alice got her own message back, it would arrive before this one.
+    bob.say("hi alice").await;
+    alice.expect_message("bob", "hi alice").await;
+}
+
+async fn presence(h: ServerHarness) {
+    let running = h.start();
+    let mut alice = running.join("alice").await;
+    let mut bob = running.join("bob").await;
+    alice.expect_joined("bob").await;
+    bob.expect_joined("alice").await;
+    bob.close().await;
+    alice.expect_left("bob").await;
+}
+
+async fn sender_is_ident(h: ServerHarness) {
+    let running = h.start();
+    let [mut alice, mut bob] = running.join_all(["alice", "bob"]).await;
+    let event = ClientEvent::Message(Message {
+        from: user("mallory"),
+        text: String::from("it was me"),
+        time: Timestamp::default(),
+    });
+    alice.send(&event).await;
+    bob.expect_message("alice", "it was me").await;
+}
+
+async fn accepted(h: ServerHarness) {
+    let running = h.start_with(|config| config.max_message_len = 8);
+    let mut op = running.join("op").await;
+    assert!(matches!(op.read_event().await, Some(ServerEvent::Accepted)));
+    let mut alice = running.join("alice").await;
+    op.expect_joined("alice").await;
+    op.say("hello").await;
+    op.say("far too long").await;
+    op.admin(AdminCommand::Mute(String::from("alice"), 60))
+        .await;
+    // answers come back in the order the client sent things in.
+    for expected in [None, Some(ChatError::MessageTooLong(8)), None] {
+        match (op.read_event().await, expected) {
+            (Some(ServerEvent::Accepted), None) => {}
+            (Some(ServerEvent::Error(err)), Some(expected)) => assert_eq!(err, expected),
+            (event, expected) => panic!("op expected {expected:?}, got: {event:?}"),
+        }
+    }
+    alice.expect_message("op", "hello").await;
+}
+
+async fn requires_ident(h: ServerHarness) {
+    let running = h.start();
+    let mut lurker = running.connect().await;
+    lurker.name = String::from("lurker");
+    let [mut alice, mut bob] = running.join_all(["alice", "bob"]).await;
+    lurker.say("let me in").await;
+    alice.say("after").await;
+    bob.expect_message("alice", "after").await;
+    lurker.expect_silence().await;
+}
+
+async fn unique_names(h: ServerHarness) {
+    let running = h.start();
+    let [alice, mut bob] = running.join_all(["alice", "bob"]).await;
+    let mut impostor = running.join("alice").await;
+    impostor
+        .expect_error(ChatError::NameTaken(String::from("alice")))
+        .await;
+    impostor.expect_disconnected().await;
+    // once alice has gone her name is free again.
+    alice.close().await;
+    bob.expect_left("alice").await;
+    let _alice = running.join("alice").await;
+    bob.expect_joined("alice").await;
+}
+
+async fn single_ident(h: ServerHarness) {
+    let running = h.start();
+    let [mut alice, mut bob] = running.join_all(["alice", "bob"]).await;
+    alice.send(&ClientEvent::Ident(user("op"))).await;
+    alice.expect_error(ChatError::AlreadyIdentified).await;
+    alice.say("who am i").await;
+    bob.expect_message("alice", "who am i").await;
+}
+
+async fn ordering(h: ServerHarness) {
+    let running = h.start();
+    let [mut alice, mut bob] = running.join_all(["alice", "bob"]).await;
+    for i in 0..100 {
+        alice.say(&format!("message {i}")).await;
+    }
+    for i in 0..100 {
+        bob.expect_message("alice", &format!("message {i}")).await;
+    }
+}
+
+async fn unicode_and_long_text(h: ServerHarness) {
+    let running = h.start();
+    let [mut alice, mut bob] = running.join_all(["alice", "bob"]).await;
+    let texts = [
+        String::from("こんにちは 世界 🦀 مرحبا"),
+        "0123456789abcdef🦀".repeat(8 * 1024),
+    ];
+    for text in &texts {
+        alice.say(text).await;
+    }
+    for text in &texts {
+        bob.expect_message("alice", text).await;
+    }
+}
+
+async fn malformed_frames(h: ServerHarness) {
+    let running = h.start();
+    let [mut alice, mut bob] = running.join_all(["alice", "bob"]).await;
+    let mut bad = running.connect().await;
+    bad.send_raw(b"this is not json\n").await;
+    bad.send_raw(b"{\"NoSuchEvent\":{}}\n").await;
+    alice.say("still working").await;
+    bob.expect_message("alice", "still working").await;
+}
+
+async fn disconnect_mid_frame(h: ServerHarness) {
+    let running = h.start();
+    let [mut alice, mut bob] = running.join_all(["alice", "bob"]).await;
+    let mut partial = running.join("partial").await;
+    alice.expect_joined("partial").await;
+    bob.expect_joined("partial").await;
+    partial.send_raw(b"{\"Message\":{\"from\":{\"na").await;
+    partial.close().await;
+    alice.expect_left("partial").await;
+    alice.say("still working").await;
+    bob.expect_message("alice", "still working").await;
+}
+
+async fn slow_writes(h: ServerHarness) {
+    let running = h.start();
+    let [mut alice, mut bob] = running.join_all(["alice", "bob"]).await;
+    let event = ClientEvent::Message(Message {
+        from: user("alice"),
+        text: String::from("one byte at a time"),
+        time: Timestamp::default(),
+    });
+    let frame = format!("{}\n", serde_json::to_string(&event).unwrap());
+    for chunk in frame.as_bytes().chunks(3) {
+        alice.send_raw(chunk).await;
+        tokio::time::sleep(Duration::from_millis(1)).await;
+    }
+    bob.expect_message("alice", "one byte at a time").await;
+}
+
+async fn stalled_reader(h: ServerHarness) {
+    let running = h.start_with(|config| config.rate_burst = 100_000);
+    let [mut alice, mut bob, _stalled] = running.join_all(["alice", "bob", "stalled"]).await;
+    // enough to fill the stalled client's socket buffers and whatever the server queues for it.
+    // alice waits for bob each time so that only the stalled client falls behind.
+    let text = "x".repeat(16 * 1024);
+    let mut left = false;
+    for i in 0..2000 {
+        let sent = format!("{i} {text}");
+        alice.say(&sent).await;
+        loop {
+            match bob.next_event().await {
+                Some(ServerEvent::Message(message)) => {
+                    assert_eq!(message.text, sent);
+                    break;
+                }
+                Some(ServerEvent::Left(user)) if user.name == "stalled" => left = true,
+                event => panic!("bob expected a message, got: {event:?}"),
+            }
+        }
+    }
+    assert!(left, "the stalled client was never disconnected");
+}
+
+async fn unterminated_line(h: ServerHarness) {
+    let running = h.start_with(|config| config.max_message_len = 8);
+    let [mut alice, mut bob] = running.join_all(["alice", "bob"]).await;
+    let mut flood = running.connect().await;
+    flood.name = String::from("flood");
+    flood.send_raw(&[b'x'; 64 * 1024]).await;
+    flood.expect_disconnected().await;
+    alice.say("still ok").await;
+    bob.expect_message("alice", "still ok").await;
+}
+
+async fn rate_limit(h: ServerHarness) {
+    // with no refill, the burst is all anyone gets.
+    let running = h.start_with(|config| {
+        config.rate_limit = 0.0;
+        config.rate_burst = 3;
+    });
+    let [mut alice, mut bob] = running.join_all(["alice", "bob"]).await;
+    for i in 0..4 {
+        alice.say(&format!("message {i}")).await;
+    }
+    alice.expect_error(ChatError::RateLimited).await;
+    for i in 0..3 {
+        bob.expect_message("alice", &format!("message {i}")).await;
+    }
+    // bob's limit is separate from alice's.
+    bob.say("my turn").await;
+    alice.expect_message("bob", "my turn").await;
+}
+
+async fn message_too_long(h: ServerHarness) {
+    let running = h.start_with(|config| config.max_message_len = 8);
+    let [mut alice, mut bob] = running.join_all(["alice", "bob"]).await;
+    alice.say("far too long").await;
+    alice.expect_error(ChatError::MessageTooLong(8)).await;
+    alice.say("short").await;
+    bob.expect_message("alice", "short").await;
+}
+
+async fn operators_only(h: ServerHarness) {
+    let running = h.start();
+    let [mut alice, mut bob] = running.join_all(["alice", "bob"]).await;
+    alice.admin(AdminCommand::Kick(String::from("bob"))).await;
+    alice.expect_error(ChatError::NotOperator).await;
+    alice.say("still here?").await;
+    bob.expect_message("alice", "still here?").await;
+}
+
+async fn operator_secret(h: ServerHarness) {
+    let running = h.start();
+    let mut alice = running.join("alice").await;
+    // op's name alone is not enough.
+    let mut op = running.connect().await;
+    op.name = String::from("op");
+    op.send(&ClientEvent::Ident(user("op"))).await;
+    op.expect_joined("alice").await;
+    alice.expect_joined("op").await;
+    op.admin(AdminCommand::Kick(String::from("alice"))).await;
+    op.expect_error(ChatError::NotOperator).await;
+    // nor is a wrong secret.
+    op.send(&ClientEvent::Operator(String::from("guess"))).await;
+    op.expect_error(ChatError::NotOperator).await;
+    op.admin(AdminCommand::Kick(String::from("alice"))).await;
+    op.expect_error(ChatError::NotOperator).await;
+    // nor is someone else's secret.
+    alice
+        .send(&ClientEvent::Operator(String::from(OP_SECRET)))
+        .await;
+    alice.expect_error(ChatError::NotOperator).await;
+    // with the right secret, op can moderate.
+    op.send(&ClientEvent::Operator(String::from(OP_SECRET)))
+        .await;
+    op.admin(AdminCommand::Kick(String::from("alice"))).await;
+    alice
+        .expect_error(ChatError::Kicked(String::from("op")))
+        .await;
+}
+
+async fn admin_rate_limit(h: ServerHarness) {
+    let running = h.start_with(|config| {
+        config.rate_limit = 0.0;
+        config.rate_burst = 3;
+    });
+    let [mut op, mut mallory] = running.join_all(["op", "mallory"]).await;
+    // op's secret took one token.
+    op.admin(AdminCommand::Mute(String::from("nobody"), 60))
+        .await;
+    op.expect_error(ChatError::NoSuchUser(String::from("nobody")))
+        .await;
+    op.admin(AdminCommand::Mute(String::from("nobody"), 60))
+        .await;
+    op.expect_error(ChatError::NoSuchUser(String::from("nobody")))
+        .await;
+    op.admin(AdminCommand::Mute(String::from("nobody"), 60))
+        .await;
+    op.expect_error(ChatError::RateLimited).await;
+    for _ in 0..3 {
+        mallory
+            .send(&ClientEvent::Operator(String::from("guess")))
+            .await;
+        mallory.expect_error(ChatError::NotOperator).await;
+    }
+    mallory
+        .send(&ClientEvent::Operator(String::from(OP_SECRET)))
+        .await;
+    mallory.expect_error(ChatError::RateLimited).await;
+}
+
+async fn rate_limit_by_name(h: ServerHarness) {
+    let running = h.start_with(|config| {
+        config.rate_limit = 0.0;
+        config.rate_burst = 3;
+    });
+    let [mut alice, mut bob] = running.join_all(["alice", "bob"]).await;
+    for i in 0..3 {
+        alice.say(&format!("message {i}")).await;
+    }
+    for i in 0..3 {
+        bob.expect_message("alice", &format!("message {i}")).await;
+    }
+    alice.close().await;
+    bob.expect_left("alice").await;
+    let mut alice = running.join("alice").await;
+    alice.expect_joined("bob").await;
+    alice.say("fresh start?").await;
+    alice.expect_error(ChatError::RateLimited).await;
+}
+
+async fn kick(h: ServerHarness) {
+    let running = h.start();
+    let [mut op, mut alice, mut bob] = running.join_all(["op", "alice", "bob"]).await;
+    op.admin(AdminCommand::Kick(String::from("nobody"))).await;
+    op.expect_error(ChatError::NoSuchUser(String::from("nobody")))
+        .await;
+    op.admin(AdminCommand::Kick(String::from("bob"))).await;
+    bob.expect_error(ChatError::Kicked(String::from("op")))
+        .await;
+    bob.expect_disconnected().await;
+    alice.expect_left("bob").await;
+    let _bob = running.join("bob").await;
+    alice.expect_joined("bob").await;
+}
+
+async fn mute(h: ServerHarness) {
+    let running = h.start();
+    let [mut op, mut alice, mut bob] = running.join_all(["op", "alice", "bob"]).await;
+    op.admin(AdminCommand::Mute(String::from("bob"), 60)).await;
+    // the op's next message shows the mute has been processed.
+    op.say("muted").await;
+    bob.expect_message("op", "muted").await;
+    bob.say("let me speak").await;
+    bob.expect_error(ChatError::Muted(60)).await;
+    alice.expect_message("op", "muted").await;
+    op.say("after").await;
+    alice.expect_message("op", "after").await;
+}
+
+async fn endless_mute(h: ServerHarness) {
+    let running = h.start();
+    let [mut op, mut bob] = running.join_all(["op", "bob"]).await;
+    op.admin(AdminCommand::Mute(String::from("bob"), u64::MAX))
+        .await;
+    op.say("muted").await;
+    bob.expect_message("op", "muted").await;
+    bob.say("let me speak").await;
+    match bob.next_event().await {
+        Some(ServerEvent::Error(ChatError::Muted(_))) => {}
+        event => panic!("bob expected to be muted, got: {event:?}"),
+    }
+}
+
+async fn ban(h: ServerHarness) {
+    let running = h.start();
+    let [mut op, mut alice, mut bob] = running.join_all(["op", "alice", "bob"]).await;
+    op.admin(AdminCommand::Ban(String::from("bob"))).await;
+    bob.expect_error(ChatError::Banned).await;
+    bob.expect_disconnected().await;
+    alice.expect_left("bob").await;
+    let mut bob = running.join("bob").await;
+    bob.expect_error(ChatError::Banned).await;
+    bob.expect_disconnected().await;
+    op.say("bob is gone").await;
+    match alice.next_event().await {
+        Some(ServerEvent::Message(message)) => assert_eq!(message.text, "bob is gone"),
+        event => panic!("alice expected a message, got: {event:?}"),
+    }
+}
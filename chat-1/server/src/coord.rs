@@ -0,0 +1,313 @@
+use std::{
+    collections::{HashMap, HashSet},
+    time::{Duration, Instant},
+};
+
+use protocol::{
+    AdminCommand, ChatError, ClientEvent, Message, ServerConfig, ServerEvent, Timestamp, User,
+};
+use tokio::sync::mpsc::{self, error::TrySendError, Receiver, Sender};
+
+use crate::limit::{whole_secs, TokenBucket};
+
+pub type ConnId = usize;
+
+/// Mutes too long to represent are cut down to this, which is forever as far as anyone can tell.
+const MAX_MUTE: Duration = Duration::from_secs(100 * 365 * 24 * 60 * 60);
+
+/// Events sent from the connections to the coordinator.
+pub enum Event {
+    /// A new connection. Server events for it are sent on the channel.
+    Join(ConnId, Sender<ServerEvent>),
+
+    /// The connection sent an event.
+    Client(ConnId, ClientEvent),
+
+    /// The connection has gone away.
+    Quit(ConnId),
+}
+
+/// Starts the coordinator, which owns the state of every connection and fans messages out to
+/// them. Connections talk to it through the returned channel. Dropping a connection's sender is
+/// how the coordinator tells it to hang up.
+pub fn start(config: &ServerConfig) -> Sender<Event> {
+    let (tx, rx) = mpsc::channel(1024);
+    tokio::spawn(Coordinator::new(config).receive_events(rx));
+    tx
+}
+
+struct Peer {
+    user: Option<User>,
+    tx: Sender<ServerEvent>,
+    /// Whether the user has proven they are an operator on this connection.
+    operator: bool,
+}
+
+/// Standing is what the server remembers about a user, by name, across their connections.
+struct Standing {
+    muted_until: Option<Instant>,
+    /// The rate limit belongs to the name, so reconnecting does not refill it.
+    bucket: TokenBucket,
+}
+
+struct Coordinator {
+    peers: HashMap<ConnId, Peer>,
+    standings: HashMap<String, Standing>,
+    banned: HashSet<String>,
+    /// Each operator's secret, by name.
+    operators: HashMap<String, String>,
+    rate_limit: f64,
+    rate_burst: u32,
+    max_message_len: usize,
+}
+
+impl Coordinator {
+    fn new(config: &ServerConfig) -> Self {
+        Self {
+            peers: HashMap::new(),
+            standings: HashMap::new(),
+            banned: HashSet::new(),
+            operators: config
+                .operators
+                .iter()
+                .map(|op| (op.name.clone(), op.secret.clone()))
+                .collect(),
+            rate_limit: config.rate_limit,
+            rate_burst: config.rate_burst,
+            max_message_len: config.max_message_len,
+        }
+    }
+
+    async fn receive_events(mut self, mut rx: Receiver<Event>) {
+        while let Some(event) = rx.recv().await {
+            match event {
+                Event::Join(id, tx) => {
+                    let peer = Peer {
+                        user: None,
+                        tx,
+                        operator: false,
+                    };
+                    self.peers.insert(id, peer);
+                }
+                Event::Client(id, ClientEvent::Ident(user)) => self.ident(id, user),
+                Event::Client(id, ClientEvent::Message(message)) => self.message(id, message),
+                Event::Client(id, ClientEvent::Operator(secret)) => self.operator(id, &secret),
+                Event::Client(id, ClientEvent::Admin(command)) => self.admin(id, command),
+                Event::Quit(id) => self.remove(id),
+            }
+        }
+    }
+
+    /// Identifies the connection as `user`. A connection only gets one name, and only one
+    /// connection may have a given name, so nobody can take over the name (and with it the mute or
+    /// rate limit) of someone who is here.
+    fn ident(&mut self, id: ConnId, user: User) {
+        let Some(peer) = self.peers.get(&id) else {
+            return;
+        };
+        if peer.user.is_some() {
+            self.send(id, ServerEvent::Error(ChatError::AlreadyIdentified));
+            return;
+        }
+        if self.banned.contains(&user.name) {
+            self.send(id, ServerEvent::Error(ChatError::Banned));
+            self.peers.remove(&id);
+            return;
+        }
+        if self.is_present(&user.name) {
+            self.send(id, ServerEvent::Error(ChatError::NameTaken(user.name)));
+            self.peers.remove(&id);
+            return;
+        }
+        // tell the new user who is already here.
+        let present: Vec<User> = self
+            .peers
+            .iter()
+            .filter(|(other, _)| **other != id)
+            .filter_map(|(_, p)| p.user.clone())
+            .collect();
+        for other in present {
+            self.send(id, ServerEvent::Joined(other));
+        }
+        self.broadcast(id, ServerEvent::Joined(user.clone()));
+        if let Some(peer) = self.peers.get_mut(&id) {
+            peer.user = Some(user);
+        }
+    }
+
+    fn message(&mut self, id: ConnId, message: Message) {
+        // messages are only accepted from clients that have identified themselves, and the
+        // sender is always the identified user.
+        let Some(from) = self.peers.get(&id).and_then(|p| p.user.clone()) else {
+            return;
+        };
+        if let Err(err) = self.check_message(&from, &message.text) {
+            self.send(id, ServerEvent::Error(err));
+            return;
+        }
+        let event = ServerEvent::Message(Message {
+            from,
+            text: message.text,
+            time: Timestamp::default(),
+        });
+        self.broadcast(id, event);
+        self.send(id, ServerEvent::Accepted);
+    }
+
+    /// Checks that `from` may send `text` right now, using up some of their rate limit if so.
+    fn check_message(&mut self, from: &User, text: &str) -> Result<(), ChatError> {
+        let now = Instant::now();
+        let standing = self.standing(&from.name);
+        if let Some(until) = standing.muted_until.filter(|until| *until > now) {
+            return Err(ChatError::Muted(whole_secs(until - now)));
+        }
+        if text.len() > self.max_message_len {
+            return Err(ChatError::MessageTooLong(self.max_message_len));
+        }
+        self.take_token(&from.name)
+    }
+
+    /// Uses up some of the user's rate limit, which messages, secrets and commands all share.
+    fn take_token(&mut self, name: &str) -> Result<(), ChatError> {
+        if !self.standing(name).bucket.take(Instant::now()) {
+            return Err(ChatError::RateLimited);
+        }
+        Ok(())
+    }
+
+    /// Makes the connection an operator if `secret` is the one configured for its user. Secrets
+    /// come out of the rate limit so they cannot be guessed quickly.
+    fn operator(&mut self, id: ConnId, secret: &str) {
+        let Some(user) = self.peers.get(&id).and_then(|p| p.user.clone()) else {
+            return;
+        };
+        if let Err(err) = self.take_token(&user.name) {
+            self.send(id, ServerEvent::Error(err));
+            return;
+        }
+        if self.operators.get(&user.name).map(String::as_str) != Some(secret) {
+            self.send(id, ServerEvent::Error(ChatError::NotOperator));
+            return;
+        }
+        if let Some(peer) = self.peers.get_mut(&id) {
+            peer.operator = true;
+        }
+        self.send(id, ServerEvent::Accepted);
+    }
+
+    fn admin(&mut self, id: ConnId, command: AdminCommand) {
+        let Some(peer) = self.peers.get(&id) else {
+            return;
+        };
+        let (Some(operator), is_operator) = (peer.user.clone(), peer.operator) else {
+            return;
+        };
+        if let Err(err) = self.take_token(&operator.name) {
+            self.send(id, ServerEvent::Error(err));
+            return;
+        }
+        if !is_operator {
+            self.send(id, ServerEvent::Error(ChatError::NotOperator));
+            return;
+        }
+        let res = match command {
+            AdminCommand::Kick(name) => {
+                let kicked = ChatError::Kicked(operator.name);
+                self.disconnect(&name, kicked)
+            }
+            AdminCommand::Mute(name, secs) => self.mute(&name, Duration::from_secs(secs)),
+            AdminCommand::Ban(name) => {
+                self.banned.insert(name.clone());
+                // a ban also applies to users who are not here right now.
+                let _ = self.disconnect(&name, ChatError::Banned);
+                Ok(())
+            }
+        };
+        match res {
+            Ok(()) => self.send(id, ServerEvent::Accepted),
+            Err(err) => self.send(id, ServerEvent::Error(err)),
+        }
+    }
+
+    fn mute(&mut self, name: &str, duration: Duration) -> Result<(), ChatError> {
+        if !self.is_present(name) {
+            return Err(ChatError::NoSuchUser(name.to_string()));
+        }
+        let now = Instant::now();
+        let until = now.checked_add(duration).unwrap_or_else(|| now + MAX_MUTE);
+        self.standing(name).muted_until = Some(until);
+        Ok(())
+    }
+
+    /// Tells every connection of the named user why they are being removed and then hangs up on
+    /// them.
+    fn disconnect(&mut self, name: &str, why: ChatError) -> Result<(), ChatError> {
+        let ids: Vec<ConnId> = self
+            .peers
+            .iter()
+            .filter(|(_, p)| p.user.as_ref().is_some_and(|u| u.name == name))
+            .map(|(id, _)| *id)
+            .collect();
+        if ids.is_empty() {
+            return Err(ChatError::NoSuchUser(name.to_string()));
+        }
+        for id in ids {
+            self.send(id, ServerEvent::Error(why.clone()));
+            self.remove(id);
+        }
+        Ok(())
+    }
+
+    /// Forgets the connection, which closes it, and tells everyone else that its user left.
+    fn remove(&mut self, id: ConnId) {
+        let Some(peer) = self.peers.remove(&id) else {
+            return;
+        };
+        if let Some(user) = peer.user {
+            self.broadcast(id, ServerEvent::Left(user));
+        }
+    }
+
+    fn is_present(&self, name: &str) -> bool {
+        self.peers
+            .values()
+            .any(|p| p.user.as_ref().is_some_and(|u| u.name == name))
+    }
+
+    fn standing(&mut self, name: &str) -> &mut Standing {
+        let (rate, burst) = (self.rate_limit, self.rate_burst);
+        self.standings
+            .entry(name.to_string())
+            .or_insert_with(|| Standing {
+                muted_until: None,
+                bucket: TokenBucket::new(rate, burst, Instant::now()),
+            })
+    }
+
+    /// Queues the event for the connection without waiting. A client that has stopped reading
+    /// would otherwise hold up everyone else, so once its queue is full it is hung up on.
+    fn send(&mut self, id: ConnId, event: ServerEvent) {
+        let Some(peer) = self.peers.get(&id) else {
+            return;
+        };
+        match peer.tx.try_send(event) {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) => self.remove(id),
+            // the connection is already gone and its `Quit` is on the way.
+            Err(TrySendError::Closed(_)) => {}
+        }
+    }
+
+    /// Sends the event to every identified peer except `from`.
+    fn broadcast(&mut self, from: ConnId, event: ServerEvent) {
+        let others: Vec<ConnId> = self
+            .peers
+            .iter()
+            .filter(|(id, peer)| **id != from && peer.user.is_some())
+            .map(|(id, _)| *id)
+            .collect();
+        for id in others {
+            self.send(id, event.clone());
+        }
+    }
+}
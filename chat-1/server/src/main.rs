@@ -0,0 +1,164 @@
+mod coord;
+mod limit;
+
+use anyhow::Result;
+use protocol::{
+    prelude::*,
+    tls::{self, BoxedStream, TlsAcceptor},
+    ClientEvent, ServerEvent,
+};
+use std::{io, process};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader, BufWriter, ReadHalf, WriteHalf},
+    net::{TcpListener, TcpStream},
+    sync::mpsc::{self, Receiver, Sender},
+};
+
+use coord::{ConnId, Event};
+
+#[tokio::main]
+async fn main() {
+    let config = protocol::ServerConfig::parse();
+    if let Err(err) = Server::start(config).await {
+        eprintln!("{err:?}");
+        process::exit(1);
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+enum ServerError {
+    #[error("could not bind to {0}: {1}")]
+    BindFailure(String, io::Error),
+
+    #[error("accept failure: {0}")]
+    AcceptFailure(io::Error),
+}
+
+struct Server {
+    config: protocol::ServerConfig,
+}
+
+impl Server {
+    async fn start(config: protocol::ServerConfig) -> Result<()> {
+        let mut server = Self::new(config);
+        server.run().await
+    }
+
+    fn new(config: protocol::ServerConfig) -> Self {
+        Self { config }
+    }
+
+    async fn run(&mut self) -> Result<()> {
+        let acceptor = match (&self.config.tls_cert, &self.config.tls_key) {
+            (Some(cert), Some(key)) => Some(tls::acceptor(cert, key)?),
+            _ => None,
+        };
+        let addr = &self.config.addr;
+        let listener = TcpListener::bind(addr)
+            .await
+            .map_err(|e| ServerError::BindFailure(addr.into(), e))?;
+        let coord = coord::start(&self.config);
+        for id in 0.. {
+            let (stream, peer) = listener
+                .accept()
+                .await
+                .map_err(ServerError::AcceptFailure)?;
+            let acceptor = acceptor.clone();
+            let coord = coord.clone();
+            let max_line_len = max_line_len(&self.config);
+            tokio::spawn(async move {
+                if let Err(err) = handle_conn(id, stream, acceptor, coord, max_line_len).await {
+                    eprintln!("connection from {peer} failed: {err:?}");
+                }
+            });
+        }
+        Ok(())
+    }
+}
+
+/// The longest line a client may send before it is disconnected. A message's text can take up to
+/// six times its length once escaped as JSON (`\u0000`), and the rest of the event is small.
+fn max_line_len(config: &protocol::ServerConfig) -> u64 {
+    (config.max_message_len as u64).saturating_mul(6) + 1024
+}
+
+/// Completes the TLS handshake if needed, registers the connection with the coordinator and then
+/// shuttles events between the two until either side goes away.
+async fn handle_conn(
+    id: ConnId,
+    stream: TcpStream,
+    acceptor: Option<TlsAcceptor>,
+    coord: Sender<Event>,
+    max_line_len: u64,
+) -> Result<()> {
+    let stream: BoxedStream = match acceptor {
+        Some(acceptor) => Box::new(acceptor.accept(stream).await?),
+        None => Box::new(stream),
+    };
+    let (client_rx, client_tx) = tokio::io::split(stream);
+    let (tx, rx) = mpsc::channel(1024);
+    coord.send(Event::Join(id, tx)).await?;
+    let mut writer = tokio::spawn(write_client(rx, BufWriter::new(client_tx)));
+    // the writer finishes when the coordinator hangs up on the client, e.g. after a kick, and
+    // then there is no point reading anything else it sends.
+    let res = tokio::select! {
+        res = read_client(id, BufReader::new(client_rx), &coord, max_line_len) => res,
+        _ = &mut writer => Ok(()),
+    };
+    let _ = coord.send(Event::Quit(id)).await;
+    res
+}
+
+async fn read_client(
+    id: ConnId,
+    mut reader: BufReader<ReadHalf<BoxedStream>>,
+    coord: &Sender<Event>,
+    max_line_len: u64,
+) -> Result<()> {
+    loop {
+        let mut buf = String::new();
+        // stop reading at the limit rather than buffering whatever the client sends.
+        let read = (&mut reader).take(max_line_len).read_line(&mut buf).await?;
+        if read == 0 {
+            return Ok(());
+        }
+        if !buf.ends_with('\n') && read as u64 == max_line_len {
+            anyhow::bail!("line longer than {max_line_len} bytes");
+        }
+        let buf = buf.trim();
+        if buf.is_empty() {
+            continue;
+        }
+        let event = serde_json::from_str::<ClientEvent>(buf)?;
+        coord.send(Event::Client(id, event)).await?;
+    }
+}
+
+async fn write_client(
+    mut rx: Receiver<ServerEvent>,
+    mut writer: BufWriter<WriteHalf<BoxedStream>>,
+) -> Result<()> {
+    while let Some(event) = rx.recv().await {
+        let event = serde_json::to_string(&event)?;
+        writer.write_all(event.as_bytes()).await?;
+        writer.write_all("\n".as_bytes()).await?;
+        writer.flush().await?;
+    }
+    writer.shutdown().await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_harness() {
+        protocol::verify_server(Server::start).await;
+    }
+
+    #[tokio::test]
+    async fn test_harness_tls() {
+        protocol::verify_server_tls(Server::start).await;
+    }
+}
@@ -1,13 +1,18 @@
 use std::error::Error;
 use std::fmt::{Debug, Display};
-use std::fs::{self, File};
-use std::io::{BufRead, BufReader};
-use std::path::Path;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::PathBuf;
 
+use clap::Parser;
+
+/// Reads paths like:
+///
 /// one/two
 /// one/three/four
 /// five/six
-/// and have it render the tree visually:
+///
+/// and renders the tree visually:
 ///
 /// ├─ one
 /// │  ├─ two
@@ -15,54 +20,74 @@ use std::path::Path;
 /// │     └─ four
 /// └─ five
 ///    └─ six
-fn main() -> Result<(), Box<dyn Error>> {
-    render_samples()?;
-    render_pwd(Path::new("."))
-}
+#[derive(Parser, Debug)]
+struct Args {
+    /// Files to read paths from; stdin is read if there are none or for `-`
+    files: Vec<PathBuf>,
 
-fn render_pwd(dir: &Path) -> Result<(), Box<dyn Error>> {
-    let mut tree = Tree::new();
-    walk_dir(dir, &mut tree)?;
-    println!("\nPWD:\n");
-    tree.print(0, false);
-    Ok(())
+    /// Paths are separated by NUL instead of newlines, like `find -print0`
+    #[arg(short = '0', long, default_value_t = false)]
+    null: bool,
+
+    /// Sort each directory's children instead of keeping them in input order
+    #[arg(short, long, default_value_t = false)]
+    sort: bool,
+
+    /// Show chains of directories that each only hold another directory on one
+    /// line, like `a/b/c`
+    #[arg(short, long, default_value_t = false)]
+    collapse: bool,
 }
 
-fn walk_dir(dir: &Path, tree: &mut Tree<String>) -> Result<(), Box<dyn Error>> {
-    for entry in fs::read_dir(dir)? {
-        let entry = entry?;
-        let path = entry.path();
-        if path.is_dir() {
-            walk_dir(&path, tree)?;
+fn main() -> Result<(), Box<dyn Error>> {
+    let args = Args::parse();
+    let separator = if args.null { b'\0' } else { b'\n' };
+    let mut tree = Tree::new();
+    if args.files.is_empty() {
+        read_paths(io::stdin().lock(), separator, &mut tree)?;
+    }
+    for file_name in &args.files {
+        if file_name.as_os_str() == "-" {
+            read_paths(io::stdin().lock(), separator, &mut tree)?;
         } else {
-            let path = path.to_str().unwrap();
-            let path = path.to_string();
-            let lines: Vec<String> = path.split('/').map(|f| f.to_string()).collect();
-            tree.add(lines);
+            let file =
+                File::open(file_name).map_err(|e| format!("{}: {e}", file_name.display()))?;
+            read_paths(BufReader::new(file), separator, &mut tree)?;
         }
     }
-    Ok(())
-}
-
-fn render_samples() -> Result<(), Box<dyn Error>> {
-    let file_names = ["simple.txt", "input.txt"];
-    for file_name in file_names {
-        render(file_name)?;
+    if args.sort {
+        tree.sort();
     }
+    let mut out = BufWriter::new(io::stdout().lock());
+    tree.print(&mut out, &mut vec![], args.collapse)?;
+    out.flush()?;
     Ok(())
 }
 
-fn render(file_name: &str) -> Result<(), Box<dyn Error>> {
-    let file = File::open(file_name)?;
-    let file = BufReader::new(file);
-    let mut tree = Tree::new();
-    for line in file.lines() {
-        let line = line?;
-        let lines: Vec<String> = line.split('/').map(|f| f.to_string()).collect();
-        tree.add(lines);
+fn read_paths(
+    reader: impl BufRead,
+    separator: u8,
+    tree: &mut Tree<String>,
+) -> Result<(), Box<dyn Error>> {
+    for path in reader.split(separator) {
+        let path = path?;
+        let path = String::from_utf8_lossy(&path);
+        let path = path.strip_suffix('\r').unwrap_or(&path);
+        if path.is_empty() {
+            continue;
+        }
+        let mut parts = vec![];
+        // keep absolute paths apart from relative ones with the same names
+        if path.starts_with('/') {
+            parts.push("/".to_string());
+        }
+        parts.extend(
+            path.split('/')
+                .filter(|part| !part.is_empty())
+                .map(ToString::to_string),
+        );
+        tree.add(parts);
     }
-    println!("\n{file_name}:\n");
-    tree.print(0, false);
     Ok(())
 }
 
@@ -74,7 +99,7 @@ struct Tree<T> {
 
 impl<T> Tree<T>
 where
-    T: PartialEq<T> + Debug + Display,
+    T: Ord + Debug + Display,
 {
     fn new() -> Self {
         Self {
@@ -82,6 +107,7 @@ where
             children: vec![],
         }
     }
+
     fn add(&mut self, parts: Vec<T>) {
         let mut tree = self;
         for part in parts {
@@ -105,31 +131,113 @@ where
             }
         }
     }
-    fn print(&self, indent: usize, mut root_last: bool) {
-        self.children.iter().enumerate().for_each(|(idx, tree)| {
+
+    fn sort(&mut self) {
+        self.children.sort_by(|a, b| a.val.cmp(&b.val));
+        for child in &mut self.children {
+            child.sort();
+        }
+    }
+
+    /// Prints the children of this tree. `lasts` holds whether each ancestor
+    /// was the last of its siblings, which decides if its guide line continues.
+    fn print(&self, out: &mut impl Write, lasts: &mut Vec<bool>, collapse: bool) -> io::Result<()> {
+        for (idx, tree) in self.children.iter().enumerate() {
             let is_last = idx == self.children.len() - 1;
-            if indent == 0 {
-                if is_last {
-                    print!("└─")
+            for last in lasts.iter() {
+                if *last {
+                    write!(out, "   ")?;
                 } else {
-                    print!("├─");
+                    write!(out, "│  ")?;
                 }
+            }
+            if is_last {
+                write!(out, "└─")?;
             } else {
-                // we are in a subtree
-                if root_last {
-                    print!("   {}", "   ".repeat(indent - 1));
-                } else {
-                    print!("│  {}", "   ".repeat(indent - 1));
-                }
-                if is_last {
-                    print!("└─")
-                } else {
-                    print!("├─");
-                }
+                write!(out, "├─")?;
             }
-            println!(" {}", tree.val.iter().next().unwrap());
-            root_last = root_last || (is_last && indent == 0);
-            tree.print(indent + 1, root_last);
-        });
+            let (label, tree) = if collapse {
+                tree.collapsed()
+            } else {
+                (tree.label(), tree)
+            };
+            writeln!(out, " {label}")?;
+            lasts.push(is_last);
+            tree.print(out, lasts, collapse)?;
+            lasts.pop();
+        }
+        Ok(())
+    }
+
+    fn label(&self) -> String {
+        self.val
+            .as_ref()
+            .map(ToString::to_string)
+            .unwrap_or_default()
+    }
+
+    /// Follows this tree down while its only child is a directory, returning
+    /// the joined labels and the tree whose children are printed below them.
+    /// A lone leaf stays on its own line so it can still be told apart.
+    fn collapsed(&self) -> (String, &Tree<T>) {
+        let mut label = self.label();
+        let mut tree = self;
+        while let [child] = tree.children.as_slice() {
+            if child.children.is_empty() {
+                break;
+            }
+            if !label.ends_with('/') {
+                label.push('/');
+            }
+            label.push_str(&child.label());
+            tree = child;
+        }
+        (label, tree)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render(paths: &str, collapse: bool) -> String {
+        let mut tree = Tree::new();
+        read_paths(paths.as_bytes(), b'\n', &mut tree).unwrap();
+        let mut out = vec![];
+        tree.print(&mut out, &mut vec![], collapse).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_print_nested_guides() {
+        let paths = "one/two\none/three/five/six\none/three/seven\none/four\neight\n";
+        assert_eq!(
+            render(paths, false),
+            "\
+├─ one
+│  ├─ two
+│  ├─ three
+│  │  ├─ five
+│  │  │  └─ six
+│  │  └─ seven
+│  └─ four
+└─ eight
+"
+        );
+    }
+
+    #[test]
+    fn test_print_collapsed() {
+        let paths = "one/three/five/six\none/three/five/seven\ntwo/four\n";
+        assert_eq!(
+            render(paths, true),
+            "\
+├─ one/three/five
+│  ├─ six
+│  └─ seven
+└─ two
+   └─ four
+"
+        );
     }
 }
@@ -4,68 +4,151 @@ use std::time::{Duration, Instant};
 
 use anyhow::Result;
 use clap::Parser;
-use scheduler_2::scheduler::{Response, Scheduler};
-use tokio::sync::mpsc;
+use scheduler_2::{
+    rules::{Queue, Rule, Rules},
+    scheduler::Scheduler,
+    task::Type,
+};
 
 #[derive(Parser)]
 struct Args {
     #[arg(short = 't', default_value_t = 64)]
     num_task_types: usize,
+
+    /// queue up to this many tasks of each type when it is at capacity instead of rejecting them.
+    #[arg(short = 'q')]
+    queue_len: Option<usize>,
+
+    /// run at most this many tasks at once across all types.
+    #[arg(short = 'm')]
+    max_running: Option<usize>,
+
+    /// flood type 0 with ten times as many tasks as each other type, and show how many tasks of
+    /// each type start, to check that the flood does not starve the others.
+    #[arg(long)]
+    fair: bool,
+
+    /// print the scheduler's stats in the Prometheus text format instead of a summary.
+    #[arg(short = 'p')]
+    prometheus: bool,
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
     let args = Args::parse();
-    let sched = Scheduler::builder().build();
+    if args.fair {
+        return fairness(&args).await;
+    }
+    let rule = Rule {
+        queue: args.queue_len.map(|max_len| Queue {
+            max_len,
+            ..Default::default()
+        }),
+        ..Default::default()
+    };
+    let mut rules = Rules::builder().default(rule);
+    if let Some(max) = args.max_running {
+        rules = rules.max_running(max);
+    }
+    let sched = Scheduler::builder().rules(rules.build()).build();
 
-    let (tx, mut rx) = mpsc::channel::<bool>(args.num_task_types);
-    tokio::spawn(generate(sched.clone(), tx, args.num_task_types));
-    let mut num_scheduled = 0;
-    let mut num_rejected = 0;
+    tokio::spawn(generate(sched.clone(), args.num_task_types));
     let mut interval = tokio::time::interval(Duration::from_millis(50));
     interval.tick().await;
+    let mut last = sched.stats().await?;
     let mut start = Instant::now();
     loop {
-        tokio::select! {
-            _ = interval.tick() => {
-                let dur = Instant::now().duration_since(start);
-                let per_sec = if num_scheduled == 0 {
-                    0.0
-                } else {
-                    f64::from(num_scheduled) / dur.as_secs_f64()
-                };
-                let success_pct = f64::from(num_scheduled)/ (f64::from(num_scheduled) + f64::from(num_rejected)) * 100.0;
-                println!("{success_pct:3.0}%\t{per_sec:.0}/sec\t{num_scheduled} {num_rejected}");
-
-                // reset
-                num_scheduled=0;
-                num_rejected=0;
-                start = Instant::now();
-            }
-            Some(scheduled) = rx.recv() => {
-                if scheduled {
-                    num_scheduled += 1;
-                } else {
-                    num_rejected += 1;
-                }
-            }
+        interval.tick().await;
+        let stats = sched.stats().await?;
+        if args.prometheus {
+            println!("{}", stats.to_prometheus());
+            continue;
         }
+        let dur = Instant::now().duration_since(start);
+        let num_accepted = stats.accepted() - last.accepted();
+        let num_rejected = stats.rejected() - last.rejected();
+        let per_sec = rate(num_accepted, dur);
+        let success_pct = percent(num_accepted, num_accepted + num_rejected);
+        println!("{success_pct:3.0}%\t{per_sec:.0}/sec\t{num_accepted} {num_rejected}");
+
+        last = stats;
+        start = Instant::now();
+    }
+}
+
+/// How many per second `n` in `dur` is.
+#[allow(clippy::cast_precision_loss)]
+fn rate(n: u64, dur: Duration) -> f64 {
+    n as f64 / dur.as_secs_f64()
+}
+
+/// What percentage of `whole` `part` is, or 0 if `whole` is.
+#[allow(clippy::cast_precision_loss)]
+fn percent(part: u64, whole: u64) -> f64 {
+    if whole == 0 {
+        return 0.0;
     }
+    part as f64 / whole as f64 * 100.0
 }
 
-async fn generate(sched: Scheduler, tx: mpsc::Sender<bool>, num_types: usize) -> Result<()> {
+async fn generate(sched: Scheduler, num_types: usize) -> Result<()> {
     let mut names = (0..num_types).map(|i| format!("{i}")).cycle();
     loop {
-        let tx = tx.clone();
-        let tx2 = tx.clone();
         let next = names.next().unwrap();
-        let res = sched
-            .run_task(next, async move {
-                tx.send(true).await.unwrap();
+        sched.run_task(next, async {}).await?;
+    }
+}
+
+async fn fairness(args: &Args) -> Result<()> {
+    let rule = Rule {
+        queue: Some(Queue {
+            max_len: args.queue_len.unwrap_or(Queue::default().max_len),
+            ..Default::default()
+        }),
+        ..Default::default()
+    };
+    let rules = Rules::builder()
+        .default(rule)
+        .max_running(args.max_running.unwrap_or(8))
+        .build();
+    let sched = Scheduler::builder().rules(rules).build();
+
+    tokio::spawn(flood(sched.clone(), args.num_task_types));
+    let mut interval = tokio::time::interval(Duration::from_millis(50));
+    interval.tick().await;
+    let mut last = vec![0; args.num_task_types];
+    loop {
+        interval.tick().await;
+        let stats = sched.stats().await?;
+        // the number of tasks of each type that finished since the last tick.
+        let finished: Vec<u64> = (0..args.num_task_types)
+            .map(|typ| {
+                let count = stats
+                    .types
+                    .get(&Type::new(format!("{typ}")))
+                    .map_or(0, |t| t.durations.count);
+                count - std::mem::replace(&mut last[typ], count)
+            })
+            .collect();
+        let flood = finished[0];
+        let others = &finished[1..];
+        let min = others.iter().min().copied().unwrap_or_default();
+        let max = others.iter().max().copied().unwrap_or_default();
+        println!("flood {flood}\tothers {min}-{max}");
+    }
+}
+
+/// Submits ten tasks of type 0 for every task of each other type. Each task takes a millisecond
+/// so that they pile up in the queues.
+async fn flood(sched: Scheduler, num_types: usize) -> Result<()> {
+    let others = 1..num_types;
+    let types = std::iter::repeat_n(0, 10).chain(others).cycle();
+    for typ in types {
+        sched
+            .run_task(format!("{typ}"), async {
+                tokio::time::sleep(Duration::from_millis(1)).await;
             })
             .await?;
-        if res == Response::Rejected {
-            tx2.send(false).await.unwrap();
-        }
     }
+    Ok(())
 }
@@ -4,25 +4,105 @@ use std::{
     sync::{Arc, Mutex},
 };
 
-type AsyncFuture = Box<dyn Future<Output = ()> + Send + 'static>;
-type WrappedFuture = Arc<Mutex<Option<Pin<AsyncFuture>>>>;
+use crate::hooks::Outcome;
 
-/// Command wraps futures to be executed by the scheduler.
-pub(crate) struct Command(WrappedFuture);
+/// One try at running a command. It resolves to how the try ended.
+pub(crate) type Attempt = Pin<Box<dyn Future<Output = Outcome> + Send + 'static>>;
+
+type MakeAttempt = Box<dyn FnMut() -> Option<Attempt> + Send + 'static>;
+
+type CancelFn = Box<dyn Fn() + Send + Sync + 'static>;
+
+/// Command wraps futures to be executed by the scheduler. A command made from a single future can
+/// only be attempted once, while a retryable command makes a fresh future for every attempt.
+pub(crate) struct Command {
+    /// behind a mutex so that commands can be shared between threads.
+    attempt: Mutex<MakeAttempt>,
+    retryable: bool,
+    /// stops the running attempt, if the command can be cancelled.
+    cancel: Option<CancelFn>,
+}
 
 impl Command {
     pub(crate) fn new<F>(f: F) -> Self
     where
         F: Future<Output = ()> + Send + 'static,
     {
-        let future: WrappedFuture = Arc::new(Mutex::new(Some(Box::pin(f))));
-        Self(future)
+        Self::once(Box::pin(async move {
+            f.await;
+            Outcome::Success
+        }))
+    }
+
+    /// Makes a command from its only attempt.
+    pub(crate) fn once(attempt: Attempt) -> Self {
+        let mut attempt = Some(attempt);
+        let attempt = move || attempt.take();
+        Self {
+            attempt: Mutex::new(Box::new(attempt)),
+            retryable: false,
+            cancel: None,
+        }
+    }
+
+    pub(crate) fn retryable<F>(mut f: F) -> Self
+    where
+        F: FnMut() -> Attempt + Send + 'static,
+    {
+        Self {
+            attempt: Mutex::new(Box::new(move || Some(f()))),
+            retryable: true,
+            cancel: None,
+        }
+    }
+
+    /// Returns the next attempt at the command, or `None` if it cannot be attempted again.
+    pub(crate) fn attempt(&mut self) -> Option<Attempt> {
+        (self.attempt.get_mut().unwrap())()
+    }
+
+    pub(crate) fn is_retryable(&self) -> bool {
+        self.retryable
+    }
+
+    /// Sets how to cancel the command. Once cancelled, its running attempt resolves at its next
+    /// await point and it makes no more attempts.
+    pub(crate) fn with_cancel<F>(mut self, cancel: F) -> Self
+    where
+        F: Fn() + Send + Sync + 'static,
+    {
+        self.cancel = Some(Box::new(cancel));
+        self
+    }
+
+    /// Cancels the command. Returns false if the command cannot be cancelled.
+    pub(crate) fn cancel(&self) -> bool {
+        match &self.cancel {
+            Some(cancel) => {
+                cancel();
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+type MakeCommand = Arc<dyn Fn() -> Command + Send + Sync + 'static>;
+
+/// Factory makes a fresh `Command` each time a recurring task is due.
+#[derive(Clone)]
+pub(crate) struct Factory(MakeCommand);
+
+impl Factory {
+    pub(crate) fn new<F, Fut>(f: F) -> Self
+    where
+        F: Fn() -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        Self(Arc::new(move || Command::new(f())))
     }
 
-    /// Runs the composed future by first taking ownership of the future and then
-    /// awaiting it.
-    pub(crate) async fn run(&mut self) {
-        let fut = { self.0.lock().unwrap().take() };
-        fut.unwrap().await;
+    pub(crate) fn make(&self) -> Command {
+        (self.0)()
     }
 }
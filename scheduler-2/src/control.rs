@@ -1,13 +1,30 @@
-use std::collections::HashMap;
+use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, BinaryHeap, HashMap, VecDeque},
+    sync::Arc,
+    time::Duration,
+};
+
+use chrono::{DateTime, Local};
 
 use crate::{
-    command::Command,
-    hooks::{self, Callback},
-    rules::Rules,
-    scheduler::{Request, Response, TaskRequest, WaitRequest},
+    command::{Attempt, Command, Factory},
+    hooks::{self, Callback, FailurePolicy, Outcome, TaskContext},
+    limit::TokenBucket,
+    queue::Queues,
+    rules::{Missed, Retry, Rules},
+    scheduler::{
+        RegisterRequest, Request, Response, ShutdownRequest, StatsRequest, Summary, TaskRequest,
+        UpdateRulesRequest, WaitRequest,
+    },
+    stats::{Stats, TypeStats},
+    store::{JobId, JobState, Kinds, Store, StoreErrors},
     task,
 };
-use tokio::sync::mpsc;
+use tokio::{
+    sync::{mpsc, oneshot, watch},
+    time::{self, Instant},
+};
 
 /// Control is the main synchronization point for running tasks. It receives requests from the
 /// scheduler on a channel and then decides what to do with those requests.
@@ -18,11 +35,70 @@ pub(crate) struct Control {
     hooks: hooks::Hooks,
     rules: Rules,
     running: HashMap<task::Type, usize>,
+    /// The token buckets for the types whose rule has a rate.
+    buckets: HashMap<task::Type, TokenBucket>,
+    /// Tasks waiting for a free slot, for types whose rule has a queue.
+    queues: Queues,
+    /// Recovered jobs waiting for a free slot, oldest first. They were accepted before the
+    /// restart, so they wait for as long as it takes whatever their type's rule says.
+    recovering: VecDeque<(TaskContext, Command)>,
+    /// Factories for the recurring task types.
+    factories: HashMap<task::Type, Factory>,
+    /// When each recurring task type is next due, soonest first.
+    deadlines: BinaryHeap<Reverse<(Instant, task::Type)>>,
+    /// The recurring task types that run on a cron schedule.
+    crons: HashMap<task::Type, CronState>,
+    /// Tells the local wall-clock time, for cron schedules.
+    clock: fn() -> DateTime<Local>,
+    /// Tells the running tasks to stop once the shutdown grace period is over.
+    abort_tx: watch::Sender<bool>,
+    /// Set once the scheduler has started shutting down.
+    stopping: Option<Stopping>,
+    /// The counters for `Scheduler::stats`. The running and queued counts are filled in when a
+    /// snapshot is taken.
+    stats: BTreeMap<task::Type, TypeStats>,
+    /// The id for the next task.
+    next_id: u64,
+    /// Where stored jobs are kept, and how to make the commands for the ones left from before.
+    store: Arc<dyn Store>,
+    kinds: Kinds,
+    /// Errors from the store met by the commands for stored jobs, which run outside of Control.
+    store_errors_tx: StoreErrors,
+    store_errors_rx: mpsc::Receiver<anyhow::Error>,
+    /// Set once the unfinished jobs have been recovered, so new jobs are not mistaken for them.
+    recovered: watch::Sender<bool>,
+}
+
+/// `CronState` tracks a task type that runs on a cron schedule.
+struct CronState {
+    /// When the schedule was last checked. Runs due after this have not been seen yet.
+    checked: DateTime<Local>,
+    /// Missed runs that are still to start, with `Missed::CatchUp`.
+    owed: usize,
+}
+
+/// Stopping tracks a shutdown until every task has stopped.
+#[derive(Default)]
+struct Stopping {
+    /// When the tasks that are still running get cancelled, unless that has happened already.
+    abort_at: Option<Instant>,
+    summary: Summary,
+    /// Everyone who asked for the shutdown, to be sent the summary.
+    waiters: Vec<oneshot::Sender<Summary>>,
 }
 
 impl Control {
-    pub(crate) fn new(rx: mpsc::Receiver<Request>, hooks: hooks::Hooks, rules: Rules) -> Self {
+    pub(crate) fn new(
+        rx: mpsc::Receiver<Request>,
+        hooks: hooks::Hooks,
+        rules: Rules,
+        store: Arc<dyn Store>,
+        kinds: Kinds,
+        recovered: watch::Sender<bool>,
+        clock: fn() -> DateTime<Local>,
+    ) -> Self {
         let (res_tx, res_rx) = mpsc::channel(1024);
+        let (store_errors_tx, store_errors_rx) = mpsc::channel(1024);
         Self {
             rx,
             res_tx,
@@ -30,63 +106,99 @@ impl Control {
             hooks,
             rules,
             running: HashMap::default(),
+            buckets: HashMap::default(),
+            queues: Queues::default(),
+            recovering: VecDeque::default(),
+            factories: HashMap::default(),
+            deadlines: BinaryHeap::default(),
+            crons: HashMap::default(),
+            clock,
+            abort_tx: watch::channel(false).0,
+            stopping: None,
+            stats: BTreeMap::default(),
+            next_id: 0,
+            store,
+            kinds,
+            store_errors_tx,
+            store_errors_rx,
+            recovered,
         }
     }
-    /// The main loop of the Controller.
+
+    /// Where the commands for stored jobs should send the errors they get from the store.
+    pub(crate) fn store_errors(&self) -> StoreErrors {
+        self.store_errors_tx.clone()
+    }
+
+    /// The main loop of the Controller. It returns once the scheduler has shut down and every task
+    /// has stopped.
     pub(crate) async fn run(&mut self) {
         let mut wait: Option<WaitRequest> = None;
+        let mut closed = false;
+        self.recover().await;
+        let _ = self.recovered.send(true);
         loop {
-            // if we are waiting and there are no more tasks running, then complete the wait by
-            // transmitting on the channel and replacing the option.
-            if wait.is_some() && self.total_running() == 0 {
+            // if we are waiting and there are no more tasks running or queued, then complete the
+            // wait by transmitting on the channel and replacing the option.
+            if wait.is_some() && self.is_idle() {
                 let wr = wait.take().unwrap();
                 let _ = wr.tx.send(Response::Accepted);
             }
+            // a shutdown with a grace period has already dropped whatever was waiting, and one
+            // without lets it start and finish first.
+            if self.stopping.is_some() && self.is_idle() {
+                self.stopped();
+                return;
+            }
+            let abort_at = self.stopping.as_ref().and_then(|s| s.abort_at);
+            let next_deadline = self.deadlines.peek().map(|Reverse((at, _))| *at);
+            let next_token = self.next_token();
+            let next_expiry = self.queues.next_expiry(&self.rules);
             // After we're done with bookkeeping, enter the select.
             tokio::select! {
-                Some(res) = self.res_rx.recv() => {
-                    match res {
-                        RunResult::Finished(typ) => {
-                            self.task_finished(&typ);
-
-                            // invoke the hook letting us know that the task has finished.
-                            let hook_res = &self.hooks.on_task_complete(&typ).await;
-                            if let Err(e) = hook_res {
-                                println!("Error in hook.on_task_complete: {e:?}");
-                            }
-                        }
+                () = time::sleep_until(next_expiry.unwrap_or_else(Instant::now)), if next_expiry.is_some() => {
+                    // queued tasks that have waited too long give up.
+                    self.expire_queued().await;
+                }
+                () = time::sleep_until(next_token.unwrap_or_else(Instant::now)), if next_token.is_some() => {
+                    // a rate limited type that has tasks waiting can start another.
+                    self.dispatch_queued().await;
+                    self.run_owed().await;
+                }
+                () = time::sleep_until(next_deadline.unwrap_or_else(Instant::now)), if next_deadline.is_some() => {
+                    // recurring tasks do not run while we are waiting, just like new ones.
+                    self.run_due(wait.is_none()).await;
+                }
+                () = time::sleep_until(abort_at.unwrap_or_else(Instant::now)), if abort_at.is_some() => {
+                    // the grace period is over, so cancel whatever is still running.
+                    let _ = self.abort_tx.send(true);
+                    if let Some(stopping) = &mut self.stopping {
+                        stopping.abort_at = None;
                     }
                 }
-                Some(req) = self.rx.recv() => {
+                Some(res) = self.res_rx.recv() => self.result(res).await,
+                Some(err) = self.store_errors_rx.recv() => self.hooks.on_store_error(&err).await,
+                req = self.rx.recv(), if !closed => {
+                    let Some(req) = req else {
+                        // every scheduler has been dropped, so nothing can be submitted any more.
+                        // let the tasks we already have finish.
+                        closed = true;
+                        self.stop(None);
+                        continue;
+                    };
                     match req {
-                        Request::Task(TaskRequest{typ, cmd, tx}) => {
+                        Request::Task(req) => {
                             // if we are waiting, that means no more tasks should be scheduled
                             // until the wait is complete.
-                            if wait.is_some() {
-                                let _ = tx.send(Response::Rejected);
-                                continue;
-                            }
-                            // otherwise, try and run the task if we are able to.
-                            if !self.try_run(&typ) {
+                            let refuse = wait.is_some() || self.stopping.is_some();
+                            self.submit(req, refuse).await;
+                        }
+                        Request::Register(RegisterRequest{typ, factory, tx}) => {
+                            if self.stopping.is_some() {
                                 let _ = tx.send(Response::Rejected);
                                 continue;
                             }
-                            let res_tx = self.res_tx.clone();
-                            let task_typ = typ.clone();
-
-                            // if we accepted, invoke the hook if it exists. we will block
-                            // the scheduler until the hook is completed so that we can
-                            // ensure consistency.
-                            let hook_res = &self.hooks.on_task_start(&typ).await;
-                            if let Err(e) = hook_res {
-                                println!("Error in hook: {e:?}");
-                            }
-                            // finally, spawn the task and send the accepted response.
-                            tokio::spawn(async move {
-                                let mut runner = Runner::new(task_typ, cmd, res_tx);
-                                runner.run().await;
-                            });
-                            let _ = tx.send(Response::Accepted);
+                            let _ = tx.send(self.register(typ, factory));
                         }
                         Request::Wait(wr) => {
                             if wait.is_some() {
@@ -95,15 +207,603 @@ impl Control {
                                 wait = Some(wr);
                             }
                         }
+                        Request::UpdateRules(UpdateRulesRequest{rules, tx}) => {
+                            let _ = tx.send(self.update_rules(rules).await);
+                        }
+                        Request::Stats(StatsRequest{tx}) => {
+                            let _ = tx.send(self.snapshot());
+                        }
+                        Request::Shutdown(ShutdownRequest{grace, tx}) => {
+                            self.stop(Some(grace));
+                            if let Some(stopping) = &mut self.stopping {
+                                stopping.waiters.push(tx);
+                            }
+                        }
                     }
                 }
             }
         }
     }
+    /// Handles news from a running task.
+    async fn result(&mut self, res: RunResult) {
+        match res {
+            RunResult::Finished {
+                ctx,
+                outcome,
+                elapsed,
+                aborted,
+            } => {
+                self.task_finished(&ctx.typ);
+                self.type_stats(&ctx.typ).durations.observe(elapsed);
+                self.job_finished(&ctx, &outcome, aborted).await;
+                if let Some(stopping) = &mut self.stopping {
+                    if aborted {
+                        stopping.summary.aborted += 1;
+                    } else {
+                        stopping.summary.completed += 1;
+                    }
+                }
+
+                // invoke the hook letting us know that the task has finished.
+                if let Err(e) = self.hooks.on_task_complete(&ctx, &outcome).await {
+                    self.hook_failed("on_task_complete", &e).await;
+                }
+
+                // a slot is free, so start the next tasks waiting for one.
+                self.dispatch_queued().await;
+                self.run_owed().await;
+            }
+            RunResult::TimedOut(ctx) => {
+                if let Err(e) = self.hooks.on_task_timeout(&ctx).await {
+                    self.hook_failed("on_task_timeout", &e).await;
+                }
+            }
+            RunResult::Retrying(ctx, attempt, delay) => {
+                if let Err(e) = self.hooks.on_task_retry(&ctx, attempt, delay).await {
+                    self.hook_failed("on_task_retry", &e).await;
+                }
+            }
+        }
+    }
+
+    /// Runs the task if we are able to, or queues it if the rule allows it, and responds with
+    /// which it was. If `refuse` is set, the task is rejected outright.
+    async fn submit(&mut self, req: TaskRequest, refuse: bool) {
+        let TaskRequest {
+            typ,
+            priority,
+            cmd,
+            tx,
+            submitted,
+            job,
+        } = req;
+        let ctx = self.context(typ, submitted, job);
+        let res = if refuse {
+            self.rejected(&ctx).await;
+            self.turned_away(&ctx).await;
+            Response::Rejected
+        } else if self.try_run(&ctx.typ) {
+            if self.start_submitted(ctx, cmd).await {
+                Response::Accepted
+            } else {
+                Response::Rejected
+            }
+        } else {
+            self.enqueue(priority, ctx, cmd).await
+        };
+        let _ = tx.send(res);
+    }
+
+    /// Starts a submitted or recovered task which has already been counted as running by
+    /// `try_run`, and counts it as accepted. Returns false if its start hook failed, and the task
+    /// is counted as rejected instead.
+    async fn start_submitted(&mut self, ctx: TaskContext, cmd: Command) -> bool {
+        if self.start(ctx.clone(), cmd).await {
+            self.type_stats(&ctx.typ).accepted += 1;
+            true
+        } else {
+            self.turned_away(&ctx).await;
+            false
+        }
+    }
+
+    /// Counts a submitted or recovered task that will not run as rejected, and records that in
+    /// its job.
+    async fn turned_away(&mut self, ctx: &TaskContext) {
+        self.type_stats(&ctx.typ).rejected += 1;
+        if let Some(job) = ctx.job {
+            self.set_job_state(job, JobState::Rejected).await;
+        }
+    }
+
+    /// Makes the context for a new task.
+    fn context(&mut self, typ: task::Type, submitted: Instant, job: Option<JobId>) -> TaskContext {
+        self.next_id += 1;
+        TaskContext {
+            id: task::Id::new(self.next_id),
+            typ,
+            submitted,
+            started: None,
+            job,
+        }
+    }
+
+    /// Runs the stored jobs which had not finished when the last scheduler using the store
+    /// stopped, or has them wait for a slot if the rules do not allow them all to run at once.
+    /// Jobs of kinds that have not been registered are left in the store.
+    async fn recover(&mut self) {
+        let jobs = match self.store.unfinished().await {
+            Ok(jobs) => jobs,
+            Err(err) => {
+                let err = err.context("could not load the unfinished jobs");
+                self.hooks.on_store_error(&err).await;
+                return;
+            }
+        };
+        for job in jobs {
+            let cmd = match self
+                .kinds
+                .decode(&job, self.store.clone(), self.store_errors())
+            {
+                Ok(cmd) => cmd,
+                Err(err) => {
+                    let err = err.context(format!("could not recover job {}", job.id));
+                    self.hooks.on_store_error(&err).await;
+                    continue;
+                }
+            };
+            let ctx = self.context(job.typ, Instant::now(), Some(job.id));
+            self.recovering.push_back((ctx, cmd));
+        }
+        self.run_recovered().await;
+    }
+
+    /// Starts the recovered jobs that are waiting, in order, for as long as their types have
+    /// free slots.
+    async fn run_recovered(&mut self) {
+        let mut waiting = VecDeque::new();
+        while let Some((ctx, cmd)) = self.recovering.pop_front() {
+            if !self.try_run(&ctx.typ) {
+                waiting.push_back((ctx, cmd));
+                continue;
+            }
+            self.start_submitted(ctx, cmd).await;
+        }
+        self.recovering = waiting;
+    }
+
+    /// Records how a stored job ended. A job that was cancelled because the scheduler shut down
+    /// is left unfinished, so that it runs again on the next start.
+    async fn job_finished(&self, ctx: &TaskContext, outcome: &Outcome, aborted: bool) {
+        let Some(job) = ctx.job else {
+            return;
+        };
+        let state = match outcome {
+            Outcome::Success => JobState::Succeeded,
+            Outcome::Cancelled if aborted => return,
+            Outcome::Cancelled => JobState::Cancelled,
+            Outcome::Failed | Outcome::Panicked(_) | Outcome::TimedOut => JobState::Failed,
+        };
+        self.set_job_state(job, state).await;
+    }
+
+    async fn set_job_state(&self, job: JobId, state: JobState) {
+        if let Err(err) = self.store.set_state(job, state).await {
+            let err = err.context(format!("could not record job {job} as {state}"));
+            self.hooks.on_store_error(&err).await;
+        }
+    }
+
+    /// Starts a task which has already been counted as running by `try_run`. Returns false if
+    /// the task was rejected because its start hook failed.
+    async fn start(&mut self, mut ctx: TaskContext, cmd: Command) -> bool {
+        let res_tx = self.res_tx.clone();
+        ctx.started = Some(Instant::now());
+
+        // invoke the hook if it exists. we will block the scheduler until the hook is completed
+        // so that we can ensure consistency.
+        if let Err(e) = self.hooks.on_task_start(&ctx).await {
+            if self.hook_failed("on_task_start", &e).await {
+                self.task_finished(&ctx.typ);
+                self.rejected(&ctx).await;
+                return false;
+            }
+        }
+        // finally, spawn the task.
+        let rule = self.rules.get(&ctx.typ);
+        let (timeout, retry) = (rule.timeout, rule.retry.clone());
+        let abort_rx = self.abort_tx.subscribe();
+        tokio::spawn(async move {
+            let mut runner = Runner::new(ctx, cmd, res_tx);
+            runner.run(timeout, retry, abort_rx).await;
+        });
+        true
+    }
+
+    /// Queues a task that cannot run yet, if its rule has a queue with room.
+    async fn enqueue(
+        &mut self,
+        priority: task::Priority,
+        ctx: TaskContext,
+        cmd: Command,
+    ) -> Response {
+        // tasks that have waited too long do not take up room.
+        self.expire_queued().await;
+        let rule = &self.rules.get(&ctx.typ).queue;
+        let res = match rule {
+            Some(rule) => self.queues.push(rule, priority, ctx, cmd),
+            None => Err(Box::new((ctx, cmd))),
+        };
+        match res {
+            Ok(()) => Response::Queued,
+            Err(task) => {
+                self.rejected(&task.0).await;
+                self.turned_away(&task.0).await;
+                Response::Rejected
+            }
+        }
+    }
+
+    /// Rejects the queued tasks that have waited longer than their queue's timeout. Dropping their
+    /// commands resolves their handles.
+    async fn expire_queued(&mut self) {
+        for ctx in self.queues.expire(&self.rules, Instant::now()) {
+            self.rejected(&ctx).await;
+            self.turned_away(&ctx).await;
+        }
+    }
+
+    /// Calls the hook for a task that will not run.
+    async fn rejected(&mut self, ctx: &TaskContext) {
+        if let Err(e) = self.hooks.on_task_rejected(ctx).await {
+            self.hook_failed("on_task_rejected", &e).await;
+        }
+    }
+
+    /// Passes a hook's error on to the hooks and applies the failure policy. Returns true if the
+    /// task the hook was called for should not run.
+    async fn hook_failed(&mut self, hook: &str, err: &Arc<anyhow::Error>) -> bool {
+        self.hooks.on_hook_failed(hook, err).await;
+        match self.hooks.policy() {
+            FailurePolicy::Ignore => false,
+            FailurePolicy::RejectTask => true,
+            FailurePolicy::Stop => {
+                self.stop(Some(Duration::ZERO));
+                true
+            }
+        }
+    }
+
+    /// Starts waiting recovered jobs and then queued tasks, whichever types they are, for as long
+    /// as there are free slots.
+    async fn dispatch_queued(&mut self) {
+        self.run_recovered().await;
+        self.expire_queued().await;
+        loop {
+            if self.at_capacity() {
+                return;
+            }
+            let now = Instant::now();
+            let (rules, running, buckets) = (&self.rules, &self.running, &self.buckets);
+            let can_run = |typ: &task::Type| {
+                let rule = rules.get(typ);
+                let has_slot = running.get(typ).copied().unwrap_or(0) < rule.max_running;
+                // a type without a bucket has not started anything yet, so its bucket is full.
+                let has_token = rule
+                    .rate
+                    .is_none_or(|rate| buckets.get(typ).is_none_or(|b| b.available(rate, now)));
+                has_slot && has_token
+            };
+            let Some((ctx, cmd)) = self.queues.pop(rules, can_run) else {
+                return;
+            };
+            let started = self.try_run(&ctx.typ);
+            debug_assert!(started, "a queued task was popped that cannot run");
+            self.start_submitted(ctx, cmd).await;
+        }
+    }
+
+    /// Whether the global limit on running tasks has been reached.
+    fn at_capacity(&self) -> bool {
+        let max = self.rules.max_running();
+        max.is_some_and(|max| self.total_running() >= max)
+    }
+
+    /// Whether the type is under its own limit on running tasks.
+    fn has_slot(&self, typ: &task::Type) -> bool {
+        self.running.get(typ).copied().unwrap_or(0) < self.rules.get(typ).max_running
+    }
+
+    /// Uses up one of the type's tokens, if its rule has a rate. Returns false if there are
+    /// none left.
+    fn take_token(&mut self, typ: &task::Type) -> bool {
+        let Some(rate) = self.rules.get(typ).rate else {
+            return true;
+        };
+        let now = Instant::now();
+        self.buckets
+            .entry(typ.clone())
+            .or_insert_with(|| TokenBucket::new(rate, now))
+            .take(rate, now)
+    }
+
+    /// When the next task that is only held back by its type's rate can start: a queued task, an
+    /// owed cron run or a recovered job whose type has a free slot. Tasks held back by a limit on running tasks
+    /// are started when one finishes instead.
+    fn next_token(&self) -> Option<Instant> {
+        if self.at_capacity() {
+            return None;
+        }
+        let now = Instant::now();
+        let owed = self
+            .crons
+            .iter()
+            .filter(|(_, cron)| cron.owed > 0)
+            .map(|(typ, _)| typ);
+        let recovering = self.recovering.iter().map(|(ctx, _)| &ctx.typ);
+        self.buckets
+            .keys()
+            .filter(|typ| self.queues.len(typ) > 0)
+            .chain(owed)
+            .chain(recovering)
+            .filter(|typ| self.has_slot(typ))
+            .filter_map(|typ| {
+                let rate = self.rules.get(typ).rate?;
+                let bucket = self.buckets.get(typ)?;
+                Some(bucket.ready_at(rate, now))
+            })
+            .min()
+    }
+
+    fn type_stats(&mut self, typ: &task::Type) -> &mut TypeStats {
+        self.stats.entry(typ.clone()).or_default()
+    }
+
+    /// Takes a snapshot of the stats, with what is running and queued right now.
+    fn snapshot(&mut self) -> Stats {
+        for typ in self.running.keys().chain(self.factories.keys()) {
+            self.stats.entry(typ.clone()).or_default();
+        }
+        let mut types = self.stats.clone();
+        for (typ, stats) in &mut types {
+            stats.running = self.running.get(typ).copied().unwrap_or_default();
+            stats.queued = self.queues.len(typ);
+        }
+        Stats { types }
+    }
+
+    /// Starts shutting down: recurring tasks stop, and nothing new is accepted. With a grace
+    /// period, queued tasks are dropped and running tasks are cancelled once it is over. Without
+    /// one, the queued and running tasks are left to finish.
+    fn stop(&mut self, grace: Option<Duration>) {
+        self.factories.clear();
+        self.deadlines.clear();
+        self.crons.clear();
+        let stopping = self.stopping.get_or_insert_with(Stopping::default);
+        if let Some(grace) = grace {
+            // recovered jobs that never started stay unfinished, so they run on the next start.
+            stopping.summary.dropped += self.queues.clear() + self.recovering.len();
+            self.recovering.clear();
+            let at = Instant::now() + grace;
+            stopping.abort_at = Some(stopping.abort_at.map_or(at, |abort_at| abort_at.min(at)));
+        }
+    }
+
+    /// Sends the summary to everyone who asked for the shutdown, once every task has stopped.
+    fn stopped(&mut self) {
+        let Some(stopping) = self.stopping.take() else {
+            return;
+        };
+        for tx in stopping.waiters {
+            let _ = tx.send(stopping.summary);
+        }
+    }
+
+    /// Replaces the rules, unless we are shutting down. Running tasks carry on, and tasks that
+    /// the new limits allow are started straight away.
+    async fn update_rules(&mut self, rules: Rules) -> Response {
+        if self.stopping.is_some() {
+            return Response::Rejected;
+        }
+        self.rules = rules;
+        self.reschedule();
+        if let Err(e) = self.hooks.on_rules_updated(&self.rules).await {
+            self.hook_failed("on_rules_updated", &e).await;
+        }
+        self.dispatch_queued().await;
+        self.run_owed().await;
+        Response::Accepted
+    }
+
+    /// Works out when each recurring type is next due under the current rules. A type that still
+    /// has `run_every` keeps its deadline, and a cron type is next due when its schedule next
+    /// matches. Types whose rule has neither are unregistered.
+    fn reschedule(&mut self) {
+        let due: HashMap<task::Type, Instant> = self
+            .deadlines
+            .drain()
+            .map(|Reverse((at, typ))| (typ, at))
+            .collect();
+        let now = self.wall_now();
+        let types: Vec<task::Type> = self.factories.keys().cloned().collect();
+        for typ in types {
+            let next = if self.rules.schedule(&typ).is_some() {
+                // the schedule may have changed, so only runs due from now on count.
+                let cron = self.crons.entry(typ.clone()).or_insert(CronState {
+                    checked: now,
+                    owed: 0,
+                });
+                cron.checked = now;
+                self.next_cron(&typ, now)
+            } else {
+                self.crons.remove(&typ);
+                let every = self.rules.get(&typ).run_every;
+                every
+                    .filter(|every| !every.is_zero())
+                    .map(|_| due.get(&typ).copied().unwrap_or_else(Instant::now))
+            };
+            if let Some(at) = next {
+                self.deadlines.push(Reverse((at, typ)));
+            } else {
+                self.factories.remove(&typ);
+                self.crons.remove(&typ);
+            }
+        }
+    }
+
+    /// Registers a recurring task type. A type with `run_every` is first due right away, and one
+    /// with `cron` the next time its schedule matches.
+    fn register(&mut self, typ: task::Type, factory: Factory) -> Response {
+        if self.factories.contains_key(&typ) {
+            return Response::Rejected;
+        }
+        let first = if self.rules.schedule(&typ).is_some() {
+            let now = self.wall_now();
+            self.crons.insert(
+                typ.clone(),
+                CronState {
+                    checked: now,
+                    owed: 0,
+                },
+            );
+            self.next_cron(&typ, now)
+        } else {
+            let every = self.rules.get(&typ).run_every;
+            every
+                .filter(|every| !every.is_zero())
+                .map(|_| Instant::now())
+        };
+        let Some(first) = first else {
+            self.crons.remove(&typ);
+            return Response::Rejected;
+        };
+        self.factories.insert(typ.clone(), factory);
+        self.deadlines.push(Reverse((first, typ)));
+        Response::Accepted
+    }
+
+    /// Starts every recurring task that is due and works out when each is next due. If `start` is
+    /// false, or a type is already at its limit, that run is skipped.
+    async fn run_due(&mut self, start: bool) {
+        let now = Instant::now();
+        while let Some(Reverse((at, _))) = self.deadlines.peek() {
+            if *at > now {
+                break;
+            }
+            let Reverse((at, typ)) = self.deadlines.pop().unwrap();
+            if self.crons.contains_key(&typ) {
+                self.run_cron(typ, start).await;
+                continue;
+            }
+            let every = self
+                .rules
+                .get(&typ)
+                .run_every
+                .expect("recurring tasks have run_every");
+            // keep to the original schedule, but skip any runs we have fallen behind on rather
+            // than starting them all at once.
+            let mut next = at + every;
+            if next <= now {
+                next = now + every;
+            }
+            self.deadlines.push(Reverse((next, typ.clone())));
+
+            if start && self.try_run(&typ) {
+                self.start_recurring(typ).await;
+            }
+        }
+    }
+
+    /// Works out how many runs of a cron type have fallen due since it was last checked, and
+    /// starts them according to its missed-run policy.
+    async fn run_cron(&mut self, typ: task::Type, start: bool) {
+        let now = self.wall_now();
+        let missed = self.rules.get(&typ).missed;
+        let Some(schedule) = self.rules.schedule(&typ) else {
+            return;
+        };
+        let Some(cron) = self.crons.get_mut(&typ) else {
+            return;
+        };
+        let limit = match missed {
+            Missed::Skip => 1,
+            Missed::CatchUp(max) => max,
+        };
+        let due = schedule
+            .after(&cron.checked)
+            .take_while(|at| *at <= now)
+            .take(limit)
+            .count();
+        cron.checked = now;
+        if let Some(next) = self.next_cron(&typ, now) {
+            self.deadlines.push(Reverse((next, typ.clone())));
+        }
+        if !start || due == 0 {
+            return;
+        }
+        match missed {
+            Missed::Skip => {
+                if self.try_run(&typ) {
+                    self.start_recurring(typ).await;
+                }
+            }
+            Missed::CatchUp(max) => {
+                if let Some(cron) = self.crons.get_mut(&typ) {
+                    cron.owed = (cron.owed + due).min(max);
+                }
+                self.run_owed().await;
+            }
+        }
+    }
+
+    /// Starts the cron runs that are owed, for as long as there are free slots.
+    async fn run_owed(&mut self) {
+        let owing: Vec<task::Type> = self
+            .crons
+            .iter()
+            .filter(|(_, cron)| cron.owed > 0)
+            .map(|(typ, _)| typ.clone())
+            .collect();
+        for typ in owing {
+            while self.crons.get(&typ).is_some_and(|cron| cron.owed > 0) && self.try_run(&typ) {
+                if let Some(cron) = self.crons.get_mut(&typ) {
+                    cron.owed -= 1;
+                }
+                self.start_recurring(typ.clone()).await;
+            }
+        }
+    }
+
+    /// Returns the instant at which the cron type is next due after `now`, if it ever is again.
+    fn next_cron(&self, typ: &task::Type, now: DateTime<Local>) -> Option<Instant> {
+        let next = self.rules.schedule(typ)?.after(&now).next()?;
+        let wait = (next - now).to_std().unwrap_or_default();
+        Some(Instant::now() + wait)
+    }
+
+    /// The local wall-clock time.
+    fn wall_now(&self) -> DateTime<Local> {
+        (self.clock)()
+    }
+
+    /// Starts a run of a recurring task type, which has already been counted as running by
+    /// `try_run`.
+    async fn start_recurring(&mut self, typ: task::Type) {
+        let cmd = self.factories[&typ].make();
+        let ctx = self.context(typ, Instant::now(), None);
+        self.start(ctx, cmd).await;
+    }
+
     /// Returns the total number of running tasks.
     fn total_running(&self) -> usize {
         self.running.values().sum()
     }
+
+    /// Whether nothing is running or waiting to run. Tasks can be left waiting with nothing
+    /// running when their type's rate holds them back.
+    fn is_idle(&self) -> bool {
+        self.total_running() == 0 && self.queues.is_empty() && self.recovering.is_empty()
+    }
     fn task_finished(&mut self, typ: &task::Type) {
         let count = self.running.get_mut(typ).unwrap();
         assert!(
@@ -117,33 +817,131 @@ impl Control {
     /// Checks to see whether or not we can run a task of this type. If so, then we mark it as
     /// running and return true. Otherwise, we return false.
     fn try_run(&mut self, typ: &task::Type) -> bool {
-        let rule = self.rules.get(typ);
-        let count = self.running.get(typ).unwrap_or(&0);
-        if count >= &rule.max_running {
+        if self.at_capacity() {
+            // we can't run any more tasks at all.
+            return false;
+        }
+        if !self.has_slot(typ) {
             // we can't run any more of this task type.
             return false;
         }
-        self.running.insert(typ.clone(), *count + 1);
+        if !self.take_token(typ) {
+            // tasks of this type are starting too often.
+            return false;
+        }
+        *self.running.entry(typ.clone()).or_default() += 1;
         true
     }
 }
 
 struct Runner {
-    typ: Option<task::Type>,
+    ctx: Option<TaskContext>,
     cmd: Command,
     res_tx: Option<mpsc::Sender<RunResult>>,
+    /// How the last attempt ended, if one has.
+    outcome: Option<Outcome>,
+    /// Set if the task was stopped because the scheduler shut down.
+    aborted: bool,
+    started: Instant,
 }
 
 impl Runner {
-    fn new(typ: task::Type, cmd: Command, res_tx: mpsc::Sender<RunResult>) -> Self {
+    fn new(ctx: TaskContext, cmd: Command, res_tx: mpsc::Sender<RunResult>) -> Self {
         Self {
-            typ: Some(typ),
+            ctx: Some(ctx),
             cmd,
             res_tx: Some(res_tx),
+            outcome: None,
+            aborted: false,
+            started: Instant::now(),
+        }
+    }
+    /// Attempts the command until it succeeds or the retry policy gives up. Each attempt is
+    /// dropped if it runs for longer than `timeout`, and the command stops early if `abort_rx`
+    /// says the scheduler is shutting down.
+    async fn run(
+        &mut self,
+        timeout: Option<Duration>,
+        retry: Option<Retry>,
+        mut abort_rx: watch::Receiver<bool>,
+    ) {
+        let mut attempt = 1;
+        while let Some(fut) = self.cmd.attempt() {
+            let Some(outcome) = self.attempt(fut, timeout, &mut abort_rx).await else {
+                self.abort();
+                return;
+            };
+            let done = matches!(outcome, Outcome::Success | Outcome::Cancelled);
+            self.outcome = Some(outcome);
+            if done || !self.cmd.is_retryable() {
+                return;
+            }
+            let Some(retry) = &retry else { return };
+            if attempt >= retry.max_attempts {
+                return;
+            }
+            let delay = retry.delay(attempt);
+            attempt += 1;
+            self.send(RunResult::Retrying(self.ctx(), attempt, delay))
+                .await;
+            tokio::select! {
+                () = time::sleep(delay) => {}
+                _ = abort_rx.wait_for(|abort| *abort) => {
+                    self.abort();
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Records that the task was stopped because the scheduler shut down.
+    fn abort(&mut self) {
+        self.aborted = true;
+        self.outcome = Some(Outcome::Cancelled);
+    }
+
+    /// Runs one attempt. Returns how it ended, or `None` if it was stopped because the scheduler
+    /// is shutting down.
+    async fn attempt(
+        &self,
+        fut: Attempt,
+        timeout: Option<Duration>,
+        abort_rx: &mut watch::Receiver<bool>,
+    ) -> Option<Outcome> {
+        let mut fut = fut;
+        let timed = async {
+            match timeout {
+                Some(timeout) => {
+                    if let Ok(outcome) = time::timeout(timeout, &mut fut).await {
+                        outcome
+                    } else {
+                        self.send(RunResult::TimedOut(self.ctx())).await;
+                        Outcome::TimedOut
+                    }
+                }
+                None => (&mut fut).await,
+            }
+        };
+        tokio::select! {
+            outcome = timed => return Some(outcome),
+            _ = abort_rx.wait_for(|abort| *abort) => {}
         }
+        // a cancelled attempt resolves as soon as it is polled, and records that it was cancelled
+        // for the task's handle. one that cannot be cancelled is just dropped.
+        if self.cmd.cancel() {
+            fut.await;
+        }
+        None
+    }
+
+    fn ctx(&self) -> TaskContext {
+        self.ctx.clone().unwrap()
     }
-    async fn run(&mut self) {
-        self.cmd.run().await;
+
+    async fn send(&self, res: RunResult) {
+        if let Some(tx) = &self.res_tx {
+            let _ = tx.send(res).await;
+        }
     }
 }
 
@@ -152,14 +950,36 @@ impl Runner {
 impl Drop for Runner {
     fn drop(&mut self) {
         let tx = self.res_tx.take().unwrap();
-        let typ = self.typ.take().unwrap();
+        let res = RunResult::Finished {
+            ctx: self.ctx.take().unwrap(),
+            // the only way not to have an outcome is for the task to have panicked out of the
+            // runner.
+            outcome: self
+                .outcome
+                .take()
+                .unwrap_or_else(|| Outcome::Panicked(String::from("task panicked"))),
+            elapsed: self.started.elapsed(),
+            aborted: self.aborted,
+        };
         tokio::spawn(async move {
-            let _ = tx.send(RunResult::Finished(typ)).await;
+            let _ = tx.send(res).await;
         });
     }
 }
 
 /// This enum is used to communicate the result of a task run back to the controller.
 enum RunResult {
-    Finished(task::Type),
+    /// The task has stopped after running for `elapsed`. `aborted` is set if it was cancelled
+    /// because the scheduler shut down.
+    Finished {
+        ctx: TaskContext,
+        outcome: Outcome,
+        elapsed: Duration,
+        aborted: bool,
+    },
+    /// An attempt at the task ran for too long and was dropped.
+    TimedOut(TaskContext),
+    /// The task failed and will be attempted again after the delay. The number is the upcoming
+    /// attempt.
+    Retrying(TaskContext, u32, Duration),
 }
@@ -1,6 +1,11 @@
-use crate::task::Type;
+use crate::{
+    rules::Rules,
+    store::JobId,
+    task::{Id, Type},
+};
 use async_trait::async_trait;
-use std::sync::Arc;
+use std::{sync::Arc, time::Duration};
+use tokio::time::Instant;
 
 /// Hooks defines the trait that clients can implement to provide
 /// callbacks to scheduler lifecycle methods.
@@ -8,37 +13,173 @@ use std::sync::Arc;
 pub trait Callback {
     /// Called when the task has been scheduled, but before the task
     /// actually starts executing.
-    async fn on_task_start(&self, typ: &Type) -> HookResult;
+    async fn on_task_start(&self, ctx: &TaskContext) -> HookResult;
 
-    /// Called when the task has been scheduled, but before the task
-    /// actually starts executing.
-    async fn on_task_complete(&self, typ: &Type) -> HookResult;
+    /// Called when the task has stopped, with how it ended.
+    async fn on_task_complete(&self, ctx: &TaskContext, outcome: &Outcome) -> HookResult;
+
+    /// Called when an attempt at a task has run for longer than its rule's timeout and has been
+    /// dropped.
+    async fn on_task_timeout(&self, _ctx: &TaskContext) -> HookResult {
+        Ok(())
+    }
+
+    /// Called when a task has failed and will be attempted again after `delay`. `attempt` is the
+    /// number of the upcoming attempt, counting from 1.
+    async fn on_task_retry(
+        &self,
+        _ctx: &TaskContext,
+        _attempt: u32,
+        _delay: Duration,
+    ) -> HookResult {
+        Ok(())
+    }
+
+    /// Called when a submitted task is rejected, including by the failure policy.
+    async fn on_task_rejected(&self, _ctx: &TaskContext) -> HookResult {
+        Ok(())
+    }
+
+    /// Called when the scheduler has replaced its rules with `Scheduler::update_rules`.
+    async fn on_rules_updated(&self, _rules: &Rules) -> HookResult {
+        Ok(())
+    }
+
+    /// Called when one of the other hooks, named by `hook`, has returned an error, before the
+    /// failure policy is applied.
+    async fn on_hook_failed(&self, _hook: &str, _err: &Arc<anyhow::Error>) {}
+
+    /// Called when the store could not load or record a job. The scheduler carries on without
+    /// it: a job whose state could not be recorded still runs, and one that could not be loaded
+    /// is left in the store.
+    async fn on_store_error(&self, _err: &anyhow::Error) {}
 }
 
 pub type HookResult = Result<(), Arc<anyhow::Error>>;
 
+/// `TaskContext` describes the task a hook is called for.
+#[derive(Clone, Debug)]
+pub struct TaskContext {
+    pub id: Id,
+    pub typ: Type,
+    /// When the task was submitted, or when it was due for recurring tasks.
+    pub submitted: Instant,
+    /// When the task started running, if it has.
+    pub started: Option<Instant>,
+    /// The stored job the task runs, for tasks submitted with `Scheduler::submit`.
+    pub job: Option<JobId>,
+}
+
+/// `Outcome` is how a task ended.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Outcome {
+    Success,
+    /// A task scheduled with `Scheduler::run_retryable` returned an error on its last attempt.
+    Failed,
+    /// The task panicked with this message.
+    Panicked(String),
+    /// The task was cancelled, by its handle or because the scheduler shut down.
+    Cancelled,
+    /// The last attempt at the task ran for longer than its rule's timeout.
+    TimedOut,
+}
+
+/// `FailurePolicy` decides what the scheduler does when a hook returns an error. Whatever the
+/// policy, the error is passed to `Callback::on_hook_failed`.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum FailurePolicy {
+    /// Carry on as if the hook had succeeded.
+    #[default]
+    Ignore,
+    /// Don't run a task whose `on_task_start` hook fails. Errors from the other hooks are
+    /// ignored.
+    RejectTask,
+    /// Shut the scheduler down, cancelling the running tasks straight away.
+    Stop,
+}
+
+/// `Hooks` calls each of its callbacks in the order they were added. Every callback is called even
+/// if an earlier one returns an error, and the first error is returned.
 #[derive(Default)]
-pub struct Hooks(pub Option<Box<dyn Callback + Send + Sync + 'static>>);
+pub struct Hooks {
+    callbacks: Vec<Box<dyn Callback + Send + Sync + 'static>>,
+    policy: FailurePolicy,
+}
+
+impl Hooks {
+    pub fn push(&mut self, callback: Box<dyn Callback + Send + Sync + 'static>) {
+        self.callbacks.push(callback);
+    }
+
+    pub fn set_policy(&mut self, policy: FailurePolicy) {
+        self.policy = policy;
+    }
+
+    #[must_use]
+    pub fn policy(&self) -> FailurePolicy {
+        self.policy
+    }
+}
 
 #[async_trait]
 impl Callback for Hooks {
-    /// Called when the task has been scheduled, but before the task
-    /// actually starts executing.
-    async fn on_task_start(&self, typ: &Type) -> HookResult {
-        if let Some(cb) = &self.0 {
-            cb.on_task_start(typ).await
-        } else {
-            Ok(())
+    async fn on_task_start(&self, ctx: &TaskContext) -> HookResult {
+        let mut res = Ok(());
+        for cb in &self.callbacks {
+            res = res.and(cb.on_task_start(ctx).await);
         }
+        res
     }
 
-    /// Called when the task has been scheduled, but before the task
-    /// actually starts executing.
-    async fn on_task_complete(&self, typ: &Type) -> HookResult {
-        if let Some(cb) = &self.0 {
-            cb.on_task_complete(typ).await
-        } else {
-            Ok(())
+    async fn on_task_complete(&self, ctx: &TaskContext, outcome: &Outcome) -> HookResult {
+        let mut res = Ok(());
+        for cb in &self.callbacks {
+            res = res.and(cb.on_task_complete(ctx, outcome).await);
+        }
+        res
+    }
+
+    async fn on_task_timeout(&self, ctx: &TaskContext) -> HookResult {
+        let mut res = Ok(());
+        for cb in &self.callbacks {
+            res = res.and(cb.on_task_timeout(ctx).await);
+        }
+        res
+    }
+
+    async fn on_task_retry(&self, ctx: &TaskContext, attempt: u32, delay: Duration) -> HookResult {
+        let mut res = Ok(());
+        for cb in &self.callbacks {
+            res = res.and(cb.on_task_retry(ctx, attempt, delay).await);
+        }
+        res
+    }
+
+    async fn on_task_rejected(&self, ctx: &TaskContext) -> HookResult {
+        let mut res = Ok(());
+        for cb in &self.callbacks {
+            res = res.and(cb.on_task_rejected(ctx).await);
+        }
+        res
+    }
+
+    async fn on_rules_updated(&self, rules: &Rules) -> HookResult {
+        let mut res = Ok(());
+        for cb in &self.callbacks {
+            res = res.and(cb.on_rules_updated(rules).await);
+        }
+        res
+    }
+
+    async fn on_hook_failed(&self, hook: &str, err: &Arc<anyhow::Error>) {
+        for cb in &self.callbacks {
+            cb.on_hook_failed(hook, err).await;
+        }
+    }
+
+    async fn on_store_error(&self, err: &anyhow::Error) {
+        for cb in &self.callbacks {
+            cb.on_store_error(err).await;
         }
     }
 }
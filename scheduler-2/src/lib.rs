@@ -2,9 +2,15 @@
 
 mod command;
 mod control;
-mod hooks;
-mod rules;
+pub mod hooks;
+mod limit;
+mod queue;
+pub mod rules;
+pub mod rules_file;
 pub mod scheduler;
-mod task;
+pub mod stats;
+pub mod store;
+pub mod task;
 #[cfg(test)]
 mod tests;
+pub mod workflow;
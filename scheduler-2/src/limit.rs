@@ -0,0 +1,65 @@
+use std::time::Duration;
+
+use tokio::time::Instant;
+
+use crate::rules::Rate;
+
+/// How far off `ready_at` puts a token that a very slow rate would take longer to earn.
+const FAR_FUTURE: Duration = Duration::from_hours(100 * 365 * 24);
+
+/// `TokenBucket` allows bursts of up to `burst` starts and refills at `per_sec` starts per
+/// second. The rate is passed in each time, so that it follows changes to the rules. It uses
+/// tokio's clock, so it follows paused time in tests.
+pub(crate) struct TokenBucket {
+    tokens: f64,
+    last: Instant,
+}
+
+impl TokenBucket {
+    /// Creates a full bucket.
+    pub(crate) fn new(rate: Rate, now: Instant) -> Self {
+        Self {
+            tokens: f64::from(rate.burst),
+            last: now,
+        }
+    }
+
+    /// Takes a token if there is one. Returns false if the start should wait.
+    pub(crate) fn take(&mut self, rate: Rate, now: Instant) -> bool {
+        self.refill(rate, now);
+        if self.tokens < 1.0 {
+            return false;
+        }
+        self.tokens -= 1.0;
+        true
+    }
+
+    /// Whether there is a token to take, without taking it.
+    pub(crate) fn available(&self, rate: Rate, now: Instant) -> bool {
+        self.tokens_at(rate, now) >= 1.0
+    }
+
+    /// When the next token will be there to take, at most `FAR_FUTURE` from now.
+    pub(crate) fn ready_at(&self, rate: Rate, now: Instant) -> Instant {
+        let missing = 1.0 - self.tokens_at(rate, now);
+        if missing <= 0.0 {
+            return now;
+        }
+        let wait = Duration::try_from_secs_f64(missing / rate.per_sec)
+            .map_or(FAR_FUTURE, |wait| wait.min(FAR_FUTURE));
+        now.checked_add(wait).unwrap_or(now)
+    }
+
+    /// Adds the tokens earned since the last refill. If the rule's rate has changed, the tokens
+    /// that are left carry over, up to the new burst.
+    fn refill(&mut self, rate: Rate, now: Instant) {
+        self.tokens = self.tokens_at(rate, now);
+        self.last = now;
+    }
+
+    fn tokens_at(&self, rate: Rate, now: Instant) -> f64 {
+        let elapsed = now.saturating_duration_since(self.last);
+        let tokens = self.tokens.min(f64::from(rate.burst));
+        (tokens + elapsed.as_secs_f64() * rate.per_sec).min(f64::from(rate.burst))
+    }
+}
@@ -0,0 +1,193 @@
+use std::collections::{BTreeMap, HashMap, VecDeque};
+
+use crate::{
+    command::Command,
+    hooks::TaskContext,
+    rules::{Order, Queue, Rules},
+    task::{Priority, Type},
+};
+use tokio::time::Instant;
+
+/// `Queues` holds the tasks that are waiting for a free slot, for every type whose rule has a
+/// queue, and decides which of them runs next.
+///
+/// Higher priority tasks always go first. Between tasks of the same priority, types take turns in
+/// proportion to their rule's weight: each type has a virtual time which advances by
+/// `1 / weight` every time one of its tasks is started, and the type that is furthest behind goes
+/// next. This way a flood of one type cannot starve the others.
+#[derive(Default)]
+pub(crate) struct Queues {
+    waiting: HashMap<Type, TaskQueue>,
+    /// The virtual time of each type that has been dispatched.
+    served: HashMap<Type, f64>,
+    /// The virtual time of the last type to be dispatched.
+    now: f64,
+}
+
+impl Queues {
+    /// Adds a task to its type's queue. Returns the task if the queue is full.
+    pub(crate) fn push(
+        &mut self,
+        rule: &Queue,
+        priority: Priority,
+        ctx: TaskContext,
+        cmd: Command,
+    ) -> Result<(), Box<(TaskContext, Command)>> {
+        let typ = &ctx.typ;
+        if !self.waiting.contains_key(typ) {
+            // a type that has been idle does not get to catch up on the turns it did not need.
+            let served = self.served.entry(typ.clone()).or_default();
+            *served = served.max(self.now);
+        }
+        self.waiting
+            .entry(typ.clone())
+            .or_default()
+            .push(rule, priority, ctx, cmd)
+    }
+
+    /// The number of tasks of the type that are waiting.
+    pub(crate) fn len(&self, typ: &Type) -> usize {
+        self.waiting.get(typ).map_or(0, TaskQueue::len)
+    }
+
+    /// Whether no tasks are waiting.
+    pub(crate) fn is_empty(&self) -> bool {
+        self.waiting.is_empty()
+    }
+
+    /// Drops every queued task. Returns how many there were.
+    pub(crate) fn clear(&mut self) -> usize {
+        self.waiting.drain().map(|(_, queue)| queue.len()).sum()
+    }
+
+    /// Takes the next task to run from the types that `can_run` allows. Tasks that have waited
+    /// too long should have been taken out with `expire` first.
+    pub(crate) fn pop(
+        &mut self,
+        rules: &Rules,
+        can_run: impl Fn(&Type) -> bool,
+    ) -> Option<(TaskContext, Command)> {
+        let served = |typ: &Type| self.served.get(typ).copied().unwrap_or_default();
+        let typ = self
+            .waiting
+            .iter()
+            .filter(|(typ, _)| can_run(typ))
+            .max_by(|(a, qa), (b, qb)| {
+                qa.priority()
+                    .cmp(&qb.priority())
+                    .then_with(|| served(b).total_cmp(&served(a)))
+            })
+            .map(|(typ, _)| typ.clone())?;
+
+        let rule = rules.get(&typ);
+        let order = rule.queue.as_ref().map(|q| q.order).unwrap_or_default();
+        let queue = self.waiting.get_mut(&typ)?;
+        let (ctx, cmd) = queue.pop(order)?;
+        if queue.is_empty() {
+            self.waiting.remove(&typ);
+        }
+        let served = self.served.entry(typ.clone()).or_default();
+        self.now = *served;
+        *served += 1.0 / f64::from(rule.weight.max(1));
+        Some((ctx, cmd))
+    }
+
+    /// Takes out the tasks that have waited longer than their queue's timeout, and returns them.
+    pub(crate) fn expire(&mut self, rules: &Rules, now: Instant) -> Vec<TaskContext> {
+        let mut expired = vec![];
+        self.waiting.retain(|typ, queue| {
+            if let Some(rule) = &rules.get(typ).queue {
+                queue.expire(rule, now, &mut expired);
+            }
+            !queue.is_empty()
+        });
+        expired
+    }
+
+    /// When the next queued task will have waited longer than its queue's timeout, if any will.
+    pub(crate) fn next_expiry(&self, rules: &Rules) -> Option<Instant> {
+        self.waiting
+            .iter()
+            .filter_map(|(typ, queue)| {
+                let timeout = rules.get(typ).queue.as_ref()?.timeout?;
+                Some(queue.oldest()? + timeout)
+            })
+            .min()
+    }
+}
+
+/// `TaskQueue` holds the tasks of one type that are waiting for a free slot, by priority.
+#[derive(Default)]
+struct TaskQueue(BTreeMap<Priority, VecDeque<(TaskContext, Command)>>);
+
+impl TaskQueue {
+    fn push(
+        &mut self,
+        rule: &Queue,
+        priority: Priority,
+        ctx: TaskContext,
+        cmd: Command,
+    ) -> Result<(), Box<(TaskContext, Command)>> {
+        if self.len() >= rule.max_len {
+            return Err(Box::new((ctx, cmd)));
+        }
+        self.0.entry(priority).or_default().push_back((ctx, cmd));
+        Ok(())
+    }
+
+    /// Takes the next task of the highest priority that has one.
+    fn pop(&mut self, order: Order) -> Option<(TaskContext, Command)> {
+        let mut entry = self.0.last_entry()?;
+        let tasks = entry.get_mut();
+        let next = match order {
+            Order::Fifo => tasks.pop_front(),
+            Order::Lifo => tasks.pop_back(),
+        };
+        if tasks.is_empty() {
+            entry.remove();
+        }
+        next
+    }
+
+    /// The priority of the task that would be popped next.
+    fn priority(&self) -> Option<Priority> {
+        self.0.keys().next_back().copied()
+    }
+
+    fn len(&self) -> usize {
+        self.0.values().map(VecDeque::len).sum()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// When the task that has waited longest was submitted. Tasks are queued in order, so the
+    /// oldest of each priority are at the front.
+    fn oldest(&self) -> Option<Instant> {
+        self.0
+            .values()
+            .filter_map(|tasks| tasks.front())
+            .map(|(ctx, _)| ctx.submitted)
+            .min()
+    }
+
+    /// Takes out every task that has waited longer than the timeout since it was submitted,
+    /// adding them to `expired`.
+    fn expire(&mut self, rule: &Queue, now: Instant, expired: &mut Vec<TaskContext>) {
+        let Some(timeout) = rule.timeout else {
+            return;
+        };
+        for tasks in self.0.values_mut() {
+            while let Some((ctx, _)) = tasks.front() {
+                if now.duration_since(ctx.submitted) < timeout {
+                    break;
+                }
+                if let Some((ctx, _)) = tasks.pop_front() {
+                    expired.push(ctx);
+                }
+            }
+        }
+        self.0.retain(|_, tasks| !tasks.is_empty());
+    }
+}
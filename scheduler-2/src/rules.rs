@@ -1,4 +1,7 @@
-use std::{collections::HashMap, time::Duration};
+use std::{collections::HashMap, fmt, str::FromStr, time::Duration};
+
+use cron::Schedule;
+use rand::Rng;
 
 use crate::task;
 
@@ -6,9 +9,13 @@ use crate::task;
 ///
 /// Each task type can have its own rule, and there is a default rule
 /// that applies to task types that do not have a specific rule.
+#[derive(Default)]
 pub struct Rules {
-    rules: HashMap<task::Type, Rule>,
+    types: HashMap<task::Type, Rule>,
     default: Rule,
+    max_running: Option<usize>,
+    /// The parsed `cron` expression of each rule that has one.
+    schedules: HashMap<task::Type, Schedule>,
 }
 
 impl Rules {
@@ -17,56 +24,303 @@ impl Rules {
         Builder::new()
     }
 
+    #[must_use]
     pub fn get(&self, typ: &task::Type) -> &Rule {
-        self.rules.get(typ).unwrap_or(&self.default)
+        self.types.get(typ).unwrap_or(&self.default)
+    }
+
+    /// The most tasks that may run at once across all types, if there is a limit.
+    #[must_use]
+    pub fn max_running(&self) -> Option<usize> {
+        self.max_running
+    }
+
+    pub(crate) fn schedule(&self, typ: &task::Type) -> Option<&Schedule> {
+        self.schedules.get(typ)
     }
 }
 
 pub struct Rule {
+    /// The most tasks of this type that may run at once.
     pub max_running: usize,
+
+    /// How often tasks of this type may start, on top of `max_running`. A task that would start
+    /// too soon is queued if the rule has a queue, and rejected otherwise.
+    pub rate: Option<Rate>,
+
+    /// Re-run tasks of this type on this interval. Only applies to task types registered with
+    /// `Scheduler::register`.
     pub run_every: Option<Duration>,
+
+    /// Run tasks of this type whenever the cron expression matches the local time. Like
+    /// `run_every`, it only applies to registered task types, and the two cannot be combined.
+    /// The expression has a field for seconds, so "every weekday at 02:00" is
+    /// `0 0 2 * * Mon-Fri`.
+    pub cron: Option<String>,
+
+    /// What to do about `cron` runs that could not start on time.
+    pub missed: Missed,
+
+    /// Where tasks wait when `max_running` of this type are already running. Without a queue,
+    /// those tasks are rejected.
+    pub queue: Option<Queue>,
+
+    /// How long each attempt at a task may run before it is dropped, freeing its slot.
+    pub timeout: Option<Duration>,
+
+    /// How tasks scheduled with `Scheduler::run_retryable` are retried when they fail or time
+    /// out. Without a policy, they are only attempted once.
+    pub retry: Option<Retry>,
+
+    /// This type's share of the free slots when tasks of several types are queued. A type with
+    /// weight 2 has twice as many tasks started as a type with weight 1 at the same priority.
+    pub weight: u32,
+}
+
+impl Default for Rule {
+    fn default() -> Self {
+        Self {
+            max_running: 1,
+            rate: None,
+            run_every: None,
+            cron: None,
+            missed: Missed::default(),
+            queue: None,
+            timeout: None,
+            retry: None,
+            weight: 1,
+        }
+    }
+}
+
+/// Rate limits how often tasks of a type start, with a token bucket: up to `burst` tasks may
+/// start at once, after which they start at `per_sec` per second on average.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Rate {
+    pub per_sec: f64,
+    pub burst: u32,
+}
+
+impl Rate {
+    /// A rate of `per_sec` starts per second, which allows no bursts.
+    #[must_use]
+    pub fn per_sec(per_sec: f64) -> Self {
+        Self { per_sec, burst: 1 }
+    }
+
+    fn is_valid(self) -> bool {
+        self.per_sec.is_finite() && self.per_sec > 0.0 && self.burst > 0
+    }
+}
+
+/// Missed decides what happens to the runs of a `cron` schedule that fall due while the type is
+/// at its limit, or while the process was too busy or asleep to start them.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Missed {
+    /// Drop them. Several runs that fall due at once start at most one task.
+    #[default]
+    Skip,
+
+    /// Owe up to this many of them, and start them as soon as there are free slots.
+    CatchUp(usize),
+}
+
+/// Retry is a policy for retrying failed tasks with exponential backoff. A task keeps its slot
+/// while it waits to be retried.
+#[derive(Clone, Debug)]
+pub struct Retry {
+    /// The most attempts at a task, including the first.
+    pub max_attempts: u32,
+
+    /// How long to wait before the first retry. The wait doubles after each attempt.
+    pub backoff: Duration,
+
+    /// The longest to wait between attempts.
+    pub max_backoff: Duration,
+
+    /// Wait a random amount between half and all of the backoff, so that tasks which failed
+    /// together do not all retry together.
+    pub jitter: bool,
 }
 
-impl Default for Rules {
+impl Default for Retry {
     fn default() -> Self {
         Self {
-            rules: HashMap::default(),
-            default: Rule {
-                max_running: 1,
-                run_every: None,
-            },
+            max_attempts: 3,
+            backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(10),
+            jitter: true,
         }
     }
 }
 
+impl Retry {
+    /// Returns how long to wait after the given attempt, counting from 1, has failed.
+    #[must_use]
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let exp = attempt.saturating_sub(1).min(31);
+        let delay = self.backoff.saturating_mul(1 << exp).min(self.max_backoff);
+        if self.jitter {
+            rand::thread_rng().gen_range(delay / 2..=delay)
+        } else {
+            delay
+        }
+    }
+}
+
+/// Queue configures how tasks wait for a free slot.
+pub struct Queue {
+    /// The most tasks that may wait at once. Tasks beyond this are rejected.
+    pub max_len: usize,
+
+    /// The order in which waiting tasks are run.
+    pub order: Order,
+
+    /// Tasks that have waited this long are dropped without being run.
+    pub timeout: Option<Duration>,
+}
+
+impl Default for Queue {
+    fn default() -> Self {
+        Self {
+            max_len: 1024,
+            order: Order::default(),
+            timeout: None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Order {
+    /// The task that has waited longest runs first.
+    #[default]
+    Fifo,
+
+    /// The task that was queued most recently runs first.
+    Lifo,
+}
+
 #[derive(Default)]
 pub struct Builder {
     rules: Rules,
 }
 
 impl Builder {
+    #[must_use]
     pub fn new() -> Self {
         Self {
             rules: Rules::default(),
         }
     }
+    /// Limits how many tasks may run at once across all types. Tasks over the limit are queued
+    /// if their rule has a queue, and rejected otherwise.
+    #[must_use]
+    pub fn max_running(mut self, max: usize) -> Self {
+        self.rules.max_running = Some(max);
+        self
+    }
     #[must_use]
     pub fn default(mut self, rule: Rule) -> Self {
         self.rules.default = rule;
         self
     }
+    /// Adds a rule for a task type.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the task type already has a rule.
     #[must_use]
     pub fn rule<T: Into<task::Type>>(mut self, typ: T, rule: Rule) -> Self {
         let typ = typ.into();
         assert!(
-            !self.rules.rules.contains_key(&typ),
+            !self.rules.types.contains_key(&typ),
             "duplicate rule for type {typ:?}"
         );
-        self.rules.rules.insert(typ.clone(), rule);
+        self.rules.types.insert(typ.clone(), rule);
         self
     }
+    /// Checks the rules and builds them.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the rules are invalid. See `try_build`.
     #[must_use]
     pub fn build(self) -> Rules {
-        self.rules
+        match self.try_build() {
+            Ok(rules) => rules,
+            Err(err) => panic!("invalid rules: {err}"),
+        }
+    }
+
+    /// Checks the rules and builds them.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a `cron` expression does not parse, if a rule has both `cron` and
+    /// `run_every`, if the default rule has `cron`, or if a rate is not positive.
+    pub fn try_build(mut self) -> Result<Rules, RuleError> {
+        if self.rules.default.cron.is_some() {
+            return Err(RuleError::DefaultCron);
+        }
+        if self.rules.default.rate.is_some_and(|rate| !rate.is_valid()) {
+            return Err(RuleError::InvalidRate(None));
+        }
+        for (typ, rule) in &self.rules.types {
+            if rule.rate.is_some_and(|rate| !rate.is_valid()) {
+                return Err(RuleError::InvalidRate(Some(typ.clone())));
+            }
+            let Some(expr) = &rule.cron else {
+                continue;
+            };
+            if rule.run_every.is_some() {
+                return Err(RuleError::CronAndRunEvery(typ.clone()));
+            }
+            let schedule = Schedule::from_str(expr).map_err(|err| RuleError::InvalidCron {
+                typ: typ.clone(),
+                expr: expr.clone(),
+                reason: err.to_string(),
+            })?;
+            self.rules.schedules.insert(typ.clone(), schedule);
+        }
+        Ok(self.rules)
     }
 }
+
+/// `RuleError` is why a set of rules is invalid.
+#[derive(Debug, PartialEq)]
+pub enum RuleError {
+    /// The `cron` expression for the type does not parse.
+    InvalidCron {
+        typ: task::Type,
+        expr: String,
+        reason: String,
+    },
+
+    /// The type's rule has both `cron` and `run_every`.
+    CronAndRunEvery(task::Type),
+
+    /// The default rule has `cron`, which would run every type that is registered at once.
+    DefaultCron,
+
+    /// The rate for the type, or for the default rule if there is no type, does not have a
+    /// positive `per_sec` and `burst`.
+    InvalidRate(Option<task::Type>),
+}
+
+impl fmt::Display for RuleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RuleError::InvalidCron { typ, expr, reason } => {
+                write!(f, "invalid cron expression {expr:?} for {typ}: {reason}")
+            }
+            RuleError::CronAndRunEvery(typ) => {
+                write!(f, "the rule for {typ} has both cron and run_every")
+            }
+            RuleError::DefaultCron => write!(f, "the default rule cannot have cron"),
+            RuleError::InvalidRate(Some(typ)) => write!(f, "the rate for {typ} is not positive"),
+            RuleError::InvalidRate(None) => write!(f, "the default rate is not positive"),
+        }
+    }
+}
+
+impl std::error::Error for RuleError {}
@@ -0,0 +1,266 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use tokio::{task::JoinHandle, time};
+
+use crate::{
+    rules::{self, Missed, Order, Queue, Rate, Retry, Rule, Rules},
+    scheduler::Scheduler,
+};
+
+/// `RulesFile` reads rules from a TOML file, and can keep a scheduler's rules in step with it.
+/// Durations are in seconds, and every field is optional:
+///
+/// ```toml
+/// max_running = 16
+///
+/// [default]
+/// max_running = 2
+///
+/// [types.fetch]
+/// max_running = 4
+/// rate = { per_sec = 10, burst = 5 }
+/// weight = 2
+/// timeout_secs = 30
+/// queue = { max_len = 100, order = "lifo", timeout_secs = 60 }
+/// retry = { max_attempts = 5, backoff_secs = 0.5, max_backoff_secs = 30, jitter = true }
+///
+/// [types.report]
+/// cron = "0 0 2 * * *"
+/// catch_up = 3
+/// ```
+pub struct RulesFile {
+    path: PathBuf,
+    interval: Duration,
+    on_error: Option<OnError>,
+}
+
+/// What `watch` does with a file that cannot be loaded.
+type OnError = Box<dyn Fn(&anyhow::Error) + Send + Sync>;
+
+impl RulesFile {
+    /// The file is checked for changes every second, unless `interval` says otherwise.
+    #[must_use]
+    pub fn new<P: Into<PathBuf>>(path: P) -> Self {
+        Self {
+            path: path.into(),
+            interval: Duration::from_secs(1),
+            on_error: None,
+        }
+    }
+
+    /// Sets how often `watch` checks whether the file has changed.
+    #[must_use]
+    pub fn interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// Sets what `watch` does with a file that cannot be loaded, besides keeping the rules as
+    /// they were. By default the error is ignored.
+    #[must_use]
+    pub fn on_error<F: Fn(&anyhow::Error) + Send + Sync + 'static>(mut self, f: F) -> Self {
+        self.on_error = Some(Box::new(f));
+        self
+    }
+
+    #[must_use]
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Reads the rules from the file.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be read or parsed, or if the rules are invalid.
+    pub async fn load(&self) -> Result<Rules> {
+        let text = tokio::fs::read_to_string(&self.path)
+            .await
+            .with_context(|| format!("reading {}", self.path.display()))?;
+        let file: File =
+            toml::from_str(&text).with_context(|| format!("parsing {}", self.path.display()))?;
+        Ok(file.into_builder()?.try_build()?)
+    }
+
+    /// Checks the file for changes every interval, and updates the scheduler's rules when it has
+    /// changed. A file that cannot be loaded is passed to `on_error` and otherwise ignored,
+    /// leaving the rules as they were. The task stops once the scheduler has shut down, or once
+    /// every clone of it has been dropped, as the task does not keep it running.
+    #[must_use]
+    pub fn watch(self, sched: &Scheduler) -> JoinHandle<()> {
+        let sched = sched.downgrade();
+        tokio::spawn(async move {
+            let mut seen = self.modified().await;
+            loop {
+                time::sleep(self.interval).await;
+                let Some(sched) = sched.upgrade() else {
+                    return;
+                };
+                let modified = self.modified().await;
+                if modified == seen {
+                    continue;
+                }
+                seen = modified;
+                let rules = match self.load().await {
+                    Ok(rules) => rules,
+                    Err(err) => {
+                        if let Some(on_error) = &self.on_error {
+                            on_error(&err);
+                        }
+                        continue;
+                    }
+                };
+                if sched.update_rules(rules).await.is_err() {
+                    return;
+                }
+            }
+        })
+    }
+
+    /// When the file was last modified and how long it is, which together tell us whether it
+    /// has changed. `None` if it cannot be read.
+    async fn modified(&self) -> Option<(SystemTime, u64)> {
+        let meta = tokio::fs::metadata(&self.path).await.ok()?;
+        Some((meta.modified().ok()?, meta.len()))
+    }
+}
+
+/// `File` is the layout of a rules file.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct File {
+    max_running: Option<usize>,
+    default: Option<FileRule>,
+    #[serde(default)]
+    types: HashMap<String, FileRule>,
+}
+
+impl File {
+    fn into_builder(self) -> Result<rules::Builder> {
+        let mut builder = Rules::builder();
+        if let Some(max) = self.max_running {
+            builder = builder.max_running(max);
+        }
+        if let Some(default) = self.default {
+            builder = builder.default(default.try_into().context("default rule")?);
+        }
+        for (typ, rule) in self.types {
+            let rule = rule.try_into().with_context(|| format!("rule for {typ}"))?;
+            builder = builder.rule(typ, rule);
+        }
+        Ok(builder)
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FileRule {
+    max_running: Option<usize>,
+    rate: Option<FileRate>,
+    run_every_secs: Option<f64>,
+    cron: Option<String>,
+    /// How many missed cron runs to catch up on. Without it, they are skipped.
+    catch_up: Option<usize>,
+    queue: Option<FileQueue>,
+    timeout_secs: Option<f64>,
+    retry: Option<FileRetry>,
+    weight: Option<u32>,
+}
+
+impl TryFrom<FileRule> for Rule {
+    type Error = anyhow::Error;
+
+    fn try_from(rule: FileRule) -> Result<Self> {
+        let default = Rule::default();
+        Ok(Rule {
+            max_running: rule.max_running.unwrap_or(default.max_running),
+            rate: rule.rate.map(|rate| Rate {
+                per_sec: rate.per_sec,
+                burst: rate.burst.unwrap_or(1),
+            }),
+            run_every: secs(rule.run_every_secs)?,
+            cron: rule.cron,
+            missed: rule.catch_up.map_or(Missed::Skip, Missed::CatchUp),
+            queue: rule.queue.map(TryInto::try_into).transpose()?,
+            timeout: secs(rule.timeout_secs)?,
+            retry: rule.retry.map(TryInto::try_into).transpose()?,
+            weight: rule.weight.unwrap_or(default.weight),
+        })
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FileRate {
+    per_sec: f64,
+    burst: Option<u32>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FileQueue {
+    max_len: Option<usize>,
+    order: Option<FileOrder>,
+    timeout_secs: Option<f64>,
+}
+
+impl TryFrom<FileQueue> for Queue {
+    type Error = anyhow::Error;
+
+    fn try_from(queue: FileQueue) -> Result<Self> {
+        let default = Queue::default();
+        Ok(Queue {
+            max_len: queue.max_len.unwrap_or(default.max_len),
+            order: match queue.order {
+                Some(FileOrder::Fifo) => Order::Fifo,
+                Some(FileOrder::Lifo) => Order::Lifo,
+                None => default.order,
+            },
+            timeout: secs(queue.timeout_secs)?,
+        })
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum FileOrder {
+    Fifo,
+    Lifo,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FileRetry {
+    max_attempts: Option<u32>,
+    backoff_secs: Option<f64>,
+    max_backoff_secs: Option<f64>,
+    jitter: Option<bool>,
+}
+
+impl TryFrom<FileRetry> for Retry {
+    type Error = anyhow::Error;
+
+    fn try_from(retry: FileRetry) -> Result<Self> {
+        let default = Retry::default();
+        Ok(Retry {
+            max_attempts: retry.max_attempts.unwrap_or(default.max_attempts),
+            backoff: secs(retry.backoff_secs)?.unwrap_or(default.backoff),
+            max_backoff: secs(retry.max_backoff_secs)?.unwrap_or(default.max_backoff),
+            jitter: retry.jitter.unwrap_or(default.jitter),
+        })
+    }
+}
+
+/// Converts a number of seconds from the file into a duration.
+fn secs(secs: Option<f64>) -> Result<Option<Duration>> {
+    secs.map(|secs| {
+        Duration::try_from_secs_f64(secs).with_context(|| format!("invalid duration {secs}"))
+    })
+    .transpose()
+}
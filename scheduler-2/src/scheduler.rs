@@ -1,28 +1,88 @@
 use crate::{
-    command::Command,
+    command::{Command, Factory},
     control::Control,
-    hooks::{Callback, Hooks},
+    hooks::{Callback, FailurePolicy, Hooks},
     rules::Rules,
-    task::Type,
+    stats::Stats,
+    store::{self, Job, JobId, Kinds, MemoryStore, Store, StoreErrors},
+    task::{self, Priority, TaskHandle, Type},
+    workflow::{self, Report, Workflow},
 };
 use anyhow::Result;
-use std::{future::Future, sync::Arc};
-use tokio::sync::{mpsc, oneshot};
+use chrono::{DateTime, Local};
+use std::{future::Future, sync::Arc, time::Duration};
+use tokio::{
+    sync::{mpsc, oneshot, watch},
+    time::Instant,
+};
 
 #[derive(Clone)]
 pub struct Scheduler {
     tx: Arc<mpsc::Sender<Request>>,
+    priority: Priority,
+    store: Arc<dyn Store>,
+    store_errors: StoreErrors,
+    /// Set once the controller has recovered the unfinished jobs in the store.
+    recovered: watch::Receiver<bool>,
 }
 
 impl Scheduler {
+    /// Makes a scheduler which keeps jobs in memory. Use the builder to give it a durable store.
     #[must_use]
     pub fn new(hooks: Hooks, rules: Rules) -> Scheduler {
+        Self::start(
+            hooks,
+            rules,
+            Arc::new(MemoryStore::new()),
+            Kinds::default(),
+            Local::now,
+        )
+    }
+
+    fn start(
+        hooks: Hooks,
+        rules: Rules,
+        store: Arc<dyn Store>,
+        kinds: Kinds,
+        clock: fn() -> DateTime<Local>,
+    ) -> Scheduler {
         let (tx, rx) = mpsc::channel(1024);
+        let (recovered_tx, recovered) = watch::channel(false);
+        let mut ctrl = Control::new(rx, hooks, rules, store.clone(), kinds, recovered_tx, clock);
+        let store_errors = ctrl.store_errors();
         tokio::spawn(async move {
-            let mut ctrl = Control::new(rx, hooks, rules);
             ctrl.run().await;
         });
-        Self { tx: tx.into() }
+        Self {
+            tx: tx.into(),
+            priority: Priority::default(),
+            store,
+            store_errors,
+            recovered,
+        }
+    }
+
+    /// Returns a scheduler for the same tasks which submits them with the given priority.
+    #[must_use]
+    pub fn with_priority(&self, priority: Priority) -> Scheduler {
+        Self {
+            tx: self.tx.clone(),
+            priority,
+            store: self.store.clone(),
+            store_errors: self.store_errors.clone(),
+            recovered: self.recovered.clone(),
+        }
+    }
+
+    /// Returns a handle to the same scheduler that does not keep it running.
+    pub(crate) fn downgrade(&self) -> WeakScheduler {
+        WeakScheduler {
+            tx: self.tx.downgrade(),
+            priority: self.priority,
+            store: self.store.clone(),
+            store_errors: self.store_errors.clone(),
+            recovered: self.recovered.clone(),
+        }
     }
 
     #[must_use]
@@ -30,8 +90,8 @@ impl Scheduler {
         Builder::new()
     }
 
-    /// Waits for all running tasks to complete. Any tasks that are attempted to be scheduled while
-    /// waiting will be rejected. Useful for tests, but not in production.
+    /// Waits for all running and queued tasks to complete. Any tasks that are attempted to be
+    /// scheduled while waiting will be rejected. Useful for tests, but not in production.
     ///
     /// # Errors
     ///
@@ -45,30 +105,180 @@ impl Scheduler {
         Ok(rx.await?)
     }
 
-    /// Schedules a task to be run. The response will indicate whether or not the task was accepted
-    /// or rejected.
+    /// Replaces the scheduler's rules. The change applies to everything that happens from then
+    /// on: running tasks are never stopped, even if their type is now over its limit, but no new
+    /// ones start until it is under it again. Recurring types keep running if their new rule
+    /// still has `run_every` or `cron`, and are unregistered otherwise.
+    ///
+    /// The update is rejected if the scheduler is shutting down.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the scheduler has been shut down.
+    pub async fn update_rules(&self, rules: Rules) -> Result<Response> {
+        let (tx, rx) = oneshot::channel();
+        let req = UpdateRulesRequest { rules, tx };
+        self.tx.send(Request::UpdateRules(req)).await?;
+        Ok(rx.await?)
+    }
+
+    /// Returns a snapshot of the scheduler's state and counters. See `Stats::to_prometheus` to
+    /// export it.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the scheduler has been shut down.
+    pub async fn stats(&self) -> Result<Stats> {
+        let (tx, rx) = oneshot::channel();
+        self.tx.send(Request::Stats(StatsRequest { tx })).await?;
+        Ok(rx.await?)
+    }
+
+    /// Shuts the scheduler down. New tasks are rejected, recurring tasks stop and queued tasks
+    /// are dropped. Running tasks are given `grace` to finish, after which any that are left are
+    /// cancelled. Returns what happened to the tasks, once every one of them has stopped.
+    ///
+    /// Once it has shut down, every call on the scheduler returns an error. The scheduler also
+    /// shuts down when every clone of it has been dropped, but then lets the running and queued
+    /// tasks finish.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the scheduler has already been shut down.
+    pub async fn shutdown(&self, grace: Duration) -> Result<Summary> {
+        let (tx, rx) = oneshot::channel();
+        let req = ShutdownRequest { grace, tx };
+        self.tx.send(Request::Shutdown(req)).await?;
+        Ok(rx.await?)
+    }
+
+    /// Schedules a task to be run. The handle's response indicates whether the task was
+    /// accepted, queued or rejected, and awaiting the handle gives the task's output.
     ///
     /// # Errors
     ///
     /// Returns an error if the scheduler has been shut down. Errors should be propagated up the
     /// stack resulting in program termination.
-    pub async fn run_task<T: Into<Type>, F>(&self, typ: T, f: F) -> Result<Response>
+    pub async fn run_task<T: Into<Type>, F, O>(&self, typ: T, f: F) -> Result<TaskHandle<O>>
     where
-        F: Future<Output = ()> + Send + 'static,
+        F: Future<Output = O> + Send + 'static,
+        O: Send + 'static,
     {
+        let (cmd, handle) = task::once(f);
+        let res = self.send_task(typ.into(), cmd).await?;
+        Ok(handle.with_response(res))
+    }
+
+    /// Schedules a fallible task, which is retried according to its rule's retry policy until it
+    /// returns `Ok`. `f` makes the future for each attempt. Awaiting the handle gives the output
+    /// of the last attempt.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the scheduler has been shut down.
+    pub async fn run_retryable<T: Into<Type>, F, Fut, O, E>(
+        &self,
+        typ: T,
+        f: F,
+    ) -> Result<TaskHandle<Result<O, E>>>
+    where
+        F: Fn() -> Fut + Send + 'static,
+        Fut: Future<Output = std::result::Result<O, E>> + Send + 'static,
+        O: Send + 'static,
+        E: Send + 'static,
+    {
+        let (cmd, handle) = task::retryable(f);
+        let res = self.send_task(typ.into(), cmd).await?;
+        Ok(handle.with_response(res))
+    }
+
+    /// Runs a workflow, starting each of its tasks once every task it depends on has succeeded.
+    /// Each task is submitted like one from `run_retryable`, so its type's rule applies. A task
+    /// whose parent fails, or is rejected, is skipped along with everything that depends on it.
+    /// Returns what happened to every task once the workflow has finished.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the workflow has a cycle or depends on a task that is not in it.
+    pub async fn run_workflow(&self, workflow: Workflow) -> Result<Report> {
+        workflow.validate()?;
+        Ok(workflow::run(self, workflow).await)
+    }
+
+    /// Stores a job and schedules it to be run, retrying it according to its rule's retry policy
+    /// until it returns `Ok`. The job's state is kept in the scheduler's store as it runs, and a
+    /// job that has not finished when the process stops is run again by the next scheduler to
+    /// start with the same store, as long as its kind is registered with `Builder::job`. That
+    /// includes jobs that were queued, or cancelled by a shutdown.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the job cannot be serialized or stored, or if the scheduler has been
+    /// shut down.
+    pub async fn submit<T: Into<Type>, J: Job>(
+        &self,
+        typ: T,
+        job: J,
+    ) -> Result<TaskHandle<Result<()>>> {
         let typ = typ.into();
-        let cmd = Command::new(f);
+        let payload = serde_json::to_string(&job)?;
+        self.recovered
+            .clone()
+            .wait_for(|recovered| *recovered)
+            .await?;
+        let id = self.store.insert(J::KIND, &typ, &payload).await?;
+        let (cmd, handle) = store::command(job, id, self.store.clone(), self.store_errors.clone());
+        let res = self.send_job(typ, cmd, Some(id)).await?;
+        Ok(handle.with_response(res))
+    }
+
+    async fn send_task(&self, typ: Type, cmd: Command) -> Result<Response> {
+        self.send_job(typ, cmd, None).await
+    }
+
+    async fn send_job(&self, typ: Type, cmd: Command, job: Option<JobId>) -> Result<Response> {
         let (tx, rx) = oneshot::channel();
-        let req = TaskRequest::new(typ, cmd, tx);
+        let mut req = TaskRequest::new(typ, self.priority, cmd, tx);
+        req.job = job;
         let req = Request::Task(req);
         self.tx.send(req).await?;
         Ok(rx.await?)
     }
+
+    /// Registers a recurring task, with `factory` making a new future for each run. The rule for
+    /// the type must have `run_every` or `cron` set. With `run_every`, the task is run right away
+    /// and then again every `run_every`, and a run is skipped if the type is already at
+    /// `max_running` when it is due. With `cron`, the task is run whenever the schedule matches,
+    /// and the rule's `missed` policy decides what happens to runs that cannot start on time.
+    ///
+    /// The registration is rejected if the rule has neither, `run_every` is zero, the schedule
+    /// never matches again, or the type is already registered.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the scheduler has been shut down.
+    pub async fn register<T: Into<Type>, F, Fut>(&self, typ: T, factory: F) -> Result<Response>
+    where
+        F: Fn() -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let (tx, rx) = oneshot::channel();
+        let req = RegisterRequest {
+            typ: typ.into(),
+            factory: Factory::new(factory),
+            tx,
+        };
+        self.tx.send(Request::Register(req)).await?;
+        Ok(rx.await?)
+    }
 }
 
 pub struct Builder {
     hooks: Hooks,
     rules: Rules,
+    store: Option<Arc<dyn Store>>,
+    kinds: Kinds,
+    clock: fn() -> DateTime<Local>,
 }
 
 impl Builder {
@@ -76,6 +286,9 @@ impl Builder {
         Self {
             hooks: Hooks::default(),
             rules: Rules::default(),
+            store: None,
+            kinds: Kinds::default(),
+            clock: Local::now,
         }
     }
 
@@ -85,27 +298,91 @@ impl Builder {
         self
     }
 
+    /// Adds a callback for the scheduler's lifecycle hooks. Callbacks are called in the order
+    /// they were added.
     #[must_use]
     pub fn hooks(mut self, hooks: Box<impl Callback + Send + Sync + 'static>) -> Self {
-        self.hooks = Hooks(Some(hooks));
+        self.hooks.push(hooks);
+        self
+    }
+
+    /// Sets what happens when a hook returns an error. The default is to ignore it.
+    #[must_use]
+    pub fn on_hook_error(mut self, policy: FailurePolicy) -> Self {
+        self.hooks.set_policy(policy);
+        self
+    }
+
+    /// Sets where jobs submitted with `Scheduler::submit` are kept. The default keeps them in
+    /// memory, so they do not survive a restart.
+    #[must_use]
+    pub fn store(mut self, store: Arc<dyn Store>) -> Self {
+        self.store = Some(store);
+        self
+    }
+
+    /// Registers a kind of job, so that stored jobs of that kind which have not finished are run
+    /// again when the scheduler starts.
+    #[must_use]
+    pub fn job<J: Job>(mut self) -> Self {
+        self.kinds.register::<J>();
+        self
+    }
+
+    /// Sets how the scheduler tells the wall-clock time for cron schedules. Tests pause tokio's
+    /// clock, which `Local::now` knows nothing about.
+    #[cfg(test)]
+    #[must_use]
+    pub(crate) fn clock(mut self, clock: fn() -> DateTime<Local>) -> Self {
+        self.clock = clock;
         self
     }
 
     #[must_use]
     pub fn build(self) -> Scheduler {
-        Scheduler::new(self.hooks, self.rules)
+        let store = self.store.unwrap_or_else(|| Arc::new(MemoryStore::new()));
+        Scheduler::start(self.hooks, self.rules, store, self.kinds, self.clock)
     }
 }
 
 impl From<Box<dyn Callback + Send + Sync + 'static>> for Hooks {
     fn from(value: Box<dyn Callback + Send + Sync + 'static>) -> Self {
-        Hooks(Some(value))
+        let mut hooks = Hooks::default();
+        hooks.push(value);
+        hooks
+    }
+}
+
+/// `WeakScheduler` refers to a scheduler without keeping it running. The controller stops once
+/// every `Scheduler` for it has been dropped, whatever weak ones are left.
+pub(crate) struct WeakScheduler {
+    tx: mpsc::WeakSender<Request>,
+    priority: Priority,
+    store: Arc<dyn Store>,
+    store_errors: StoreErrors,
+    recovered: watch::Receiver<bool>,
+}
+
+impl WeakScheduler {
+    /// Returns a scheduler to use for a while, or `None` if every other one has been dropped.
+    pub(crate) fn upgrade(&self) -> Option<Scheduler> {
+        Some(Scheduler {
+            tx: Arc::new(self.tx.upgrade()?),
+            priority: self.priority,
+            store: self.store.clone(),
+            store_errors: self.store_errors.clone(),
+            recovered: self.recovered.clone(),
+        })
     }
 }
 
 pub(crate) enum Request {
     Task(TaskRequest),
+    Register(RegisterRequest),
     Wait(WaitRequest),
+    Shutdown(ShutdownRequest),
+    Stats(StatsRequest),
+    UpdateRules(UpdateRulesRequest),
 }
 
 /// Instructs the scheduler to wait for all currently running tasks to complete. Any other requests
@@ -116,25 +393,75 @@ pub(crate) struct WaitRequest {
     pub tx: oneshot::Sender<Response>,
 }
 
+/// Instructs the scheduler to stop, giving running tasks `grace` to finish.
+pub(crate) struct ShutdownRequest {
+    pub grace: Duration,
+    /// The scheduler will transmit the summary on this channel once every task has stopped.
+    pub tx: oneshot::Sender<Summary>,
+}
+
+/// Asks the scheduler for a snapshot of its stats.
+pub(crate) struct StatsRequest {
+    pub tx: oneshot::Sender<Stats>,
+}
+
+/// Asks the scheduler to replace its rules.
+pub(crate) struct UpdateRulesRequest {
+    pub rules: Rules,
+    pub tx: oneshot::Sender<Response>,
+}
+
 /// A request to run a particular command/task.
 pub(crate) struct TaskRequest {
     pub typ: Type,
+    pub priority: Priority,
     pub cmd: Command,
     pub tx: oneshot::Sender<Response>,
+    pub submitted: Instant,
+    /// The stored job the command runs, if any.
+    pub job: Option<JobId>,
 }
 
 impl TaskRequest {
-    pub(crate) fn new(task_id: Type, command: Command, tx: oneshot::Sender<Response>) -> Self {
+    pub(crate) fn new(
+        task_id: Type,
+        priority: Priority,
+        command: Command,
+        tx: oneshot::Sender<Response>,
+    ) -> Self {
         Self {
             typ: task_id,
+            priority,
             cmd: command,
             tx,
+            submitted: Instant::now(),
+            job: None,
         }
     }
 }
 
-#[derive(Debug, PartialEq)]
+/// A request to run a task type periodically, according to its rule.
+pub(crate) struct RegisterRequest {
+    pub typ: Type,
+    pub factory: Factory,
+    pub tx: oneshot::Sender<Response>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum Response {
     Accepted,
+    /// The task will run once a slot is free, unless it waits longer than the queue's timeout.
+    Queued,
     Rejected,
 }
+
+/// `Summary` is what happened to the tasks that had not finished when the scheduler shut down.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Summary {
+    /// Tasks that finished by themselves, within the grace period.
+    pub completed: usize,
+    /// Tasks that were still running at the end of the grace period and were cancelled.
+    pub aborted: usize,
+    /// Queued tasks that never ran.
+    pub dropped: usize,
+}
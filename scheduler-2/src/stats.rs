@@ -0,0 +1,117 @@
+use std::{collections::BTreeMap, fmt::Write, time::Duration};
+
+use crate::task::Type;
+
+/// The upper bounds of the task duration histogram's buckets, in seconds.
+pub const BUCKETS: [f64; 10] = [0.001, 0.005, 0.01, 0.05, 0.1, 0.5, 1.0, 5.0, 10.0, 60.0];
+
+/// A metric with a single value per type: its name, its Prometheus type and how to get the value.
+type Metric = (&'static str, &'static str, fn(&TypeStats) -> u64);
+
+/// `Stats` is a snapshot of what the scheduler is doing, from `Scheduler::stats`.
+#[derive(Clone, Debug, Default)]
+pub struct Stats {
+    /// Every type that has had a task submitted or run, by type.
+    pub types: BTreeMap<Type, TypeStats>,
+}
+
+impl Stats {
+    /// The number of tasks accepted across all types.
+    #[must_use]
+    pub fn accepted(&self) -> u64 {
+        self.types.values().map(|t| t.accepted).sum()
+    }
+
+    /// The number of tasks rejected across all types.
+    #[must_use]
+    pub fn rejected(&self) -> u64 {
+        self.types.values().map(|t| t.rejected).sum()
+    }
+
+    /// Formats the snapshot in the Prometheus text exposition format, with the task type as the
+    /// `type` label.
+    #[must_use]
+    pub fn to_prometheus(&self) -> String {
+        let mut out = String::new();
+        let metrics: [Metric; 4] = [
+            ("scheduler_tasks_running", "gauge", |t| t.running as u64),
+            ("scheduler_tasks_queued", "gauge", |t| t.queued as u64),
+            ("scheduler_tasks_accepted_total", "counter", |t| t.accepted),
+            ("scheduler_tasks_rejected_total", "counter", |t| t.rejected),
+        ];
+        for (name, kind, value) in metrics {
+            let _ = writeln!(out, "# TYPE {name} {kind}");
+            for (typ, stats) in &self.types {
+                let typ = label(typ);
+                let _ = writeln!(out, "{name}{{type=\"{typ}\"}} {}", value(stats));
+            }
+        }
+
+        let name = "scheduler_task_duration_seconds";
+        let _ = writeln!(out, "# TYPE {name} histogram");
+        for (typ, stats) in &self.types {
+            let typ = label(typ);
+            let hist = &stats.durations;
+            let mut count = 0;
+            for (le, n) in BUCKETS.iter().zip(hist.buckets) {
+                count += n;
+                let _ = writeln!(out, "{name}_bucket{{type=\"{typ}\",le=\"{le}\"}} {count}");
+            }
+            let _ = writeln!(
+                out,
+                "{name}_bucket{{type=\"{typ}\",le=\"+Inf\"}} {}",
+                hist.count
+            );
+            let _ = writeln!(out, "{name}_sum{{type=\"{typ}\"}} {}", hist.sum);
+            let _ = writeln!(out, "{name}_count{{type=\"{typ}\"}} {}", hist.count);
+        }
+        out
+    }
+}
+
+/// Escapes a task type for use as a label value.
+fn label(typ: &Type) -> String {
+    typ.to_string()
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// `TypeStats` is what the scheduler is doing with one task type.
+#[derive(Clone, Debug, Default)]
+pub struct TypeStats {
+    /// Tasks running now.
+    pub running: usize,
+    /// Tasks waiting in the type's queue now.
+    pub queued: usize,
+    /// Tasks submitted with `run_task` or `run_retryable` that have started, whether straight
+    /// away or after waiting in the queue. Recovered jobs count once they start too.
+    pub accepted: u64,
+    /// Tasks submitted with `run_task` or `run_retryable` that were turned away or gave up
+    /// waiting in the queue.
+    pub rejected: u64,
+    /// How long tasks took to finish, including all of their attempts.
+    pub durations: Histogram,
+}
+
+/// `Histogram` counts durations into the buckets in `BUCKETS`.
+#[derive(Clone, Debug, Default)]
+pub struct Histogram {
+    /// How many durations fell into each bucket, and no lower one. Longer durations than the
+    /// last bound are only in `count`.
+    pub buckets: [u64; BUCKETS.len()],
+    pub count: u64,
+    /// The total of all durations, in seconds.
+    pub sum: f64,
+}
+
+impl Histogram {
+    pub fn observe(&mut self, duration: Duration) {
+        let secs = duration.as_secs_f64();
+        if let Some(i) = BUCKETS.iter().position(|le| secs <= *le) {
+            self.buckets[i] += 1;
+        }
+        self.count += 1;
+        self.sum += secs;
+    }
+}
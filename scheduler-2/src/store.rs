@@ -0,0 +1,345 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt,
+    str::FromStr,
+    sync::{Arc, Mutex},
+};
+
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use serde::{de::DeserializeOwned, Serialize};
+use sqlx::{
+    sqlite::{SqliteConnectOptions, SqlitePoolOptions},
+    Pool, Row, Sqlite,
+};
+use tokio::sync::mpsc;
+
+use crate::{
+    command::Command,
+    task::{self, Handle, Type},
+};
+
+/// `Job` is a kind of task that can be stored, so that it still runs if the process restarts
+/// before it has finished. Jobs are submitted with `Scheduler::submit`, and each kind has to be
+/// registered with `scheduler::Builder::job` for stored jobs to be run again.
+///
+/// Jobs are run at least once: one that was running when the process stopped is run again from
+/// the start.
+#[async_trait]
+pub trait Job: Serialize + DeserializeOwned + Clone + Send + Sync + 'static {
+    /// The name jobs of this kind are stored under. It must not change once jobs have been
+    /// stored.
+    const KIND: &'static str;
+
+    /// Runs the job. An error fails the attempt, which is retried according to the type's rule.
+    async fn run(self) -> Result<()>;
+}
+
+/// `JobId` identifies a stored job.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct JobId(pub i64);
+
+impl fmt::Display for JobId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// `JobState` is where a stored job is in its life.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum JobState {
+    /// Submitted, and waiting to run.
+    Pending,
+    /// An attempt at the job has started.
+    Running,
+    Succeeded,
+    /// The last attempt at the job failed, panicked or timed out.
+    Failed,
+    /// The job was cancelled with its handle.
+    Cancelled,
+    /// The scheduler turned the job away.
+    Rejected,
+}
+
+impl JobState {
+    /// Whether the job still has to run.
+    #[must_use]
+    pub fn is_unfinished(self) -> bool {
+        matches!(self, JobState::Pending | JobState::Running)
+    }
+}
+
+impl fmt::Display for JobState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state = match self {
+            JobState::Pending => "pending",
+            JobState::Running => "running",
+            JobState::Succeeded => "succeeded",
+            JobState::Failed => "failed",
+            JobState::Cancelled => "cancelled",
+            JobState::Rejected => "rejected",
+        };
+        write!(f, "{state}")
+    }
+}
+
+impl FromStr for JobState {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s {
+            "pending" => JobState::Pending,
+            "running" => JobState::Running,
+            "succeeded" => JobState::Succeeded,
+            "failed" => JobState::Failed,
+            "cancelled" => JobState::Cancelled,
+            "rejected" => JobState::Rejected,
+            other => return Err(anyhow!("unknown job state: {other}")),
+        })
+    }
+}
+
+/// `StoredJob` is a job as it is kept in a `Store`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct StoredJob {
+    pub id: JobId,
+    pub kind: String,
+    pub typ: Type,
+    /// The job, serialized as JSON.
+    pub payload: String,
+    pub state: JobState,
+    /// How many attempts at the job have started.
+    pub attempts: u32,
+}
+
+/// `Store` is where the scheduler keeps jobs.
+#[async_trait]
+pub trait Store: Send + Sync {
+    /// Stores a new pending job and returns its id.
+    async fn insert(&self, kind: &str, typ: &Type, payload: &str) -> Result<JobId>;
+
+    /// Records that an attempt at the job has started.
+    async fn start_attempt(&self, id: JobId) -> Result<()>;
+
+    async fn set_state(&self, id: JobId, state: JobState) -> Result<()>;
+
+    async fn get(&self, id: JobId) -> Result<Option<StoredJob>>;
+
+    /// Returns the jobs that are pending or running, oldest first.
+    async fn unfinished(&self) -> Result<Vec<StoredJob>>;
+}
+
+/// `MemoryStore` keeps jobs in memory, so they only survive as long as the store does.
+#[derive(Default)]
+pub struct MemoryStore {
+    jobs: Mutex<BTreeMap<JobId, StoredJob>>,
+}
+
+impl MemoryStore {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn update(&self, id: JobId, f: impl FnOnce(&mut StoredJob)) -> Result<()> {
+        let mut jobs = self.jobs.lock().unwrap();
+        let job = jobs.get_mut(&id).ok_or_else(|| anyhow!("no job {id}"))?;
+        f(job);
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Store for MemoryStore {
+    async fn insert(&self, kind: &str, typ: &Type, payload: &str) -> Result<JobId> {
+        let mut jobs = self.jobs.lock().unwrap();
+        let id = JobId(jobs.keys().next_back().map_or(1, |id| id.0 + 1));
+        let job = StoredJob {
+            id,
+            kind: kind.to_string(),
+            typ: typ.clone(),
+            payload: payload.to_string(),
+            state: JobState::Pending,
+            attempts: 0,
+        };
+        jobs.insert(id, job);
+        Ok(id)
+    }
+
+    async fn start_attempt(&self, id: JobId) -> Result<()> {
+        self.update(id, |job| {
+            job.state = JobState::Running;
+            job.attempts += 1;
+        })
+    }
+
+    async fn set_state(&self, id: JobId, state: JobState) -> Result<()> {
+        self.update(id, |job| job.state = state)
+    }
+
+    async fn get(&self, id: JobId) -> Result<Option<StoredJob>> {
+        Ok(self.jobs.lock().unwrap().get(&id).cloned())
+    }
+
+    async fn unfinished(&self) -> Result<Vec<StoredJob>> {
+        let jobs = self.jobs.lock().unwrap();
+        let unfinished = jobs.values().filter(|job| job.state.is_unfinished());
+        Ok(unfinished.cloned().collect())
+    }
+}
+
+/// `SqliteStore` keeps jobs in a `jobs` table in a `SQLite` database, which it creates if needed.
+pub struct SqliteStore {
+    pool: Pool<Sqlite>,
+}
+
+impl SqliteStore {
+    /// Opens the database at the url, such as `sqlite://jobs.db`, creating it if it does not
+    /// exist.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the database cannot be opened or the table cannot be created.
+    pub async fn connect(url: &str) -> Result<Self> {
+        let options = SqliteConnectOptions::from_str(url)?.create_if_missing(true);
+        // an in-memory database only lives as long as its connection, so keep to one.
+        let pool = SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect_with(options)
+            .await?;
+        sqlx::query(
+            "CREATE TABLE IF NOT EXISTS jobs (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                kind TEXT NOT NULL,
+                type TEXT NOT NULL,
+                payload TEXT NOT NULL,
+                state TEXT NOT NULL,
+                attempts INTEGER NOT NULL DEFAULT 0
+            )",
+        )
+        .execute(&pool)
+        .await?;
+        Ok(Self { pool })
+    }
+
+    async fn fetch(&self, query: &str, id: Option<JobId>) -> Result<Vec<StoredJob>> {
+        let mut query = sqlx::query(query);
+        if let Some(id) = id {
+            query = query.bind(id.0);
+        }
+        let rows = query.fetch_all(&self.pool).await?;
+        rows.iter()
+            .map(|row| {
+                Ok(StoredJob {
+                    id: JobId(row.try_get("id")?),
+                    kind: row.try_get("kind")?,
+                    typ: Type::new(row.try_get::<String, _>("type")?),
+                    payload: row.try_get("payload")?,
+                    state: row.try_get::<String, _>("state")?.parse()?,
+                    attempts: row.try_get("attempts")?,
+                })
+            })
+            .collect()
+    }
+}
+
+#[async_trait]
+impl Store for SqliteStore {
+    async fn insert(&self, kind: &str, typ: &Type, payload: &str) -> Result<JobId> {
+        let res = sqlx::query("INSERT INTO jobs (kind, type, payload, state) VALUES (?, ?, ?, ?)")
+            .bind(kind)
+            .bind(typ.to_string())
+            .bind(payload)
+            .bind(JobState::Pending.to_string())
+            .execute(&self.pool)
+            .await?;
+        Ok(JobId(res.last_insert_rowid()))
+    }
+
+    async fn start_attempt(&self, id: JobId) -> Result<()> {
+        sqlx::query("UPDATE jobs SET state = ?, attempts = attempts + 1 WHERE id = ?")
+            .bind(JobState::Running.to_string())
+            .bind(id.0)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn set_state(&self, id: JobId, state: JobState) -> Result<()> {
+        sqlx::query("UPDATE jobs SET state = ? WHERE id = ?")
+            .bind(state.to_string())
+            .bind(id.0)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn get(&self, id: JobId) -> Result<Option<StoredJob>> {
+        let jobs = self
+            .fetch("SELECT * FROM jobs WHERE id = ?", Some(id))
+            .await?;
+        Ok(jobs.into_iter().next())
+    }
+
+    async fn unfinished(&self) -> Result<Vec<StoredJob>> {
+        self.fetch(
+            "SELECT * FROM jobs WHERE state IN ('pending', 'running') ORDER BY id",
+            None,
+        )
+        .await
+    }
+}
+
+/// Where the commands for stored jobs send the store's errors, to be passed to
+/// `Callback::on_store_error`.
+pub(crate) type StoreErrors = mpsc::Sender<anyhow::Error>;
+
+/// Makes the command that runs a stored job, recording each attempt in the store.
+pub(crate) fn command<J: Job>(
+    job: J,
+    id: JobId,
+    store: Arc<dyn Store>,
+    errors: StoreErrors,
+) -> (Command, Handle<Result<()>>) {
+    task::retryable(move || {
+        let (job, store, errors) = (job.clone(), store.clone(), errors.clone());
+        async move {
+            if let Err(err) = store.start_attempt(id).await {
+                let err = err.context(format!("could not record an attempt at job {id}"));
+                let _ = errors.send(err).await;
+            }
+            job.run().await
+        }
+    })
+}
+
+type Decode =
+    Box<dyn Fn(JobId, &str, Arc<dyn Store>, StoreErrors) -> Result<Command> + Send + Sync>;
+
+/// `Kinds` makes the commands for stored jobs, by the name of their kind.
+#[derive(Default)]
+pub(crate) struct Kinds(HashMap<&'static str, Decode>);
+
+impl Kinds {
+    pub(crate) fn register<J: Job>(&mut self) {
+        let decode = |id, payload: &str, store, errors| {
+            let job: J = serde_json::from_str(payload)?;
+            Ok(command(job, id, store, errors).0)
+        };
+        self.0.insert(J::KIND, Box::new(decode));
+    }
+
+    /// Makes the command for a stored job.
+    pub(crate) fn decode(
+        &self,
+        job: &StoredJob,
+        store: Arc<dyn Store>,
+        errors: StoreErrors,
+    ) -> Result<Command> {
+        let decode = self
+            .0
+            .get(job.kind.as_str())
+            .ok_or_else(|| anyhow!("unknown job kind {:?}", job.kind))?;
+        decode(job.id, &job.payload, store, errors)
+    }
+}
@@ -1,5 +1,30 @@
+use std::{
+    any::Any,
+    fmt,
+    future::Future,
+    panic::AssertUnwindSafe,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    task::{Context, Poll},
+};
+
+use futures::{
+    future::{AbortHandle, AbortRegistration, Abortable, Aborted},
+    FutureExt,
+};
+use tokio::sync::oneshot;
+
+use crate::{
+    command::{Attempt, Command},
+    hooks::Outcome,
+    scheduler::Response,
+};
+
 /// `TaskType` identifies the kind of task.
-#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct Type(String);
 
 impl Type {
@@ -11,6 +36,12 @@ impl Type {
     }
 }
 
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
 impl From<&str> for Type {
     fn from(id: &str) -> Self {
         Self(id.to_string())
@@ -22,3 +53,256 @@ impl From<String> for Type {
         Self(id)
     }
 }
+
+/// `Id` identifies one task, for hooks. Ids are unique within a scheduler.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Id(u64);
+
+impl Id {
+    pub(crate) fn new(id: u64) -> Self {
+        Self(id)
+    }
+}
+
+impl fmt::Display for Id {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// `Priority` decides which queued task runs first when a slot frees up. Higher priority tasks
+/// always go before lower priority ones, whatever their type.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Priority {
+    Low,
+    #[default]
+    Normal,
+    High,
+}
+
+/// `TaskHandle` is returned when a task is scheduled. Awaiting it gives the task's output once it
+/// has run. Dropping it leaves the task to run in the background.
+pub struct TaskHandle<T> {
+    response: Response,
+    rx: oneshot::Receiver<Result<T, TaskError>>,
+    cancel: Arc<Cancel>,
+}
+
+impl<T> TaskHandle<T> {
+    /// Whether the scheduler accepted, queued or rejected the task.
+    #[must_use]
+    pub fn response(&self) -> Response {
+        self.response
+    }
+
+    /// Cancels the task. A task that is running stops at its next await point, and a queued
+    /// task is dropped when its turn comes. Either way the handle resolves to
+    /// `TaskError::Cancelled`.
+    pub fn abort(&self) {
+        self.cancel.cancel();
+    }
+}
+
+impl<T> Future for TaskHandle<T> {
+    type Output = Result<T, TaskError>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        if self.response == Response::Rejected {
+            return Poll::Ready(Err(TaskError::Rejected));
+        }
+        Pin::new(&mut self.rx)
+            .poll(cx)
+            .map(|res| res.unwrap_or(Err(TaskError::Dropped)))
+    }
+}
+
+/// Makes a command that runs the future once and sends its output to the handle.
+pub(crate) fn once<F, T>(f: F) -> (Command, Handle<T>)
+where
+    F: Future<Output = T> + Send + 'static,
+    T: Send + 'static,
+{
+    let (tracker, handle) = Tracker::new();
+    let cancel = tracker.cancel.clone();
+    let cmd = Command::once(attempt(tracker, f, |_| true)).with_cancel(move || cancel.cancel());
+    (cmd, handle)
+}
+
+/// Makes a command that calls `f` for each attempt, which may be retried until it returns `Ok`.
+/// The output of the last attempt is sent to the handle.
+pub(crate) fn retryable<F, Fut, T, E>(f: F) -> (Command, Handle<Result<T, E>>)
+where
+    F: Fn() -> Fut + Send + 'static,
+    Fut: Future<Output = Result<T, E>> + Send + 'static,
+    T: Send + 'static,
+    E: Send + 'static,
+{
+    let (tracker, handle) = Tracker::new();
+    let cancel = tracker.cancel.clone();
+    let cmd = Command::retryable(move || attempt(tracker.clone(), f(), Result::is_ok))
+        .with_cancel(move || cancel.cancel());
+    (cmd, handle)
+}
+
+/// Wraps one attempt at a task so that it never panics, can be cancelled and records its output
+/// in the tracker. The attempt succeeds if `succeeded` says so.
+fn attempt<F, O>(tracker: Arc<Tracker<O>>, f: F, succeeded: fn(&O) -> bool) -> Attempt
+where
+    F: Future<Output = O> + Send + 'static,
+    O: Send + 'static,
+{
+    Box::pin(async move {
+        let Some(registration) = tracker.cancel.register() else {
+            tracker.set(Err(TaskError::Cancelled));
+            return Outcome::Cancelled;
+        };
+        // the scheduler only drops an attempt part way through when it runs for too long, and
+        // this is overwritten if it finishes. when the scheduler shuts down it cancels the
+        // attempt instead.
+        tracker.set(Err(TaskError::TimedOut));
+        let (res, outcome) =
+            match Abortable::new(AssertUnwindSafe(f).catch_unwind(), registration).await {
+                Ok(Ok(out)) => {
+                    let outcome = if succeeded(&out) {
+                        Outcome::Success
+                    } else {
+                        Outcome::Failed
+                    };
+                    (Ok(out), outcome)
+                }
+                Ok(Err(panic)) => {
+                    let msg = panic_message(panic.as_ref());
+                    (
+                        Err(TaskError::Panicked(msg.clone())),
+                        Outcome::Panicked(msg),
+                    )
+                }
+                Err(Aborted) => (Err(TaskError::Cancelled), Outcome::Cancelled),
+            };
+        tracker.set(res);
+        outcome
+    })
+}
+
+/// The parts of a `TaskHandle` made before the scheduler has responded.
+pub(crate) struct Handle<T> {
+    rx: oneshot::Receiver<Result<T, TaskError>>,
+    cancel: Arc<Cancel>,
+}
+
+impl<T> Handle<T> {
+    pub(crate) fn with_response(self, response: Response) -> TaskHandle<T> {
+        TaskHandle {
+            response,
+            rx: self.rx,
+            cancel: self.cancel,
+        }
+    }
+}
+
+/// Tracker holds the outcome of the latest attempt at a task. It is shared by the command and its
+/// attempts, and sends the outcome to the handle once they have all been dropped, which is when
+/// the scheduler is done with the task.
+struct Tracker<O> {
+    last: Mutex<Option<Result<O, TaskError>>>,
+    tx: Option<oneshot::Sender<Result<O, TaskError>>>,
+    cancel: Arc<Cancel>,
+}
+
+impl<O> Tracker<O> {
+    fn new() -> (Arc<Self>, Handle<O>) {
+        let (tx, rx) = oneshot::channel();
+        let cancel = Arc::new(Cancel::default());
+        let tracker = Self {
+            last: Mutex::new(None),
+            tx: Some(tx),
+            cancel: cancel.clone(),
+        };
+        (Arc::new(tracker), Handle { rx, cancel })
+    }
+
+    fn set(&self, res: Result<O, TaskError>) {
+        *self.last.lock().unwrap() = Some(res);
+    }
+}
+
+impl<O> Drop for Tracker<O> {
+    fn drop(&mut self) {
+        let res = self.last.get_mut().unwrap().take();
+        if let Some(tx) = self.tx.take() {
+            let _ = tx.send(res.unwrap_or(Err(TaskError::Dropped)));
+        }
+    }
+}
+
+/// Cancel lets a handle stop the running attempt at its task and any that would follow.
+#[derive(Default)]
+struct Cancel {
+    cancelled: AtomicBool,
+    current: Mutex<Option<AbortHandle>>,
+}
+
+impl Cancel {
+    fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+        if let Some(current) = &*self.current.lock().unwrap() {
+            current.abort();
+        }
+    }
+
+    /// Returns the registration for a new attempt, or `None` if the task has been cancelled.
+    fn register(&self) -> Option<AbortRegistration> {
+        let mut current = self.current.lock().unwrap();
+        if self.cancelled.load(Ordering::SeqCst) {
+            return None;
+        }
+        let (handle, registration) = AbortHandle::new_pair();
+        *current = Some(handle);
+        Some(registration)
+    }
+}
+
+fn panic_message(panic: &(dyn Any + Send)) -> String {
+    if let Some(msg) = panic.downcast_ref::<&str>() {
+        (*msg).to_string()
+    } else if let Some(msg) = panic.downcast_ref::<String>() {
+        msg.clone()
+    } else {
+        String::from("task panicked")
+    }
+}
+
+/// `TaskError` is why a task did not produce any output.
+#[derive(Debug, PartialEq)]
+pub enum TaskError {
+    /// The scheduler rejected the task.
+    Rejected,
+
+    /// The task was cancelled with `TaskHandle::abort`, or was still running when the scheduler's
+    /// shutdown grace period ran out.
+    Cancelled,
+
+    /// The task panicked with this message.
+    Panicked(String),
+
+    /// The task ran for longer than its rule's timeout.
+    TimedOut,
+
+    /// The scheduler dropped the task without running it, e.g. because it waited in a queue for
+    /// too long or was still queued when the scheduler shut down.
+    Dropped,
+}
+
+impl fmt::Display for TaskError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TaskError::Rejected => write!(f, "task was rejected"),
+            TaskError::Cancelled => write!(f, "task was cancelled"),
+            TaskError::Panicked(msg) => write!(f, "task panicked: {msg}"),
+            TaskError::TimedOut => write!(f, "task timed out"),
+            TaskError::Dropped => write!(f, "task was dropped without running"),
+        }
+    }
+}
+
+impl std::error::Error for TaskError {}
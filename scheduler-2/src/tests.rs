@@ -1,14 +1,22 @@
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex};
 use std::time::Duration;
 
-use crate::hooks::HookResult;
-use crate::rules::{Rule, Rules};
-use crate::scheduler::Response;
-use crate::task::Type;
+use crate::hooks::{FailurePolicy, HookResult, Outcome, TaskContext};
+use crate::limit::TokenBucket;
+use crate::rules::{self, Missed, Order, Queue, Rate, Retry, Rule, RuleError, Rules};
+use crate::rules_file::RulesFile;
+use crate::scheduler::{Response, Summary};
+use crate::store::{Job, JobId, JobState, MemoryStore, SqliteStore, Store, StoredJob};
+use crate::task::{Priority, TaskError, Type};
+use crate::workflow::{NodeId, NodeStatus, Workflow, WorkflowError};
 use crate::{hooks::Callback, scheduler::Scheduler};
 use anyhow::Result;
 use async_trait::async_trait;
-use tokio::time::sleep;
+use chrono::{DateTime, Local, TimeDelta};
+use serde::{Deserialize, Serialize};
+use tokio::time::{sleep, Instant};
 
 #[tokio::test]
 async fn test_scheduler() -> Result<()> {
@@ -71,14 +79,14 @@ async fn test_scheduler_rules() -> Result<()> {
             "foo",
             Rule {
                 max_running: count,
-                run_every: None,
+                ..Default::default()
             },
         )
         .rule(
             "bar",
             Rule {
                 max_running: 5,
-                run_every: None,
+                ..Default::default()
             },
         )
         .build();
@@ -95,7 +103,7 @@ async fn test_scheduler_rules() -> Result<()> {
                 let _ = tx.send(()).await;
             })
             .await?;
-        assert_eq!(res, Response::Accepted);
+        assert_eq!(res.response(), Response::Accepted);
     }
     // allow the tasks to run.
     drop(rx);
@@ -103,15 +111,1579 @@ async fn test_scheduler_rules() -> Result<()> {
     Ok(())
 }
 
+#[tokio::test(start_paused = true)]
+async fn test_scheduler_run_every() -> Result<()> {
+    let rules = Rules::builder()
+        .rule(
+            "tick",
+            Rule {
+                max_running: 1,
+                run_every: Some(Duration::from_secs(10)),
+                ..Default::default()
+            },
+        )
+        .build();
+    let sched = Scheduler::builder().rules(rules).build();
+    let runs = Arc::new(Mutex::new(0));
+    let counter = runs.clone();
+    let res = sched
+        .register("tick", move || {
+            let counter = counter.clone();
+            async move {
+                *counter.lock().unwrap() += 1;
+            }
+        })
+        .await?;
+    assert_eq!(res, Response::Accepted);
+
+    // runs right away, then at 10s, 20s and 30s.
+    sleep(Duration::from_secs(35)).await;
+    assert_eq!(4, *runs.lock().unwrap());
+    Ok(())
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_scheduler_run_every_skips_busy() -> Result<()> {
+    let hooks = TestHooks::new();
+    let rules = Rules::builder()
+        .rule(
+            "slow",
+            Rule {
+                max_running: 1,
+                run_every: Some(Duration::from_secs(10)),
+                ..Default::default()
+            },
+        )
+        .build();
+    let sched = Scheduler::builder()
+        .hooks(hooks.clone().into())
+        .rules(rules)
+        .build();
+    sched
+        .register("slow", || sleep(Duration::from_secs(15)))
+        .await?;
+
+    // each run takes 15s, so the runs due at 10s and 30s find the last one still going.
+    sleep(Duration::from_secs(45)).await;
+    assert_eq!(3, hooks.get_count());
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_scheduler_register_rejected() -> Result<()> {
+    let rules = Rules::builder()
+        .rule(
+            "tick",
+            Rule {
+                max_running: 1,
+                run_every: Some(Duration::from_mins(1)),
+                ..Default::default()
+            },
+        )
+        .build();
+    let sched = Scheduler::builder().rules(rules).build();
+
+    // the default rule has no run_every.
+    assert_eq!(
+        sched.register("once", || async {}).await?,
+        Response::Rejected
+    );
+    assert_eq!(
+        sched.register("tick", || async {}).await?,
+        Response::Accepted
+    );
+    assert_eq!(
+        sched.register("tick", || async {}).await?,
+        Response::Rejected
+    );
+    Ok(())
+}
+
+/// Runs one blocking task of type "queued" and then schedules `num` more, which have to queue
+/// behind it. Returns the responses and the order the queued tasks ran in.
+async fn run_queued(queue: Queue, num: usize) -> Result<(Vec<Response>, Vec<usize>)> {
+    let rules = Rules::builder()
+        .rule(
+            "queued",
+            Rule {
+                max_running: 1,
+                queue: Some(queue),
+                ..Default::default()
+            },
+        )
+        .build();
+    let sched = Scheduler::builder().rules(rules).build();
+    let (release, blocked) = tokio::sync::oneshot::channel::<()>();
+    let res = sched
+        .run_task("queued", async move {
+            let _ = blocked.await;
+        })
+        .await?;
+    assert_eq!(res.response(), Response::Accepted);
+
+    let ran = Arc::new(Mutex::new(vec![]));
+    let mut responses = vec![];
+    for i in 0..num {
+        let ran = ran.clone();
+        let res = sched
+            .run_task("queued", async move {
+                ran.lock().unwrap().push(i);
+            })
+            .await?;
+        responses.push(res.response());
+    }
+    let _ = release.send(());
+    sched.wait().await?;
+    let ran = ran.lock().unwrap().clone();
+    Ok((responses, ran))
+}
+
+#[tokio::test]
+async fn test_scheduler_queue_fifo() -> Result<()> {
+    let queue = Queue {
+        max_len: 3,
+        ..Default::default()
+    };
+    let (responses, ran) = run_queued(queue, 4).await?;
+    assert_eq!(
+        responses,
+        vec![
+            Response::Queued,
+            Response::Queued,
+            Response::Queued,
+            Response::Rejected
+        ]
+    );
+    assert_eq!(ran, vec![0, 1, 2]);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_scheduler_queue_lifo() -> Result<()> {
+    let queue = Queue {
+        max_len: 3,
+        order: Order::Lifo,
+        ..Default::default()
+    };
+    let (_, ran) = run_queued(queue, 3).await?;
+    assert_eq!(ran, vec![2, 1, 0]);
+    Ok(())
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_scheduler_queue_timeout() -> Result<()> {
+    let rules = Rules::builder()
+        .rule(
+            "slow",
+            Rule {
+                max_running: 1,
+                queue: Some(Queue {
+                    timeout: Some(Duration::from_secs(5)),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            },
+        )
+        .build();
+    let hooks = TestHooks::new();
+    let sched = Scheduler::builder()
+        .rules(rules)
+        .hooks(hooks.clone().into())
+        .build();
+    sched
+        .run_task("slow", sleep(Duration::from_secs(10)))
+        .await?;
+
+    // this one gives up waiting before the slot is free.
+    let ran = Arc::new(Mutex::new(false));
+    let flag = ran.clone();
+    let res = sched
+        .run_task("slow", async move {
+            *flag.lock().unwrap() = true;
+        })
+        .await?;
+    assert_eq!(res.response(), Response::Queued);
+    // it gives up when its timeout is over, not when the slot finally frees up.
+    let start = Instant::now();
+    assert_eq!(res.await, Err(TaskError::Dropped));
+    assert_eq!(start.elapsed().as_secs(), 5);
+    assert_eq!(*hooks.rejected.lock().unwrap(), [Type::from("slow")]);
+    // and counts as rejected rather than accepted.
+    let stats = sched.stats().await?;
+    let slow = &stats.types[&Type::from("slow")];
+    assert_eq!((slow.accepted, slow.rejected, slow.queued), (1, 1, 0));
+    sched.wait().await?;
+    assert!(!*ran.lock().unwrap());
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_task_handle_output() -> Result<()> {
+    let sched = Scheduler::builder().build();
+    let handle = sched.run_task("task", async { 40 + 2 }).await?;
+    assert_eq!(handle.await, Ok(42));
+
+    let handle = sched
+        .run_task("task", async {
+            panic!("task panic");
+        })
+        .await?;
+    assert_eq!(
+        handle.await,
+        Err::<(), _>(TaskError::Panicked(String::from("task panic")))
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_task_handle_rejected() -> Result<()> {
+    let sched = Scheduler::builder().build();
+    let (release, blocked) = tokio::sync::oneshot::channel::<()>();
+    let running = sched
+        .run_task("task", async move {
+            let _ = blocked.await;
+        })
+        .await?;
+    let rejected = sched.run_task("task", async {}).await?;
+    assert_eq!(rejected.response(), Response::Rejected);
+    assert_eq!(rejected.await, Err(TaskError::Rejected));
+    let _ = release.send(());
+    assert_eq!(running.await, Ok(()));
+    Ok(())
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_task_handle_abort() -> Result<()> {
+    let hooks = TestHooks::new();
+    let rules = Rules::builder()
+        .rule(
+            "task",
+            Rule {
+                max_running: 1,
+                queue: Some(Queue::default()),
+                ..Default::default()
+            },
+        )
+        .build();
+    let sched = Scheduler::builder()
+        .hooks(hooks.clone().into())
+        .rules(rules)
+        .build();
+    let running = sched
+        .run_task("task", sleep(Duration::from_hours(1)))
+        .await?;
+    let queued = sched.run_task("task", async { 1 }).await?;
+    assert_eq!(queued.response(), Response::Queued);
+
+    // cancelling the queued task means it is dropped when its turn comes.
+    queued.abort();
+    running.abort();
+    assert_eq!(running.await, Err(TaskError::Cancelled));
+    assert_eq!(queued.await, Err(TaskError::Cancelled));
+    sched.wait().await?;
+
+    // both slots were given back, so the type can run again.
+    let handle = sched.run_task("task", async { 2 }).await?;
+    assert_eq!(handle.response(), Response::Accepted);
+    assert_eq!(handle.await, Ok(2));
+    assert_eq!(hooks.get_count(), 3);
+    Ok(())
+}
+
+fn retry_rules(timeout: Option<Duration>) -> Rules {
+    Rules::builder()
+        .rule(
+            "flaky",
+            Rule {
+                max_running: 1,
+                timeout,
+                retry: Some(Retry {
+                    max_attempts: 3,
+                    backoff: Duration::from_secs(1),
+                    max_backoff: Duration::from_secs(10),
+                    jitter: false,
+                }),
+                ..Default::default()
+            },
+        )
+        .build()
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_task_timeout() -> Result<()> {
+    let hooks = TestHooks::new();
+    let rules = Rules::builder()
+        .rule(
+            "hangs",
+            Rule {
+                timeout: Some(Duration::from_secs(5)),
+                ..Default::default()
+            },
+        )
+        .build();
+    let sched = Scheduler::builder()
+        .hooks(hooks.clone().into())
+        .rules(rules)
+        .build();
+    let handle = sched
+        .run_task("hangs", sleep(Duration::from_hours(1)))
+        .await?;
+    assert_eq!(handle.await, Err(TaskError::TimedOut));
+    assert_eq!(*hooks.timeouts.lock().unwrap(), 1);
+
+    // the slot was freed.
+    let handle = sched.run_task("hangs", async {}).await?;
+    assert_eq!(handle.response(), Response::Accepted);
+    Ok(())
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_task_retry() -> Result<()> {
+    let hooks = TestHooks::new();
+    let sched = Scheduler::builder()
+        .hooks(hooks.clone().into())
+        .rules(retry_rules(None))
+        .build();
+    let attempts = Arc::new(Mutex::new(0));
+    let counter = attempts.clone();
+    let handle = sched
+        .run_retryable("flaky", move || {
+            let counter = counter.clone();
+            async move {
+                let mut attempts = counter.lock().unwrap();
+                *attempts += 1;
+                if *attempts < 2 {
+                    Err("not yet")
+                } else {
+                    Ok(*attempts)
+                }
+            }
+        })
+        .await?;
+    assert_eq!(handle.await, Ok(Ok(2)));
+    assert_eq!(
+        *hooks.retries.lock().unwrap(),
+        vec![(2, Duration::from_secs(1))]
+    );
+
+    // a task that never succeeds gives up after the last attempt, with its last error.
+    let handle = sched
+        .run_retryable("flaky", || async { Err::<(), _>("never") })
+        .await?;
+    assert_eq!(handle.await, Ok(Err("never")));
+    assert_eq!(
+        hooks.retries.lock().unwrap()[1..],
+        [(2, Duration::from_secs(1)), (3, Duration::from_secs(2))]
+    );
+    Ok(())
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_task_retry_after_timeout() -> Result<()> {
+    let hooks = TestHooks::new();
+    let sched = Scheduler::builder()
+        .hooks(hooks.clone().into())
+        .rules(retry_rules(Some(Duration::from_secs(5))))
+        .build();
+    let attempts = Arc::new(Mutex::new(0));
+    let counter = attempts.clone();
+    let handle = sched
+        .run_retryable("flaky", move || {
+            let counter = counter.clone();
+            async move {
+                let attempt = {
+                    let mut attempts = counter.lock().unwrap();
+                    *attempts += 1;
+                    *attempts
+                };
+                // only the last attempt is quick enough.
+                if attempt < 3 {
+                    sleep(Duration::from_mins(1)).await;
+                }
+                Ok::<_, ()>(attempt)
+            }
+        })
+        .await?;
+    assert_eq!(handle.await, Ok(Ok(3)));
+    assert_eq!(*hooks.timeouts.lock().unwrap(), 2);
+    Ok(())
+}
+
+#[test]
+fn test_retry_delay() {
+    let mut retry = Retry {
+        max_attempts: 10,
+        backoff: Duration::from_millis(100),
+        max_backoff: Duration::from_secs(1),
+        jitter: false,
+    };
+    assert_eq!(retry.delay(1), Duration::from_millis(100));
+    assert_eq!(retry.delay(2), Duration::from_millis(200));
+    assert_eq!(retry.delay(4), Duration::from_millis(800));
+    assert_eq!(retry.delay(5), Duration::from_secs(1));
+    assert_eq!(retry.delay(100), Duration::from_secs(1));
+
+    retry.jitter = true;
+    for _ in 0..100 {
+        let delay = retry.delay(3);
+        assert!(delay >= Duration::from_millis(200) && delay <= Duration::from_millis(400));
+    }
+}
+
+#[tokio::test]
+async fn test_scheduler_max_running() -> Result<()> {
+    let rules = Rules::builder().max_running(2).build();
+    let sched = Scheduler::builder().rules(rules).build();
+    let (release, blocked) = tokio::sync::watch::channel(());
+    for (typ, expected) in [
+        ("a", Response::Accepted),
+        ("b", Response::Accepted),
+        ("c", Response::Rejected),
+    ] {
+        let mut blocked = blocked.clone();
+        let res = sched
+            .run_task(typ, async move {
+                let _ = blocked.changed().await;
+            })
+            .await?;
+        assert_eq!(res.response(), expected, "for {typ}");
+    }
+    drop(release);
+    sched.wait().await?;
+    Ok(())
+}
+
+#[test]
+fn test_rules_cron_validation() {
+    let cron = |expr: &str| Rule {
+        cron: Some(expr.to_string()),
+        ..Default::default()
+    };
+    assert!(Rules::builder()
+        .rule("nightly", cron("0 0 2 * * Mon-Fri"))
+        .try_build()
+        .is_ok());
+
+    let err = Rules::builder()
+        .rule("bad", cron("at two"))
+        .try_build()
+        .err();
+    assert!(matches!(err, Some(RuleError::InvalidCron { typ, .. }) if typ == Type::from("bad")));
+
+    let both = Rule {
+        run_every: Some(Duration::from_secs(1)),
+        ..cron("* * * * * *")
+    };
+    assert_eq!(
+        Rules::builder().rule("both", both).try_build().err(),
+        Some(RuleError::CronAndRunEvery(Type::from("both")))
+    );
+    assert_eq!(
+        Rules::builder()
+            .default(cron("* * * * * *"))
+            .try_build()
+            .err(),
+        Some(RuleError::DefaultCron)
+    );
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_scheduler_cron() -> Result<()> {
+    let rules = Rules::builder()
+        .rule(
+            "tick",
+            Rule {
+                cron: Some(String::from("*/10 * * * * *")),
+                ..Default::default()
+            },
+        )
+        .build();
+    let sched = Scheduler::builder()
+        .rules(rules)
+        .clock(paused_clock)
+        .build();
+    let count = Arc::new(Mutex::new(0));
+    let counter = count.clone();
+    let res = sched
+        .register("tick", move || {
+            *counter.lock().unwrap() += 1;
+            async {}
+        })
+        .await?;
+    assert_eq!(res, Response::Accepted);
+
+    // a cron type does not run right away, but every ten seconds on the clock.
+    assert_eq!(*count.lock().unwrap(), 0);
+    sleep(Duration::from_secs(35)).await;
+    assert!((3..=4).contains(&*count.lock().unwrap()));
+    Ok(())
+}
+
+/// A wall clock that follows tokio's clock, which the cron tests pause and advance.
+fn paused_clock() -> DateTime<Local> {
+    thread_local! {
+        static START: (DateTime<Local>, Instant) = (Local::now(), Instant::now());
+    }
+    START.with(|(wall, instant)| *wall + TimeDelta::from_std(instant.elapsed()).unwrap_or_default())
+}
+
+/// Runs a cron type every ten seconds whose first run takes 45 seconds, and returns how many
+/// runs have started 66 seconds after the first one did.
+async fn cron_runs(missed: Missed) -> Result<usize> {
+    let rules = Rules::builder()
+        .rule(
+            "slow",
+            Rule {
+                cron: Some(String::from("*/10 * * * * *")),
+                missed,
+                ..Default::default()
+            },
+        )
+        .build();
+    let sched = Scheduler::builder()
+        .rules(rules)
+        .clock(paused_clock)
+        .build();
+    let count = Arc::new(Mutex::new(0));
+    let counter = count.clone();
+    sched
+        .register("slow", move || {
+            let mut count = counter.lock().unwrap();
+            *count += 1;
+            let first = *count == 1;
+            async move {
+                if first {
+                    sleep(Duration::from_secs(45)).await;
+                }
+            }
+        })
+        .await?;
+    while *count.lock().unwrap() == 0 {
+        sleep(Duration::from_secs(1)).await;
+    }
+    sleep(Duration::from_secs(65)).await;
+    let runs = *count.lock().unwrap();
+    Ok(runs)
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_scheduler_cron_missed() -> Result<()> {
+    // the runs due at 10s to 40s are skipped while the first is running, leaving the ones at 50s
+    // and 60s.
+    assert_eq!(cron_runs(Missed::Skip).await?, 3);
+    // two of the runs missed while the first is running are made up as soon as it is done.
+    assert_eq!(cron_runs(Missed::CatchUp(2)).await?, 5);
+    // and all four of them with a higher limit.
+    assert_eq!(cron_runs(Missed::CatchUp(10)).await?, 7);
+    Ok(())
+}
+
+/// Starts a task that holds the only slot, then queues the given tasks behind it. Returns the
+/// order the queued tasks ran in once the slot is free.
+async fn dispatch_order(rules: Rules, tasks: Vec<(&'static str, Priority)>) -> Result<Vec<String>> {
+    let sched = Scheduler::builder().rules(rules).build();
+    let (release, blocked) = tokio::sync::oneshot::channel::<()>();
+    sched
+        .run_task("block", async move {
+            let _ = blocked.await;
+        })
+        .await?;
+    let ran = Arc::new(Mutex::new(vec![]));
+    for (typ, priority) in tasks {
+        let ran = ran.clone();
+        let res = sched
+            .with_priority(priority)
+            .run_task(typ, async move {
+                ran.lock().unwrap().push(format!("{typ}:{priority:?}"));
+            })
+            .await?;
+        assert_eq!(res.response(), Response::Queued);
+    }
+    let _ = release.send(());
+    sched.wait().await?;
+    let ran = ran.lock().unwrap().clone();
+    Ok(ran)
+}
+
+fn queued_rules() -> rules::Builder {
+    Rules::builder().max_running(1).default(Rule {
+        max_running: 10,
+        queue: Some(Queue::default()),
+        ..Default::default()
+    })
+}
+
+#[tokio::test]
+async fn test_scheduler_priority() -> Result<()> {
+    let tasks = vec![
+        ("a", Priority::Low),
+        ("a", Priority::Normal),
+        ("b", Priority::Low),
+        ("b", Priority::High),
+        ("a", Priority::High),
+    ];
+    let ran = dispatch_order(queued_rules().build(), tasks).await?;
+    let mut high = ran[..2].to_vec();
+    high.sort();
+    assert_eq!(high, ["a:High", "b:High"]);
+    assert_eq!(ran[2], "a:Normal");
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_scheduler_weighted_fairness() -> Result<()> {
+    let rules = queued_rules()
+        .rule(
+            "heavy",
+            Rule {
+                max_running: 10,
+                queue: Some(Queue::default()),
+                weight: 2,
+                ..Default::default()
+            },
+        )
+        .build();
+    // the flood of heavy tasks is queued before any of the light ones.
+    let mut tasks = vec![("heavy", Priority::Normal); 30];
+    tasks.extend(vec![("light", Priority::Normal); 10]);
+    let ran = dispatch_order(rules, tasks).await?;
+
+    // while both are queued, heavy gets two turns for every one of light's.
+    let heavy = ran[..15].iter().filter(|t| t.starts_with("heavy")).count();
+    assert!((9..=11).contains(&heavy), "{ran:?}");
+    Ok(())
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_scheduler_shutdown() -> Result<()> {
+    let rules = Rules::builder()
+        .rule(
+            "task",
+            Rule {
+                max_running: 1,
+                queue: Some(Queue::default()),
+                ..Default::default()
+            },
+        )
+        .build();
+    let sched = Scheduler::builder().rules(rules).build();
+    let quick = sched
+        .run_task("task", async {
+            sleep(Duration::from_secs(1)).await;
+            1
+        })
+        .await?;
+    let queued = sched.run_task("task", async { 2 }).await?;
+    let slow = sched
+        .run_task("slow", sleep(Duration::from_hours(1)))
+        .await?;
+    assert_eq!(queued.response(), Response::Queued);
+
+    let summary = sched.shutdown(Duration::from_secs(10)).await?;
+    assert_eq!(
+        summary,
+        Summary {
+            completed: 1,
+            aborted: 1,
+            dropped: 1,
+        }
+    );
+    assert_eq!(quick.await, Ok(1));
+    assert_eq!(queued.await, Err(TaskError::Dropped));
+    assert_eq!(slow.await.err(), Some(TaskError::Cancelled));
+
+    // nothing more is accepted.
+    assert!(sched.run_task("task", async {}).await.is_err());
+    assert!(sched.shutdown(Duration::ZERO).await.is_err());
+    Ok(())
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_scheduler_dropped() -> Result<()> {
+    let hooks = TestHooks::new();
+    let rules = Rules::builder()
+        .rule(
+            "task",
+            Rule {
+                max_running: 1,
+                queue: Some(Queue::default()),
+                ..Default::default()
+            },
+        )
+        .build();
+    let sched = Scheduler::builder()
+        .hooks(hooks.clone().into())
+        .rules(rules)
+        .build();
+    let running = sched
+        .run_task("task", sleep(Duration::from_secs(1)))
+        .await?;
+    let queued = sched.run_task("task", async { 2 }).await?;
+    drop(sched);
+
+    // the tasks that were already submitted still run.
+    assert_eq!(running.await, Ok(()));
+    assert_eq!(queued.await, Ok(2));
+
+    // and then the controller exits, dropping its hooks.
+    while Arc::strong_count(&hooks.count) > 1 {
+        sleep(Duration::from_millis(10)).await;
+    }
+    assert_eq!(hooks.get_count(), 2);
+    Ok(())
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_scheduler_dropped_rate_limited() -> Result<()> {
+    let rules = Rules::builder()
+        .rule(
+            "task",
+            Rule {
+                max_running: 10,
+                rate: Some(Rate {
+                    per_sec: 1.0,
+                    burst: 1,
+                }),
+                queue: Some(Queue::default()),
+                ..Default::default()
+            },
+        )
+        .build();
+    let sched = Scheduler::builder().rules(rules).build();
+    let start = Instant::now();
+    let first = sched.run_task("task", async { 1 }).await?;
+    let queued = sched.run_task("task", async { 2 }).await?;
+    assert_eq!(queued.response(), Response::Queued);
+    drop(sched);
+
+    // nothing is running when the scheduler is dropped, but the queued task still gets its turn
+    // once the rate allows it.
+    assert_eq!(first.await, Ok(1));
+    assert_eq!(queued.await, Ok(2));
+    assert_eq!(start.elapsed(), Duration::from_secs(1));
+    Ok(())
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_scheduler_stats() -> Result<()> {
+    let rules = Rules::builder()
+        .rule(
+            "task",
+            Rule {
+                max_running: 1,
+                queue: Some(Queue::default()),
+                ..Default::default()
+            },
+        )
+        .rule(
+            "never",
+            Rule {
+                max_running: 0,
+                ..Default::default()
+            },
+        )
+        .build();
+    let sched = Scheduler::builder().rules(rules).build();
+    for _ in 0..2 {
+        sched
+            .run_task("task", async { sleep(Duration::from_secs(2)).await })
+            .await?;
+    }
+    sched.run_task("never", async {}).await?;
+
+    let stats = sched.stats().await?;
+    let task = &stats.types[&Type::from("task")];
+    assert_eq!((task.running, task.queued), (1, 1));
+    // the queued task only counts as accepted once it starts.
+    assert_eq!((stats.accepted(), stats.rejected()), (1, 1));
+    assert_eq!(stats.types[&Type::from("never")].rejected, 1);
+
+    sched.wait().await?;
+    let stats = sched.stats().await?;
+    let task = &stats.types[&Type::from("task")];
+    assert_eq!((task.running, task.queued), (0, 0));
+    assert_eq!(task.accepted, 2);
+    assert_eq!(task.durations.count, 2);
+    // both took two seconds, which is in the bucket up to five.
+    assert_eq!(task.durations.buckets[7], 2);
+
+    let text = stats.to_prometheus();
+    for line in [
+        "# TYPE scheduler_tasks_running gauge",
+        "scheduler_tasks_accepted_total{type=\"task\"} 2",
+        "scheduler_tasks_rejected_total{type=\"never\"} 1",
+        "scheduler_task_duration_seconds_bucket{type=\"task\",le=\"1\"} 0",
+        "scheduler_task_duration_seconds_bucket{type=\"task\",le=\"5\"} 2",
+        "scheduler_task_duration_seconds_bucket{type=\"task\",le=\"+Inf\"} 2",
+        "scheduler_task_duration_seconds_sum{type=\"task\"} 4",
+    ] {
+        assert!(text.lines().any(|l| l == line), "{line} not in:\n{text}");
+    }
+    Ok(())
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_hooks_context() -> Result<()> {
+    let hooks = TestHooks::new();
+    let rules = Rules::builder()
+        .rule(
+            "never",
+            Rule {
+                max_running: 0,
+                ..Default::default()
+            },
+        )
+        .build();
+    let sched = Scheduler::builder()
+        .hooks(hooks.clone().into())
+        .rules(rules)
+        .build();
+    sched
+        .run_task("ok", async { sleep(Duration::from_secs(1)).await })
+        .await?
+        .await?;
+    let _ = sched
+        .run_task("panics", async { panic!("oops") })
+        .await?
+        .await;
+    let cancelled = sched
+        .run_task("cancelled", sleep(Duration::from_hours(1)))
+        .await?;
+    cancelled.abort();
+    let _ = cancelled.await;
+    let _ = sched.run_task("never", async {}).await?.await;
+    sched.wait().await?;
+
+    let outcomes = hooks.outcomes.lock().unwrap().clone();
+    let by_type: Vec<_> = outcomes
+        .iter()
+        .map(|(ctx, outcome)| (ctx.typ.to_string(), outcome.clone()))
+        .collect();
+    assert_eq!(
+        by_type,
+        [
+            (String::from("ok"), Outcome::Success),
+            (
+                String::from("panics"),
+                Outcome::Panicked(String::from("oops"))
+            ),
+            (String::from("cancelled"), Outcome::Cancelled),
+        ]
+    );
+    let ids: HashSet<_> = outcomes.iter().map(|(ctx, _)| ctx.id).collect();
+    assert_eq!(ids.len(), 3);
+    for (ctx, _) in &outcomes {
+        assert!(ctx.started.is_some_and(|started| started >= ctx.submitted));
+    }
+    assert_eq!(*hooks.rejected.lock().unwrap(), [Type::from("never")]);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_hooks_composed() -> Result<()> {
+    let (sched, log) = recorded(FailurePolicy::Ignore);
+    sched.run_task("good", async {}).await?.await?;
+    // both hooks are called even though the first fails, the first error is passed on to both,
+    // and the task still runs.
+    let res = sched.run_task("bad", async { 1 }).await?;
+    assert_eq!(res.response(), Response::Accepted);
+    assert_eq!(res.await, Ok(1));
+    assert_eq!(
+        *log.lock().unwrap(),
+        [
+            "first start good",
+            "second start good",
+            "first complete good",
+            "second complete good",
+            "first start bad",
+            "second start bad",
+            "first failed on_task_start: first refuses bad tasks",
+            "second failed on_task_start: first refuses bad tasks",
+            "first complete bad",
+            "second complete bad",
+        ]
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_hooks_reject_task() -> Result<()> {
+    let (sched, log) = recorded(FailurePolicy::RejectTask);
+    let res = sched.run_task("bad", async { 1 }).await?;
+    assert_eq!(res.response(), Response::Rejected);
+    assert_eq!(res.await, Err(TaskError::Rejected));
+    assert_eq!(
+        *log.lock().unwrap(),
+        [
+            "first start bad",
+            "second start bad",
+            "first failed on_task_start: first refuses bad tasks",
+            "second failed on_task_start: first refuses bad tasks",
+            "first rejected bad",
+            "second rejected bad"
+        ]
+    );
+
+    // other tasks still run.
+    assert_eq!(sched.run_task("good", async { 2 }).await?.await, Ok(2));
+    Ok(())
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_hooks_stop() -> Result<()> {
+    let (sched, _) = recorded(FailurePolicy::Stop);
+    let running = sched
+        .run_task("good", sleep(Duration::from_hours(1)))
+        .await?;
+    let res = sched.run_task("bad", async {}).await?;
+    assert_eq!(res.response(), Response::Rejected);
+
+    // the scheduler shuts down, cancelling what was running.
+    assert_eq!(running.await, Err(TaskError::Cancelled));
+    assert!(sched.run_task("good", async {}).await.is_err());
+    Ok(())
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_scheduler_rate_reject() -> Result<()> {
+    let rules = Rules::builder()
+        .rule(
+            "api",
+            Rule {
+                max_running: 100,
+                rate: Some(Rate {
+                    per_sec: 10.0,
+                    burst: 2,
+                }),
+                ..Default::default()
+            },
+        )
+        .build();
+    let sched = Scheduler::builder().rules(rules).build();
+    let mut responses = vec![];
+    for _ in 0..4 {
+        responses.push(sched.run_task("api", async {}).await?.response());
+    }
+    assert_eq!(
+        responses,
+        [
+            Response::Accepted,
+            Response::Accepted,
+            Response::Rejected,
+            Response::Rejected
+        ]
+    );
+
+    // a tenth of a second later there is one more token.
+    sleep(Duration::from_millis(100)).await;
+    let res = sched.run_task("api", async {}).await?;
+    assert_eq!(res.response(), Response::Accepted);
+    let res = sched.run_task("api", async {}).await?;
+    assert_eq!(res.response(), Response::Rejected);
+    Ok(())
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_scheduler_rate_queue() -> Result<()> {
+    let rules = Rules::builder()
+        .rule(
+            "api",
+            Rule {
+                max_running: 100,
+                rate: Some(Rate::per_sec(10.0)),
+                queue: Some(Queue::default()),
+                ..Default::default()
+            },
+        )
+        .build();
+    let sched = Scheduler::builder().rules(rules).build();
+    let starts = Arc::new(Mutex::new(vec![]));
+    for _ in 0..5 {
+        let starts = starts.clone();
+        sched
+            .run_task("api", async move {
+                starts.lock().unwrap().push(Instant::now());
+            })
+            .await?;
+    }
+    sched.wait().await?;
+
+    // the queued tasks start a tenth of a second apart.
+    let starts = starts.lock().unwrap().clone();
+    assert_eq!(starts.len(), 5);
+    for pair in starts.windows(2) {
+        let gap = pair[1] - pair[0];
+        assert!(
+            gap >= Duration::from_millis(100) && gap < Duration::from_millis(102),
+            "{gap:?}"
+        );
+    }
+    Ok(())
+}
+
+#[test]
+fn test_rules_rate_validation() {
+    let rate = |per_sec, burst| {
+        Rules::builder()
+            .rule(
+                "api",
+                Rule {
+                    rate: Some(Rate { per_sec, burst }),
+                    ..Default::default()
+                },
+            )
+            .try_build()
+            .err()
+    };
+    assert_eq!(rate(10.0, 1), None);
+    assert_eq!(
+        rate(0.0, 1),
+        Some(RuleError::InvalidRate(Some("api".into())))
+    );
+    assert_eq!(
+        rate(f64::NAN, 1),
+        Some(RuleError::InvalidRate(Some("api".into())))
+    );
+    assert_eq!(
+        rate(1.0, 0),
+        Some(RuleError::InvalidRate(Some("api".into())))
+    );
+}
+
+#[test]
+fn test_token_bucket_slow_rate() {
+    let now = Instant::now();
+    let rate = Rate::per_sec(f64::MIN_POSITIVE);
+    let mut bucket = TokenBucket::new(rate, now);
+    assert!(bucket.take(rate, now));
+    let ready = bucket.ready_at(rate, now);
+    assert!(ready > now + Duration::from_hours(365 * 24));
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_scheduler_update_rules() -> Result<()> {
+    let hooks = TestHooks::new();
+    let limited = |max| {
+        Rules::builder()
+            .max_running(max)
+            .default(Rule {
+                max_running: 10,
+                queue: Some(Queue::default()),
+                ..Default::default()
+            })
+            .build()
+    };
+    let sched = Scheduler::builder()
+        .hooks(hooks.clone().into())
+        .rules(
+            Rules::builder()
+                .max_running(2)
+                .default(Rule {
+                    max_running: 10,
+                    queue: Some(Queue::default()),
+                    ..Default::default()
+                })
+                .rule(
+                    "tick",
+                    Rule {
+                        run_every: Some(Duration::from_secs(1)),
+                        ..Default::default()
+                    },
+                )
+                .build(),
+        )
+        .build();
+    let ticks = Arc::new(Mutex::new(0));
+    let counter = ticks.clone();
+    sched
+        .register("tick", move || {
+            let counter = counter.clone();
+            async move { *counter.lock().unwrap() += 1 }
+        })
+        .await?;
+    sleep(Duration::from_millis(1500)).await;
+    assert_eq!(*ticks.lock().unwrap(), 2);
+    for _ in 0..2 {
+        sched
+            .run_task("task", async { sleep(Duration::from_secs(10)).await })
+            .await?;
+    }
+
+    // lowering the limit leaves both tasks running, but new ones have to wait.
+    assert_eq!(sched.update_rules(limited(1)).await?, Response::Accepted);
+    let waiting = sched.run_task("task", async {}).await?;
+    assert_eq!(waiting.response(), Response::Queued);
+    let stats = sched.stats().await?;
+    let task = &stats.types[&Type::from("task")];
+    assert_eq!((task.running, task.queued), (2, 1));
+
+    // raising it starts the waiting task straight away.
+    sched.update_rules(limited(3)).await?;
+    assert_eq!(waiting.await, Ok(()));
+    assert_eq!(*hooks.updates.lock().unwrap(), [Some(1), Some(3)]);
+
+    // "tick" no longer has run_every, so it stopped being recurring.
+    sleep(Duration::from_secs(5)).await;
+    assert_eq!(*ticks.lock().unwrap(), 2);
+
+    sched.shutdown(Duration::ZERO).await?;
+    assert!(sched.update_rules(limited(1)).await.is_err());
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_rules_file() -> Result<()> {
+    let path = std::env::temp_dir().join(format!("scheduler-rules-{}.toml", std::process::id()));
+    std::fs::write(
+        &path,
+        r#"
+            max_running = 4
+
+            [types.task]
+            max_running = 1
+            timeout_secs = 1.5
+            queue = { max_len = 2, order = "lifo" }
+            retry = { max_attempts = 5, jitter = false }
+        "#,
+    )?;
+    let errors = Arc::new(Mutex::new(vec![]));
+    let file = RulesFile::new(&path)
+        .interval(Duration::from_millis(10))
+        .on_error({
+            let errors = errors.clone();
+            move |err| errors.lock().unwrap().push(err.to_string())
+        });
+    let rules = file.load().await?;
+    assert_eq!(rules.max_running(), Some(4));
+    let rule = rules.get(&Type::from("task"));
+    assert_eq!(rule.max_running, 1);
+    assert_eq!(rule.timeout, Some(Duration::from_millis(1500)));
+    let queue = rule.queue.as_ref().unwrap();
+    assert_eq!((queue.max_len, queue.order), (2, Order::Lifo));
+    let retry = rule.retry.as_ref().unwrap();
+    assert_eq!((retry.max_attempts, retry.jitter), (5, false));
+
+    let hooks = TestHooks::new();
+    let sched = Scheduler::builder()
+        .hooks(hooks.clone().into())
+        .rules(rules)
+        .build();
+    let watcher = file.watch(&sched);
+    sleep(Duration::from_millis(50)).await;
+
+    // a file that does not parse is ignored, and the next good one is applied.
+    std::fs::write(&path, "max_running = \"lots\"")?;
+    sleep(Duration::from_millis(100)).await;
+    std::fs::write(&path, "max_running = 8\n")?;
+    while hooks.updates.lock().unwrap().is_empty() {
+        sleep(Duration::from_millis(10)).await;
+    }
+    assert_eq!(*hooks.updates.lock().unwrap(), [Some(8)]);
+    assert_eq!(
+        *errors.lock().unwrap(),
+        [format!("parsing {}", path.display())]
+    );
+
+    // the watcher stops with the scheduler.
+    sched.shutdown(Duration::ZERO).await?;
+    std::fs::write(&path, "max_running = 2\n")?;
+    watcher.await?;
+
+    // and does not keep it running once the other handles are dropped.
+    let sched = Scheduler::builder().build();
+    let watcher = RulesFile::new(&path)
+        .interval(Duration::from_millis(10))
+        .watch(&sched);
+    drop(sched);
+    tokio::time::timeout(Duration::from_secs(1), watcher).await??;
+    let _ = std::fs::remove_file(&path);
+    Ok(())
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+struct Nap {
+    secs: u64,
+    fail: bool,
+}
+
+#[async_trait]
+impl Job for Nap {
+    const KIND: &'static str = "nap";
+
+    async fn run(self) -> Result<()> {
+        sleep(Duration::from_secs(self.secs)).await;
+        if self.fail {
+            anyhow::bail!("failed");
+        }
+        Ok(())
+    }
+}
+
+async fn job_status(store: &dyn Store, id: JobId) -> Result<(JobState, u32)> {
+    let job = store.get(id).await?.expect("job is stored");
+    Ok((job.state, job.attempts))
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_scheduler_jobs_restart() -> Result<()> {
+    let store = Arc::new(MemoryStore::new());
+    let sched = Scheduler::builder()
+        .store(store.clone())
+        .job::<Nap>()
+        .build();
+    let ok = sched
+        .submit(
+            "job",
+            Nap {
+                secs: 1,
+                fail: false,
+            },
+        )
+        .await?;
+    assert_eq!(ok.response(), Response::Accepted);
+    ok.await??;
+    let failed = sched
+        .submit(
+            "job",
+            Nap {
+                secs: 1,
+                fail: true,
+            },
+        )
+        .await?;
+    assert!(failed.await?.is_err());
+    let cancelled = sched
+        .submit(
+            "job",
+            Nap {
+                secs: 60,
+                fail: false,
+            },
+        )
+        .await?;
+    sleep(Duration::from_secs(1)).await;
+    cancelled.abort();
+    let _ = cancelled.await;
+    let slow = sched
+        .submit(
+            "job",
+            Nap {
+                secs: 3600,
+                fail: false,
+            },
+        )
+        .await?;
+    sleep(Duration::from_secs(1)).await;
+    let summary = sched.shutdown(Duration::ZERO).await?;
+    assert_eq!(summary.aborted, 1);
+    assert_eq!(slow.await.err(), Some(TaskError::Cancelled));
+
+    let (ok, failed, cancelled, slow) = (JobId(1), JobId(2), JobId(3), JobId(4));
+    assert_eq!(job_status(&*store, ok).await?, (JobState::Succeeded, 1));
+    assert_eq!(job_status(&*store, failed).await?, (JobState::Failed, 1));
+    assert_eq!(
+        job_status(&*store, cancelled).await?,
+        (JobState::Cancelled, 1)
+    );
+    assert_eq!(job_status(&*store, slow).await?, (JobState::Running, 1));
+
+    // a new scheduler with the same store runs the unfinished job again.
+    let sched = Scheduler::builder()
+        .store(store.clone())
+        .job::<Nap>()
+        .build();
+    sched.wait().await?;
+    assert_eq!(job_status(&*store, slow).await?, (JobState::Succeeded, 2));
+    assert!(store.unfinished().await?.is_empty());
+    Ok(())
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_scheduler_jobs_recover_over_limit() -> Result<()> {
+    let store = Arc::new(MemoryStore::new());
+    let rules = Rules::builder()
+        .rule(
+            "job",
+            Rule {
+                max_running: 3,
+                ..Default::default()
+            },
+        )
+        .build();
+    let sched = Scheduler::builder()
+        .store(store.clone())
+        .rules(rules)
+        .job::<Nap>()
+        .build();
+    for _ in 0..3 {
+        let nap = Nap {
+            secs: 3600,
+            fail: false,
+        };
+        sched.submit("job", nap).await?;
+    }
+    sleep(Duration::from_secs(1)).await;
+    let summary = sched.shutdown(Duration::ZERO).await?;
+    assert_eq!(summary.aborted, 3);
+
+    // by default only one can run at a time and there is no queue, but the jobs were accepted
+    // before the restart, so the others wait their turn rather than being rejected.
+    let sched = Scheduler::builder()
+        .store(store.clone())
+        .job::<Nap>()
+        .build();
+    let start = Instant::now();
+    sched.wait().await?;
+    assert_eq!(start.elapsed().as_secs(), 3 * 3600);
+    for id in 1..=3 {
+        assert_eq!(
+            job_status(&*store, JobId(id)).await?,
+            (JobState::Succeeded, 2)
+        );
+    }
+    Ok(())
+}
+
+/// Keeps `Held` jobs running until it is cleared.
+static HOLD: AtomicBool = AtomicBool::new(true);
+
+#[derive(Clone, Serialize, Deserialize)]
+struct Held;
+
+#[async_trait]
+impl Job for Held {
+    const KIND: &'static str = "held";
+
+    async fn run(self) -> Result<()> {
+        while HOLD.load(Ordering::SeqCst) {
+            sleep(Duration::from_millis(10)).await;
+        }
+        Ok(())
+    }
+}
+
+#[tokio::test]
+async fn test_scheduler_jobs_sqlite() -> Result<()> {
+    let path = std::env::temp_dir().join(format!("scheduler-jobs-{}.db", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let url = format!("sqlite://{}", path.display());
+
+    let store = Arc::new(SqliteStore::connect(&url).await?);
+    let sched = Scheduler::builder()
+        .store(store.clone())
+        .job::<Held>()
+        .build();
+    let held = sched.submit("job", Held).await?;
+    let id = JobId(1);
+    while job_status(&*store, id).await?.0 != JobState::Running {
+        sleep(Duration::from_millis(10)).await;
+    }
+    sched.shutdown(Duration::ZERO).await?;
+    assert_eq!(held.await.err(), Some(TaskError::Cancelled));
+    drop(store);
+
+    // the job is still in the database after it is reopened, and runs again.
+    HOLD.store(false, Ordering::SeqCst);
+    let store = Arc::new(SqliteStore::connect(&url).await?);
+    assert_eq!(store.unfinished().await?.len(), 1);
+    let sched = Scheduler::builder()
+        .store(store.clone())
+        .job::<Held>()
+        .build();
+    sched.wait().await?;
+    assert_eq!(job_status(&*store, id).await?, (JobState::Succeeded, 2));
+
+    let _ = std::fs::remove_file(&path);
+    Ok(())
+}
+
+/// `BrokenStore` keeps new jobs, but cannot record anything about them after that.
+struct BrokenStore(MemoryStore);
+
+#[async_trait]
+impl Store for BrokenStore {
+    async fn insert(&self, kind: &str, typ: &Type, payload: &str) -> Result<JobId> {
+        self.0.insert(kind, typ, payload).await
+    }
+
+    async fn start_attempt(&self, _id: JobId) -> Result<()> {
+        anyhow::bail!("disk full")
+    }
+
+    async fn set_state(&self, _id: JobId, _state: JobState) -> Result<()> {
+        anyhow::bail!("disk full")
+    }
+
+    async fn get(&self, id: JobId) -> Result<Option<StoredJob>> {
+        self.0.get(id).await
+    }
+
+    async fn unfinished(&self) -> Result<Vec<StoredJob>> {
+        self.0.unfinished().await
+    }
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_scheduler_jobs_store_errors() -> Result<()> {
+    let hooks = TestHooks::new();
+    let sched = Scheduler::builder()
+        .hooks(hooks.clone().into())
+        .store(Arc::new(BrokenStore(MemoryStore::new())))
+        .job::<Nap>()
+        .build();
+    // the job still runs, and the hooks hear about what could not be recorded.
+    let nap = Nap {
+        secs: 1,
+        fail: false,
+    };
+    sched.submit("nap", nap).await?.await??;
+    sched.wait().await?;
+    assert_eq!(
+        *hooks.store_errors.lock().unwrap(),
+        [
+            "could not record an attempt at job 1: disk full",
+            "could not record job 1 as succeeded: disk full",
+        ]
+    );
+    Ok(())
+}
+
+/// Adds a task to the workflow which logs its type when it runs, and fails if `fail` is set.
+fn logged(
+    workflow: &mut Workflow,
+    typ: &'static str,
+    log: &Arc<Mutex<Vec<&'static str>>>,
+    fail: bool,
+) -> NodeId {
+    let log = log.clone();
+    workflow.task(typ, move || {
+        let log = log.clone();
+        async move {
+            sleep(Duration::from_secs(1)).await;
+            log.lock().unwrap().push(typ);
+            if fail {
+                anyhow::bail!("{typ} failed");
+            }
+            Ok(())
+        }
+    })
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_workflow() -> Result<()> {
+    let log = Arc::new(Mutex::new(Vec::new()));
+    let mut workflow = Workflow::new();
+    let fetch = logged(&mut workflow, "fetch", &log, false);
+    let resize = logged(&mut workflow, "resize", &log, false);
+    let tag = logged(&mut workflow, "tag", &log, false);
+    let publish = logged(&mut workflow, "publish", &log, false);
+    workflow.depend(resize, fetch);
+    workflow.depend(tag, fetch);
+    workflow.depend(publish, resize);
+    workflow.depend(publish, tag);
+
+    let sched = Scheduler::builder().build();
+    let report = sched.run_workflow(workflow).await?;
+    assert!(report.succeeded(), "{report:?}");
+    let mut log = log.lock().unwrap().clone();
+    assert_eq!((log[0], log[3]), ("fetch", "publish"));
+    log[1..3].sort_unstable();
+    assert_eq!(log, ["fetch", "resize", "tag", "publish"]);
+    Ok(())
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_workflow_failure() -> Result<()> {
+    let log = Arc::new(Mutex::new(Vec::new()));
+    let mut workflow = Workflow::new();
+    let fetch = logged(&mut workflow, "fetch", &log, true);
+    let transform = logged(&mut workflow, "transform", &log, false);
+    let publish = logged(&mut workflow, "publish", &log, false);
+    let audit = logged(&mut workflow, "audit", &log, false);
+    workflow.depend(transform, fetch);
+    workflow.depend(publish, transform);
+
+    let sched = Scheduler::builder().build();
+    let report = sched.run_workflow(workflow).await?;
+    assert!(!report.succeeded());
+    assert_eq!(
+        *report.status(fetch),
+        NodeStatus::Failed(String::from("fetch failed"))
+    );
+    assert_eq!(*report.status(transform), NodeStatus::Skipped);
+    assert_eq!(*report.status(publish), NodeStatus::Skipped);
+    // tasks that do not depend on the failure still run.
+    assert_eq!(*report.status(audit), NodeStatus::Succeeded);
+    let mut log = log.lock().unwrap().clone();
+    log.sort_unstable();
+    assert_eq!(log, ["audit", "fetch"]);
+    Ok(())
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_workflow_rules() -> Result<()> {
+    // only one "step" can run at a time, and there is no queue, so the second is rejected.
+    let log = Arc::new(Mutex::new(Vec::new()));
+    let mut workflow = Workflow::new();
+    let first = logged(&mut workflow, "step", &log, false);
+    let second = logged(&mut workflow, "step", &log, false);
+    let after = logged(&mut workflow, "after", &log, false);
+    workflow.depend(after, second);
+
+    let sched = Scheduler::builder().build();
+    let report = sched.run_workflow(workflow).await?;
+    assert_eq!(*report.status(first), NodeStatus::Succeeded);
+    assert_eq!(*report.status(second), NodeStatus::Rejected);
+    assert_eq!(*report.status(after), NodeStatus::Skipped);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_workflow_validation() -> Result<()> {
+    let log = Arc::new(Mutex::new(Vec::new()));
+    let mut workflow = Workflow::new();
+    let a = logged(&mut workflow, "a", &log, false);
+    let b = logged(&mut workflow, "b", &log, false);
+    let c = logged(&mut workflow, "c", &log, false);
+    workflow.depend(b, a);
+    workflow.depend(c, b);
+    assert_eq!(workflow.validate(), Ok(vec![a, b, c]));
+
+    workflow.depend(a, c);
+    assert_eq!(workflow.validate(), Err(WorkflowError::Cycle(a)));
+    let sched = Scheduler::builder().build();
+    assert!(sched.run_workflow(workflow).await.is_err());
+    assert!(log.lock().unwrap().is_empty());
+
+    let mut other = Workflow::new();
+    let d = logged(&mut other, "d", &log, false);
+    other.depend(d, c);
+    assert_eq!(other.validate(), Err(WorkflowError::UnknownTask(c)));
+    Ok(())
+}
+
 #[derive(Clone)]
 struct TestHooks {
     count: Arc<Mutex<usize>>,
+    timeouts: Arc<Mutex<usize>>,
+    retries: Arc<Mutex<Vec<(u32, Duration)>>>,
+    outcomes: Arc<Mutex<Vec<(TaskContext, Outcome)>>>,
+    rejected: Arc<Mutex<Vec<Type>>>,
+    /// The global limit of each set of rules the scheduler was updated to.
+    updates: Arc<Mutex<Vec<Option<usize>>>>,
+    store_errors: Arc<Mutex<Vec<String>>>,
 }
 
 impl TestHooks {
     fn new() -> Self {
         TestHooks {
             count: Arc::new(Mutex::new(0)),
+            timeouts: Arc::new(Mutex::new(0)),
+            retries: Arc::new(Mutex::new(vec![])),
+            outcomes: Arc::new(Mutex::new(vec![])),
+            rejected: Arc::new(Mutex::new(vec![])),
+            updates: Arc::new(Mutex::new(vec![])),
+            store_errors: Arc::new(Mutex::new(vec![])),
         }
     }
     fn get_count(&self) -> usize {
@@ -120,20 +1692,108 @@ impl TestHooks {
     }
     fn bump_count(&self) {
         let mut count = self.count.lock().unwrap();
-        *count = *count + 1;
+        *count += 1;
     }
 }
 
 #[async_trait]
 impl Callback for TestHooks {
-    async fn on_task_start(&self, typ: &Type) -> HookResult {
-        println!("Hook: on_task_start: {:?}", typ);
+    async fn on_task_start(&self, ctx: &TaskContext) -> HookResult {
+        println!("Hook: on_task_start: {ctx:?}");
         self.bump_count();
         Ok(())
     }
 
-    async fn on_task_complete(&self, typ: &Type) -> HookResult {
-        println!("Hook: on_task_complete: {:?}", typ);
+    async fn on_task_complete(&self, ctx: &TaskContext, outcome: &Outcome) -> HookResult {
+        println!("Hook: on_task_complete: {ctx:?} {outcome:?}");
+        self.outcomes
+            .lock()
+            .unwrap()
+            .push((ctx.clone(), outcome.clone()));
+        Ok(())
+    }
+
+    async fn on_task_timeout(&self, ctx: &TaskContext) -> HookResult {
+        println!("Hook: on_task_timeout: {ctx:?}");
+        *self.timeouts.lock().unwrap() += 1;
+        Ok(())
+    }
+
+    async fn on_task_retry(&self, ctx: &TaskContext, attempt: u32, delay: Duration) -> HookResult {
+        println!("Hook: on_task_retry: {ctx:?} {attempt} {delay:?}");
+        self.retries.lock().unwrap().push((attempt, delay));
         Ok(())
     }
+
+    async fn on_task_rejected(&self, ctx: &TaskContext) -> HookResult {
+        println!("Hook: on_task_rejected: {ctx:?}");
+        self.rejected.lock().unwrap().push(ctx.typ.clone());
+        Ok(())
+    }
+
+    async fn on_rules_updated(&self, rules: &Rules) -> HookResult {
+        self.updates.lock().unwrap().push(rules.max_running());
+        Ok(())
+    }
+
+    async fn on_store_error(&self, err: &anyhow::Error) {
+        self.store_errors.lock().unwrap().push(format!("{err:#}"));
+    }
+}
+
+/// `Recorder` logs the hooks it is called for and the hook errors it is told about under its
+/// name, and fails the start hook for tasks of the type "bad".
+struct Recorder {
+    name: &'static str,
+    log: Arc<Mutex<Vec<String>>>,
+}
+
+impl Recorder {
+    fn record(&self, hook: &str, ctx: &TaskContext) {
+        let line = format!("{} {hook} {}", self.name, ctx.typ);
+        self.log.lock().unwrap().push(line);
+    }
+}
+
+#[async_trait]
+impl Callback for Recorder {
+    async fn on_task_start(&self, ctx: &TaskContext) -> HookResult {
+        self.record("start", ctx);
+        if ctx.typ == Type::from("bad") {
+            return Err(Arc::new(anyhow::anyhow!("{} refuses bad tasks", self.name)));
+        }
+        Ok(())
+    }
+
+    async fn on_task_complete(&self, ctx: &TaskContext, _outcome: &Outcome) -> HookResult {
+        self.record("complete", ctx);
+        Ok(())
+    }
+
+    async fn on_task_rejected(&self, ctx: &TaskContext) -> HookResult {
+        self.record("rejected", ctx);
+        Ok(())
+    }
+
+    async fn on_hook_failed(&self, hook: &str, err: &Arc<anyhow::Error>) {
+        let line = format!("{} failed {hook}: {err}", self.name);
+        self.log.lock().unwrap().push(line);
+    }
+}
+
+/// Builds a scheduler with two recorders sharing a log, in order, and the given failure policy.
+fn recorded(policy: FailurePolicy) -> (Scheduler, Arc<Mutex<Vec<String>>>) {
+    let log = Arc::new(Mutex::new(vec![]));
+    let recorder = |name| {
+        Box::new(Recorder {
+            name,
+            log: log.clone(),
+        })
+    };
+    let sched = Scheduler::builder()
+        .hooks(recorder("first"))
+        .hooks(recorder("second"))
+        .on_hook_error(policy)
+        .build();
+    (sched, log)
 }
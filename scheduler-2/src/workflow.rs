@@ -0,0 +1,265 @@
+use std::{collections::VecDeque, fmt, future::Future, sync::Arc};
+
+use anyhow::Result;
+use futures::{future::BoxFuture, stream::FuturesUnordered, FutureExt, StreamExt};
+
+use crate::{
+    scheduler::{Response, Scheduler},
+    task::{TaskError, Type},
+};
+
+type MakeNode = Arc<dyn Fn() -> BoxFuture<'static, Result<()>> + Send + Sync>;
+
+/// `Workflow` is a graph of tasks, where a task only starts once the tasks it depends on have
+/// succeeded. Run it with `Scheduler::run_workflow`.
+#[derive(Default)]
+pub struct Workflow {
+    nodes: Vec<Node>,
+}
+
+struct Node {
+    typ: Type,
+    make: MakeNode,
+    /// The nodes that have to succeed before this one starts.
+    parents: Vec<NodeId>,
+}
+
+/// `NodeId` identifies a task in a workflow.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct NodeId(usize);
+
+impl fmt::Display for NodeId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl Workflow {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a task of the type to the workflow. `f` makes the future for each attempt, like
+    /// `Scheduler::run_retryable`, and the task succeeds once an attempt returns `Ok`.
+    pub fn task<T, F, Fut>(&mut self, typ: T, f: F) -> NodeId
+    where
+        T: Into<Type>,
+        F: Fn() -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<()>> + Send + 'static,
+    {
+        self.nodes.push(Node {
+            typ: typ.into(),
+            make: Arc::new(move || f().boxed()),
+            parents: Vec::new(),
+        });
+        NodeId(self.nodes.len() - 1)
+    }
+
+    /// Makes `node` wait for `on` to succeed before it starts.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `node` is not in the workflow.
+    pub fn depend(&mut self, node: NodeId, on: NodeId) {
+        self.nodes[node.0].parents.push(on);
+    }
+
+    /// Checks that every dependency is on a task in the workflow, and that no task depends on
+    /// itself, however indirectly. Returns the tasks in an order they could run in.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error naming the first unknown task or a task that is part of a cycle.
+    pub fn validate(&self) -> Result<Vec<NodeId>, WorkflowError> {
+        let mut waiting = Vec::with_capacity(self.nodes.len());
+        for node in &self.nodes {
+            if let Some(parent) = node.parents.iter().find(|p| p.0 >= self.nodes.len()) {
+                return Err(WorkflowError::UnknownTask(*parent));
+            }
+            waiting.push(node.parents.len());
+        }
+        let children = self.children();
+        let mut ready: VecDeque<NodeId> = self.roots().collect();
+        let mut order = Vec::with_capacity(self.nodes.len());
+        while let Some(id) = ready.pop_front() {
+            order.push(id);
+            for child in &children[id.0] {
+                waiting[child.0] -= 1;
+                if waiting[child.0] == 0 {
+                    ready.push_back(*child);
+                }
+            }
+        }
+        // whatever never became ready is waiting on a cycle.
+        match waiting.iter().position(|n| *n > 0) {
+            Some(i) => Err(WorkflowError::Cycle(NodeId(i))),
+            None => Ok(order),
+        }
+    }
+
+    /// The nodes that depend on each node. A node that depends on another twice is listed twice.
+    fn children(&self) -> Vec<Vec<NodeId>> {
+        let mut children = vec![Vec::new(); self.nodes.len()];
+        for (i, node) in self.nodes.iter().enumerate() {
+            for parent in &node.parents {
+                children[parent.0].push(NodeId(i));
+            }
+        }
+        children
+    }
+
+    /// The nodes that do not depend on any others.
+    fn roots(&self) -> impl Iterator<Item = NodeId> + '_ {
+        self.nodes
+            .iter()
+            .enumerate()
+            .filter(|(_, node)| node.parents.is_empty())
+            .map(|(i, _)| NodeId(i))
+    }
+}
+
+/// `WorkflowError` is why a workflow cannot run.
+#[derive(Debug, PartialEq)]
+pub enum WorkflowError {
+    /// A task depends on this task, which is not in the workflow.
+    UnknownTask(NodeId),
+
+    /// The task is part of a cycle of dependencies, or depends on one.
+    Cycle(NodeId),
+}
+
+impl fmt::Display for WorkflowError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WorkflowError::UnknownTask(id) => write!(f, "task {id} is not in the workflow"),
+            WorkflowError::Cycle(id) => write!(f, "task {id} depends on a cycle of tasks"),
+        }
+    }
+}
+
+impl std::error::Error for WorkflowError {}
+
+/// `NodeStatus` is what happened to a task in a workflow.
+#[derive(Clone, Debug, PartialEq)]
+pub enum NodeStatus {
+    Succeeded,
+    /// The task's last attempt returned this error, or it did not finish for this reason.
+    Failed(String),
+    /// The scheduler rejected the task, e.g. because its type was at `max_running` and its rule
+    /// has no queue.
+    Rejected,
+    /// A task this one depends on did not succeed, so it never ran.
+    Skipped,
+}
+
+/// `Report` is what happened to every task in a workflow once it has finished.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Report {
+    statuses: Vec<NodeStatus>,
+}
+
+impl Report {
+    /// Whether every task in the workflow succeeded.
+    #[must_use]
+    pub fn succeeded(&self) -> bool {
+        self.statuses.iter().all(|s| *s == NodeStatus::Succeeded)
+    }
+
+    /// # Panics
+    ///
+    /// Panics if the task is not in the workflow.
+    #[must_use]
+    pub fn status(&self, id: NodeId) -> &NodeStatus {
+        &self.statuses[id.0]
+    }
+
+    /// Every task's status, by id.
+    pub fn statuses(&self) -> impl Iterator<Item = (NodeId, &NodeStatus)> {
+        self.statuses
+            .iter()
+            .enumerate()
+            .map(|(i, s)| (NodeId(i), s))
+    }
+}
+
+/// Runs a workflow that has been validated, submitting each task once its parents have
+/// succeeded.
+pub(crate) async fn run(sched: &Scheduler, workflow: Workflow) -> Report {
+    let mut run = Run {
+        children: workflow.children(),
+        waiting: workflow.nodes.iter().map(|n| n.parents.len()).collect(),
+        statuses: vec![None; workflow.nodes.len()],
+        ready: workflow.roots().collect(),
+    };
+    let mut running = FuturesUnordered::new();
+    loop {
+        while let Some(id) = run.ready.pop_front() {
+            let node = &workflow.nodes[id.0];
+            let make = node.make.clone();
+            match sched.run_retryable(node.typ.clone(), move || make()).await {
+                Ok(handle) if handle.response() == Response::Rejected => {
+                    run.finish(id, NodeStatus::Rejected);
+                }
+                Ok(handle) => running.push(async move { (id, handle.await) }),
+                Err(err) => run.finish(id, NodeStatus::Failed(err.to_string())),
+            }
+        }
+        let Some((id, res)) = running.next().await else {
+            break;
+        };
+        let status = match res {
+            Ok(Ok(())) => NodeStatus::Succeeded,
+            Ok(Err(err)) => NodeStatus::Failed(err.to_string()),
+            Err(TaskError::Rejected) => NodeStatus::Rejected,
+            Err(err) => NodeStatus::Failed(err.to_string()),
+        };
+        run.finish(id, status);
+    }
+    Report {
+        statuses: run
+            .statuses
+            .into_iter()
+            .map(|s| s.unwrap_or(NodeStatus::Skipped))
+            .collect(),
+    }
+}
+
+/// `Run` tracks which tasks of a running workflow can start.
+struct Run {
+    children: Vec<Vec<NodeId>>,
+    /// How many parents of each task have still to succeed.
+    waiting: Vec<usize>,
+    /// What happened to each task, once it is known.
+    statuses: Vec<Option<NodeStatus>>,
+    /// The tasks that can start now.
+    ready: VecDeque<NodeId>,
+}
+
+impl Run {
+    fn finish(&mut self, id: NodeId, status: NodeStatus) {
+        let succeeded = status == NodeStatus::Succeeded;
+        self.statuses[id.0] = Some(status);
+        if !succeeded {
+            self.skip_children(id);
+            return;
+        }
+        for child in &self.children[id.0] {
+            self.waiting[child.0] -= 1;
+            if self.waiting[child.0] == 0 && self.statuses[child.0].is_none() {
+                self.ready.push_back(*child);
+            }
+        }
+    }
+
+    /// Skips every task that depends on the task, however indirectly.
+    fn skip_children(&mut self, id: NodeId) {
+        let mut skip = self.children[id.0].clone();
+        while let Some(child) = skip.pop() {
+            if self.statuses[child.0].is_none() {
+                self.statuses[child.0] = Some(NodeStatus::Skipped);
+                skip.extend(&self.children[child.0]);
+            }
+        }
+    }
+}
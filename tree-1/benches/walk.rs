@@ -0,0 +1,80 @@
+//! Times `tt` walking a generated tree sequentially (`-j 1`) and in parallel,
+//! and checks that both print the same thing.
+//!
+//! `cargo bench` runs it over ~100k files; `TT_BENCH_FANOUT`, `TT_BENCH_DEPTH`,
+//! `TT_BENCH_FILES` and `TT_BENCH_RUNS` change the shape of the tree and how
+//! many times each mode is timed.
+
+use std::{
+    env, fs,
+    path::Path,
+    process::Command,
+    time::{Duration, Instant},
+};
+
+fn var(name: &str, default: usize) -> usize {
+    env::var(name)
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(default)
+}
+
+/// `fanout` directories per level, `depth` levels deep, with `files` files in
+/// every directory.
+fn generate(dir: &Path, fanout: usize, depth: usize, files: usize) -> usize {
+    let mut count = 0;
+    for i in 0..files {
+        fs::write(dir.join(format!("file-{i}.txt")), "x".repeat(i)).unwrap();
+        count += 1;
+    }
+    if depth > 0 {
+        for i in 0..fanout {
+            let sub = dir.join(format!("dir-{i}"));
+            fs::create_dir(&sub).unwrap();
+            count += generate(&sub, fanout, depth - 1, files) + 1;
+        }
+    }
+    count
+}
+
+fn run(dir: &Path, jobs: &str, runs: usize) -> (Duration, Vec<u8>) {
+    let mut best = Duration::MAX;
+    let mut output = vec![];
+    for _ in 0..runs {
+        let start = Instant::now();
+        let out = Command::new(env!("CARGO_BIN_EXE_tt"))
+            .args(["--size", "--jobs", jobs])
+            .arg(dir)
+            .env("NO_COLOR", "1")
+            .output()
+            .unwrap();
+        best = best.min(start.elapsed());
+        assert!(out.status.success(), "tt failed: {out:?}");
+        output = out.stdout;
+    }
+    (best, output)
+}
+
+fn main() {
+    let fanout = var("TT_BENCH_FANOUT", 10);
+    let depth = var("TT_BENCH_DEPTH", 4);
+    let files = var("TT_BENCH_FILES", 9);
+    let runs = var("TT_BENCH_RUNS", 5);
+
+    let dir = tempfile::tempdir().unwrap();
+    let entries = generate(dir.path(), fanout, depth, files);
+    println!("walking {entries} entries, best of {runs} runs");
+
+    let (sequential, expected) = run(dir.path(), "1", runs);
+    println!("sequential: {sequential:?}");
+    let (parallel, output) = run(dir.path(), "0", runs);
+    println!("parallel:   {parallel:?}");
+    assert!(
+        output == expected,
+        "parallel output differs from sequential"
+    );
+    println!(
+        "speedup:    {:.2}x",
+        sequential.as_secs_f64() / parallel.as_secs_f64()
+    );
+}
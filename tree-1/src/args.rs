@@ -1,8 +1,9 @@
-pub use clap::Parser;
+pub use clap::{Parser, ValueEnum};
 
 use crate::prelude::*;
 
 #[derive(Parser, Debug)]
+#[allow(clippy::struct_excessive_bools)]
 pub struct Args {
     /// How many levels of depth to search for files
     #[arg(short, long)]
@@ -20,10 +21,78 @@ pub struct Args {
     #[arg(short = 'E', default_value_t = false)]
     pub executables_only: bool,
 
+    /// Follow symlinks to directories; links back into a directory that is
+    /// already being walked are reported instead of followed
+    #[arg(short = 'l', long, default_value_t = false)]
+    pub follow: bool,
+
+    /// Don't respect .gitignore and .ignore files
+    #[arg(long, default_value_t = false)]
+    pub no_ignore: bool,
+
+    /// Only show files matching this glob; can be repeated
+    #[arg(long, value_name = "GLOB")]
+    pub include: Vec<String>,
+
+    /// Hide files and directories matching this glob; can be repeated
+    #[arg(long, value_name = "GLOB")]
+    pub exclude: Vec<String>,
+
+    /// Show sizes; directories show the total size of the files below them,
+    /// including ones that -D, -E or --include leave out
+    #[arg(short, long, default_value_t = false)]
+    pub size: bool,
+
+    /// Show modification times
+    #[arg(short, long, default_value_t = false)]
+    pub mtime: bool,
+
+    /// Show permissions
+    #[arg(short, long, default_value_t = false)]
+    pub perms: bool,
+
+    /// Show owners
+    #[arg(short = 'u', long, default_value_t = false)]
+    pub owner: bool,
+
+    /// How to order the entries of each directory
+    #[arg(long, value_enum, default_value_t = Sort::Name)]
+    pub sort: Sort,
+
+    /// How many threads to walk with; 0 uses one per CPU and 1 walks
+    /// sequentially
+    #[arg(short, long, default_value_t = 0)]
+    pub jobs: usize,
+
+    /// How to print the tree
+    #[arg(short, long, value_enum, default_value_t = Format::Text)]
+    pub format: Format,
+
     /// The directory to search
     pub dir: Option<String>,
 }
 
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Sort {
+    /// Alphabetically
+    Name,
+    /// Largest first
+    Size,
+    /// Most recently modified first
+    Mtime,
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// Lines drawn with unicode box characters
+    Text,
+    /// Lines drawn with plain ASCII
+    Ascii,
+    Json,
+    Xml,
+    Html,
+}
+
 impl Args {
     pub fn validate(&self) -> WalkResult<()> {
         if self.executables_only && self.dirs_only {
@@ -33,4 +102,10 @@ impl Args {
         }
         Ok(())
     }
+
+    /// Whether directory sizes have to be rolled up, which means walking
+    /// below `depth` even though nothing there is shown.
+    pub fn needs_sizes(&self) -> bool {
+        self.size || self.sort == Sort::Size
+    }
 }
@@ -7,8 +7,8 @@ pub enum Error {
     NotFound(String),
     NotDirectory(String),
     IO(io::Error),
-    NoFileName,
     InvalidArgs(String),
+    ThreadPool(rayon::ThreadPoolBuildError),
 }
 
 impl Error {}
@@ -20,9 +20,9 @@ impl Display for Error {
         match self {
             Self::NotFound(ref s) => write!(f, "{s} not found"),
             Self::IO(ref e) => write!(f, "{e}"),
-            Self::NoFileName => write!(f, "no filename present"),
             Self::NotDirectory(ref s) => write!(f, "{s} is not a directory"),
             Self::InvalidArgs(ref s) => write!(f, "{s}"),
+            Self::ThreadPool(ref e) => write!(f, "failed to start walker threads: {e}"),
         }
     }
 }
@@ -32,3 +32,9 @@ impl From<io::Error> for Error {
         Self::IO(value)
     }
 }
+
+impl From<rayon::ThreadPoolBuildError> for Error {
+    fn from(value: rayon::ThreadPoolBuildError) -> Self {
+        Self::ThreadPool(value)
+    }
+}
@@ -0,0 +1,192 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use ignore::{
+    gitignore::{Gitignore, GitignoreBuilder},
+    Match,
+};
+
+use crate::prelude::*;
+
+/// Ignore files read from each directory, in increasing order of precedence.
+const IGNORE_FILES: [&str; 2] = [".gitignore", ".ignore"];
+
+/// Decides which entries of a directory are shown and recursed into.
+pub struct Filter<'a> {
+    args: &'a Args,
+    root: PathBuf,
+    include: Option<GlobSet>,
+    exclude: Option<GlobSet>,
+}
+
+impl<'a> Filter<'a> {
+    pub fn new(args: &'a Args, root: &Path) -> WalkResult<Self> {
+        Ok(Self {
+            args,
+            root: root.to_path_buf(),
+            include: build_globs(&args.include)?,
+            exclude: build_globs(&args.exclude)?,
+        })
+    }
+
+    /// The ignore files that apply to `root` from its parent directories, up to
+    /// the root of the enclosing git repository. Outside a repository nothing
+    /// above `root` is consulted.
+    pub fn root_ignores(&self) -> Ignores {
+        if self.args.no_ignore {
+            return Ignores::default();
+        }
+        let Ok(start) = self.root.canonicalize() else {
+            return Ignores::default();
+        };
+        let mut parents = vec![];
+        for dir in start.ancestors().skip(1) {
+            parents.push(dir);
+            if dir.join(".git").exists() {
+                let mut ignores = Ignores::default();
+                // outermost first so deeper directories take precedence
+                for dir in parents.into_iter().rev() {
+                    if let Some(matcher) = read_ignores(dir) {
+                        let prefix = start.strip_prefix(dir).unwrap_or(&start);
+                        ignores.push(matcher, &self.root, prefix);
+                    }
+                }
+                return ignores;
+            }
+        }
+        Ignores::default()
+    }
+
+    /// `ignores` extended with the ignore files found in `dir`.
+    pub fn descend(&self, ignores: &Ignores, dir: &Path) -> Ignores {
+        let mut ignores = ignores.clone();
+        if !self.args.no_ignore {
+            if let Some(matcher) = read_ignores(dir) {
+                ignores.push(matcher, dir, Path::new(""));
+            }
+        }
+        ignores
+    }
+
+    /// Whether `entry` is walked at all. Hidden, ignored and excluded entries
+    /// are left out entirely, so excluded directories are not recursed into.
+    pub fn walks(&self, entry: &Entry, ignores: &Ignores) -> bool {
+        let Some(name) = entry.file_name.to_str() else {
+            return false;
+        };
+        // check for hidden files
+        if !self.args.show_hidden && name.starts_with('.') {
+            return false;
+        }
+        if ignores.is_ignored(&entry.path, entry.is_dir()) {
+            return false;
+        }
+        !self
+            .exclude
+            .as_ref()
+            .is_some_and(|set| self.matches(set, entry))
+    }
+
+    /// Whether a walked `entry` is shown. Entries that aren't still count
+    /// towards the size of their directory. Include patterns only apply to
+    /// files so that matches in subdirectories can still be reached.
+    pub fn shows(&self, entry: &Entry) -> bool {
+        let is_dir = entry.is_dir();
+        // check for dir only
+        if self.args.dirs_only && !is_dir {
+            return false;
+        }
+        if self.args.executables_only
+            && entry.is_file()
+            && entry
+                .target
+                .as_ref()
+                .is_some_and(|m| !is_executable(&entry.path, m))
+        {
+            return false;
+        }
+        is_dir
+            || self
+                .include
+                .as_ref()
+                .is_none_or(|set| self.matches(set, entry))
+    }
+
+    /// Whether the entry's name or its path below the start matches `set`.
+    fn matches(&self, set: &GlobSet, entry: &Entry) -> bool {
+        let path = &entry.path;
+        let relative = path.strip_prefix(&self.root).unwrap_or(path);
+        set.is_match(&entry.file_name) || set.is_match(relative)
+    }
+}
+
+/// The stack of ignore files in effect for a directory.
+#[derive(Debug, Clone, Default)]
+pub struct Ignores(Vec<Arc<IgnoreFile>>);
+
+#[derive(Debug)]
+struct IgnoreFile {
+    matcher: Gitignore,
+    /// The walked path that `prefix` is relative to.
+    root: PathBuf,
+    /// Where `root` sits relative to the directory holding the ignore file.
+    prefix: PathBuf,
+}
+
+impl Ignores {
+    fn push(&mut self, matcher: Gitignore, root: &Path, prefix: &Path) {
+        self.0.push(Arc::new(IgnoreFile {
+            matcher,
+            root: root.to_path_buf(),
+            prefix: prefix.to_path_buf(),
+        }));
+    }
+
+    /// The innermost ignore file with a matching rule decides, so a deeper
+    /// `!pattern` can re-include something ignored higher up.
+    fn is_ignored(&self, path: &Path, is_dir: bool) -> bool {
+        for ignore in self.0.iter().rev() {
+            let Ok(relative) = path.strip_prefix(&ignore.root) else {
+                continue;
+            };
+            match ignore.matcher.matched(ignore.prefix.join(relative), is_dir) {
+                Match::None => {}
+                Match::Ignore(_) => return true,
+                Match::Whitelist(_) => return false,
+            }
+        }
+        false
+    }
+}
+
+/// Reads `.gitignore` and `.ignore` in `dir`, if there are any with rules in them.
+/// Unreadable or malformed lines are skipped rather than failing the walk.
+fn read_ignores(dir: &Path) -> Option<Gitignore> {
+    let mut builder = GitignoreBuilder::new(dir);
+    for file in IGNORE_FILES {
+        let path = dir.join(file);
+        if path.is_file() {
+            builder.add(path);
+        }
+    }
+    builder.build().ok().filter(|m| !m.is_empty())
+}
+
+fn build_globs(patterns: &[String]) -> WalkResult<Option<GlobSet>> {
+    if patterns.is_empty() {
+        return Ok(None);
+    }
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        let glob = Glob::new(pattern)
+            .map_err(|e| Error::InvalidArgs(format!("invalid pattern {pattern}: {e}")))?;
+        builder.add(glob);
+    }
+    builder
+        .build()
+        .map(Some)
+        .map_err(|e| Error::InvalidArgs(e.to_string()))
+}
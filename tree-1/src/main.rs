@@ -2,55 +2,42 @@
 
 mod args;
 mod error;
+mod filter;
+mod meta;
+mod render;
 mod walk;
 
 mod prelude {
     pub use crate::args::*;
     pub use crate::error::*;
+    pub use crate::filter::*;
+    pub use crate::meta::*;
+    pub use crate::render::*;
     pub use crate::walk::*;
     pub use colored::Colorize;
 }
 
 use prelude::*;
-use std::process;
+use std::{
+    io::{self, BufWriter},
+    process,
+};
 
 fn main() {
-    if let Err(e) = run() {
-        eprintln!("{e}");
-        process::exit(1);
+    match run() {
+        Ok(report) if report.errors > 0 => process::exit(1),
+        Ok(_) => {}
+        Err(e) => {
+            eprintln!("{e}");
+            process::exit(1);
+        }
     }
 }
 
-/// ├─ one
-/// │  ├─ two
-/// │  └─ three
-/// │     └─ four
-/// └─ five
-///    └─ six
-fn run() -> WalkResult<()> {
+fn run() -> WalkResult<Report> {
     let args = Args::parse();
     args.validate()?;
-    walk(&args, print)?;
-    Ok(())
-}
-
-fn print(w: &Walked) {
-    if !w.start {
-        // lhs tree rendering
-        for v in w.lasts {
-            if *v {
-                print!("   ");
-            } else {
-                print!("│  ");
-            }
-        }
-        // render the marker right before the name
-        if w.last {
-            print!("└─ ");
-        } else {
-            print!("├─ ");
-        }
-    }
-    let formatted = w.details.colorize(w.name);
-    println!("{}", formatted);
+    let out = BufWriter::new(io::stdout().lock());
+    let mut renderer = renderer(&args, out);
+    walk(&args, renderer.as_mut())
 }
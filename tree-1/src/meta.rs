@@ -0,0 +1,147 @@
+use std::{fs::Metadata, time::SystemTime};
+
+use chrono::{DateTime, Local};
+
+use crate::prelude::*;
+
+/// The metadata shown in the optional columns next to each name.
+#[derive(Debug, Clone, Default)]
+pub struct Meta {
+    /// For directories, the cumulative size of every walked file below them,
+    /// including files that `-D`, `-E` or `--include` leave out.
+    pub size: u64,
+    pub modified: Option<SystemTime>,
+    pub mode: Option<u32>,
+    pub uid: Option<u32>,
+}
+
+impl Meta {
+    pub fn new(meta: &Metadata) -> Self {
+        let mode;
+        let uid;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::MetadataExt;
+            mode = Some(meta.mode());
+            uid = Some(meta.uid());
+        }
+        #[cfg(not(unix))]
+        {
+            mode = None;
+            uid = None;
+        }
+        Self {
+            size: if meta.is_dir() { 0 } else { meta.len() },
+            modified: meta.modified().ok(),
+            mode,
+            uid,
+        }
+    }
+
+    /// The enabled columns, e.g. `[ 4.0K 2023-07-10 14:03 -rw-r--r-- brhoades]`,
+    /// or `None` if no column was asked for.
+    pub fn columns(&self, args: &Args) -> Option<String> {
+        let mut columns = vec![];
+        if args.size {
+            columns.push(format!("{:>5}", human_size(self.size)));
+        }
+        if args.mtime {
+            columns.push(self.modified_local().map_or_else(
+                || format!("{:16}", "?"),
+                |t| t.format("%Y-%m-%d %H:%M").to_string(),
+            ));
+        }
+        if args.perms {
+            columns.push(self.permissions().unwrap_or_else(|| format!("{:10}", "?")));
+        }
+        if args.owner {
+            columns.push(self.owner().unwrap_or_else(|| "?".to_string()));
+        }
+        if columns.is_empty() {
+            None
+        } else {
+            Some(format!("[{}]", columns.join(" ")))
+        }
+    }
+
+    pub fn modified_local(&self) -> Option<DateTime<Local>> {
+        self.modified.map(DateTime::from)
+    }
+
+    /// The mode like `ls -l` renders it, e.g. `drwxr-xr-x`.
+    pub fn permissions(&self) -> Option<String> {
+        self.mode.map(permissions)
+    }
+
+    pub fn owner(&self) -> Option<String> {
+        self.uid.map(owner)
+    }
+}
+
+/// Formats a byte count like `tree -h`: `512B`, `4.0K`, `12M`.
+#[allow(clippy::cast_precision_loss)]
+fn human_size(size: u64) -> String {
+    const UNITS: [&str; 6] = ["K", "M", "G", "T", "P", "E"];
+    if size < 1024 {
+        return format!("{size}B");
+    }
+    let mut size = size as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if size < 10.0 {
+        format!("{size:.1}{}", UNITS[unit])
+    } else {
+        format!("{size:.0}{}", UNITS[unit])
+    }
+}
+
+fn permissions(mode: u32) -> String {
+    let kind = match mode & 0o170_000 {
+        0o040_000 => 'd',
+        0o120_000 => 'l',
+        0o010_000 => 'p',
+        0o140_000 => 's',
+        0o020_000 => 'c',
+        0o060_000 => 'b',
+        _ => '-',
+    };
+    let mut out = String::with_capacity(10);
+    out.push(kind);
+    for shift in [6, 3, 0] {
+        let bits = (mode >> shift) & 0o7;
+        out.push(if bits & 0o4 != 0 { 'r' } else { '-' });
+        out.push(if bits & 0o2 != 0 { 'w' } else { '-' });
+        out.push(if bits & 0o1 != 0 { 'x' } else { '-' });
+    }
+    out
+}
+
+#[cfg(unix)]
+fn owner(uid: u32) -> String {
+    use std::{cell::RefCell, collections::HashMap};
+
+    thread_local! {
+        // looking a user up goes through nss, so only do it once per uid
+        static OWNERS: RefCell<HashMap<u32, String>> = RefCell::new(HashMap::new());
+    }
+    OWNERS.with(|owners| {
+        owners
+            .borrow_mut()
+            .entry(uid)
+            .or_insert_with(|| {
+                uzers::get_user_by_uid(uid).map_or_else(
+                    || uid.to_string(),
+                    |u| u.name().to_string_lossy().to_string(),
+                )
+            })
+            .clone()
+    })
+}
+
+#[cfg(not(unix))]
+fn owner(uid: u32) -> String {
+    uid.to_string()
+}
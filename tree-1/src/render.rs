@@ -0,0 +1,113 @@
+use std::io::Write;
+
+use crate::prelude::*;
+
+mod html;
+mod json;
+mod text;
+mod xml;
+
+pub use html::Html;
+pub use json::Json;
+pub use text::{Charset, Text};
+pub use xml::Xml;
+
+/// Builds output from the entries `walk` visits. Entries arrive parents first;
+/// the children of an entry are bracketed by `enter` and `leave`, which are
+/// only called for directories that have something shown below them.
+pub trait Renderer {
+    fn entry(&mut self, w: &Walked) -> WalkResult<()>;
+
+    /// The entries until the matching `leave` are children of the last entry.
+    fn enter(&mut self) -> WalkResult<()> {
+        Ok(())
+    }
+
+    fn leave(&mut self) -> WalkResult<()> {
+        Ok(())
+    }
+
+    /// Called once after the last entry, with the totals of what was shown.
+    fn finish(&mut self, report: &Report) -> WalkResult<()>;
+}
+
+/// The renderer for `--format`, writing to `out`.
+pub fn renderer<'a, W: Write + 'a>(args: &'a Args, out: W) -> Box<dyn Renderer + 'a> {
+    match args.format {
+        Format::Text => Box::new(Text::new(args, out, Charset::Unicode)),
+        Format::Ascii => Box::new(Text::new(args, out, Charset::Ascii)),
+        Format::Json => Box::new(Json::new(args, out)),
+        Format::Xml => Box::new(Xml::new(args, out)),
+        Format::Html => Box::new(Html::new(args, out)),
+    }
+}
+
+pub enum Value {
+    Number(u64),
+    Text(String),
+}
+
+/// A link's target, an entry's error and the metadata enabled on the command
+/// line, for the structured formats.
+pub fn fields(args: &Args, w: &Walked) -> Vec<(&'static str, Value)> {
+    let mut fields = vec![];
+    if let Some(link) = w.link {
+        fields.push((
+            "target",
+            Value::Text(link.target.to_string_lossy().to_string()),
+        ));
+    }
+    if let Some(error) = w.error {
+        fields.push(("error", Value::Text(error.to_string())));
+    }
+    let meta = w.meta;
+    if args.size {
+        fields.push(("size", Value::Number(meta.size)));
+    }
+    if args.mtime {
+        if let Some(t) = meta.modified_local() {
+            fields.push(("modified", Value::Text(t.to_rfc3339())));
+        }
+    }
+    if args.perms {
+        if let Some(mode) = meta.permissions() {
+            fields.push(("mode", Value::Text(mode)));
+        }
+    }
+    if args.owner {
+        if let Some(owner) = meta.owner() {
+            fields.push(("owner", Value::Text(owner)));
+        }
+    }
+    fields
+}
+
+/// The report's line for the text formats, e.g. `3 directories, 10 files, 1 error`.
+pub fn summary(args: &Args, report: &Report) -> String {
+    let plural =
+        |n: usize, one: &str, many: &str| format!("{n} {}", if n == 1 { one } else { many });
+    let mut parts = vec![plural(report.directories, "directory", "directories")];
+    if !args.dirs_only {
+        parts.push(plural(report.files, "file", "files"));
+    }
+    if report.errors > 0 {
+        parts.push(plural(report.errors, "error", "errors"));
+    }
+    parts.join(", ")
+}
+
+/// Escapes `s` for use in XML and HTML text and attribute values.
+pub fn escape_markup(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            c => out.push(c),
+        }
+    }
+    out
+}
@@ -0,0 +1,102 @@
+use std::io::Write;
+
+use crate::prelude::*;
+
+const STYLE: &str = "ul { list-style: none; } \
+    .directory { color: green; } \
+    .executable { color: red; } \
+    .link { color: darkcyan; } \
+    .meta { color: gray; font-family: monospace; }";
+
+/// A standalone page with the tree as nested lists.
+pub struct Html<'a, W: Write> {
+    args: &'a Args,
+    out: W,
+    depth: usize,
+    /// Whether the last entry's `<li>` is still waiting for its children.
+    open: bool,
+}
+
+impl<'a, W: Write> Html<'a, W> {
+    pub fn new(args: &'a Args, out: W) -> Self {
+        Self {
+            args,
+            out,
+            depth: 0,
+            open: false,
+        }
+    }
+
+    fn close(&mut self) -> WalkResult<()> {
+        if self.open {
+            write!(self.out, "</li>")?;
+            self.open = false;
+        }
+        Ok(())
+    }
+}
+
+impl<W: Write> Renderer for Html<'_, W> {
+    fn entry(&mut self, w: &Walked) -> WalkResult<()> {
+        if w.start {
+            write!(
+                self.out,
+                "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n\
+                 <title>{}</title>\n<style>{STYLE}</style>\n</head>\n<body>\n<ul>",
+                escape_markup(w.name),
+            )?;
+        }
+        self.close()?;
+        write!(
+            self.out,
+            "\n{:indent$}<li class=\"{}\">",
+            "",
+            w.kind(),
+            indent = self.depth * 2,
+        )?;
+        if let Some(columns) = w.meta.columns(self.args) {
+            write!(
+                self.out,
+                "<span class=\"meta\">{}</span> ",
+                escape_markup(&columns)
+            )?;
+        }
+        write!(self.out, "{}", escape_markup(w.name))?;
+        if let Some(link) = w.link {
+            let target = link.target.to_string_lossy();
+            write!(self.out, " -&gt; {}", escape_markup(&target))?;
+        }
+        if let Some(error) = w.error {
+            write!(self.out, " [{}]", escape_markup(&error.to_string()))?;
+        }
+        self.open = true;
+        Ok(())
+    }
+
+    fn enter(&mut self) -> WalkResult<()> {
+        self.depth += 1;
+        write!(self.out, "\n{:indent$}<ul>", "", indent = self.depth * 2)?;
+        self.open = false;
+        Ok(())
+    }
+
+    fn leave(&mut self) -> WalkResult<()> {
+        self.close()?;
+        write!(self.out, "\n{:indent$}</ul>", "", indent = self.depth * 2)?;
+        self.depth -= 1;
+        // the parent's item is still open
+        self.open = true;
+        Ok(())
+    }
+
+    fn finish(&mut self, report: &Report) -> WalkResult<()> {
+        self.close()?;
+        writeln!(
+            self.out,
+            "\n</ul>\n<p class=\"report\">{}</p>\n</body>\n</html>",
+            summary(self.args, report)
+        )?;
+        self.out.flush()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,116 @@
+use std::io::Write;
+
+use crate::prelude::*;
+
+/// A nested array of objects like `tree -J`, with each directory's children
+/// under `contents`.
+pub struct Json<'a, W: Write> {
+    args: &'a Args,
+    out: W,
+    depth: usize,
+    /// Whether the current array has no entries yet.
+    first: bool,
+    /// Whether the last entry's object is still waiting for a `}`.
+    open: bool,
+}
+
+impl<'a, W: Write> Json<'a, W> {
+    pub fn new(args: &'a Args, out: W) -> Self {
+        Self {
+            args,
+            out,
+            depth: 0,
+            first: true,
+            open: false,
+        }
+    }
+
+    fn close(&mut self) -> WalkResult<()> {
+        if self.open {
+            write!(self.out, "}}")?;
+            self.open = false;
+        }
+        Ok(())
+    }
+}
+
+impl<W: Write> Renderer for Json<'_, W> {
+    fn entry(&mut self, w: &Walked) -> WalkResult<()> {
+        if w.start {
+            write!(self.out, "[")?;
+        }
+        self.close()?;
+        if !self.first {
+            write!(self.out, ",")?;
+        }
+        write!(
+            self.out,
+            "\n{:indent$}{{\"type\":{},\"name\":{}",
+            "",
+            string(w.kind()),
+            string(w.name),
+            indent = (self.depth + 1) * 2,
+        )?;
+        for (key, value) in fields(self.args, w) {
+            match value {
+                Value::Number(n) => write!(self.out, ",\"{key}\":{n}")?,
+                Value::Text(s) => write!(self.out, ",\"{key}\":{}", string(&s))?,
+            }
+        }
+        self.first = false;
+        self.open = true;
+        Ok(())
+    }
+
+    fn enter(&mut self) -> WalkResult<()> {
+        write!(self.out, ",\"contents\":[")?;
+        self.depth += 1;
+        self.first = true;
+        self.open = false;
+        Ok(())
+    }
+
+    fn leave(&mut self) -> WalkResult<()> {
+        self.close()?;
+        write!(self.out, "\n{:indent$}]", "", indent = self.depth * 2)?;
+        self.depth -= 1;
+        // the parent's object is still open
+        self.open = true;
+        Ok(())
+    }
+
+    fn finish(&mut self, report: &Report) -> WalkResult<()> {
+        self.close()?;
+        // `tree -J` ends the array with its report
+        writeln!(
+            self.out,
+            ",\n  {{\"type\":\"report\",\"directories\":{},\"files\":{},\"errors\":{}}}\n]",
+            report.directories, report.files, report.errors
+        )?;
+        self.out.flush()?;
+        Ok(())
+    }
+}
+
+/// `s` as a quoted JSON string.
+fn string(s: &str) -> String {
+    use std::fmt::Write as _;
+
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if u32::from(c) < 0x20 => {
+                let _ = write!(out, "\\u{:04x}", u32::from(c));
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
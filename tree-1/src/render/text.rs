@@ -0,0 +1,98 @@
+use std::io::Write;
+
+use crate::prelude::*;
+
+/// The characters used to draw the tree.
+#[derive(Debug, Clone, Copy)]
+pub enum Charset {
+    Unicode,
+    /// For terminals that can't draw box characters.
+    Ascii,
+}
+
+struct Guides {
+    blank: &'static str,
+    line: &'static str,
+    tee: &'static str,
+    corner: &'static str,
+}
+
+const UNICODE: Guides = Guides {
+    blank: "   ",
+    line: "│  ",
+    tee: "├─ ",
+    corner: "└─ ",
+};
+
+const ASCII: Guides = Guides {
+    blank: "    ",
+    line: "|   ",
+    tee: "|-- ",
+    corner: "`-- ",
+};
+
+/// ├─ one
+/// │  ├─ two
+/// │  └─ three
+/// │     └─ four
+/// └─ five
+///    └─ six
+pub struct Text<'a, W: Write> {
+    args: &'a Args,
+    out: W,
+    guides: &'static Guides,
+}
+
+impl<'a, W: Write> Text<'a, W> {
+    pub fn new(args: &'a Args, out: W, charset: Charset) -> Self {
+        let guides = match charset {
+            Charset::Unicode => &UNICODE,
+            Charset::Ascii => &ASCII,
+        };
+        Self { args, out, guides }
+    }
+}
+
+impl<W: Write> Renderer for Text<'_, W> {
+    fn entry(&mut self, w: &Walked) -> WalkResult<()> {
+        if !w.start {
+            // lhs tree rendering
+            for v in w.lasts {
+                if *v {
+                    write!(self.out, "{}", self.guides.blank)?;
+                } else {
+                    write!(self.out, "{}", self.guides.line)?;
+                }
+            }
+            // render the marker right before the name
+            if w.last {
+                write!(self.out, "{}", self.guides.corner)?;
+            } else {
+                write!(self.out, "{}", self.guides.tee)?;
+            }
+        }
+        if let Some(columns) = w.meta.columns(self.args) {
+            write!(self.out, "{columns}  ")?;
+        }
+        let formatted = match (w.link, w.error) {
+            (None, _) => w.details.colorize(w.name),
+            (Some(_), None) => w.name.cyan().to_string(),
+            (Some(_), Some(_)) => w.name.red().to_string(),
+        };
+        write!(self.out, "{formatted}")?;
+        if let Some(link) = w.link {
+            write!(self.out, " -> {}", link.target.display())?;
+        }
+        if let Some(error) = w.error {
+            write!(self.out, " [{error}]")?;
+        }
+        writeln!(self.out)?;
+        Ok(())
+    }
+
+    fn finish(&mut self, report: &Report) -> WalkResult<()> {
+        writeln!(self.out, "\n{}", summary(self.args, report))?;
+        self.out.flush()?;
+        Ok(())
+    }
+}
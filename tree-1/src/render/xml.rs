@@ -0,0 +1,92 @@
+use std::io::Write;
+
+use crate::prelude::*;
+
+/// An XML document like `tree -X`: one element per entry, named after its type,
+/// with the name and metadata as attributes.
+pub struct Xml<'a, W: Write> {
+    args: &'a Args,
+    out: W,
+    /// The elements that children are currently being written into.
+    parents: Vec<&'static str>,
+    /// The last entry's element, if its start tag hasn't been closed yet.
+    open: Option<&'static str>,
+}
+
+impl<'a, W: Write> Xml<'a, W> {
+    pub fn new(args: &'a Args, out: W) -> Self {
+        Self {
+            args,
+            out,
+            parents: vec![],
+            open: None,
+        }
+    }
+
+    fn close(&mut self) -> WalkResult<()> {
+        if self.open.take().is_some() {
+            write!(self.out, "/>")?;
+        }
+        Ok(())
+    }
+
+    fn indent(&self) -> usize {
+        (self.parents.len() + 1) * 2
+    }
+}
+
+impl<W: Write> Renderer for Xml<'_, W> {
+    fn entry(&mut self, w: &Walked) -> WalkResult<()> {
+        if w.start {
+            write!(
+                self.out,
+                "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<tree>"
+            )?;
+        }
+        self.close()?;
+        let tag = w.kind();
+        write!(
+            self.out,
+            "\n{:indent$}<{tag} name=\"{}\"",
+            "",
+            escape_markup(w.name),
+            indent = self.indent(),
+        )?;
+        for (key, value) in fields(self.args, w) {
+            match value {
+                Value::Number(n) => write!(self.out, " {key}=\"{n}\"")?,
+                Value::Text(s) => write!(self.out, " {key}=\"{}\"", escape_markup(&s))?,
+            }
+        }
+        self.open = Some(tag);
+        Ok(())
+    }
+
+    fn enter(&mut self) -> WalkResult<()> {
+        if let Some(tag) = self.open.take() {
+            write!(self.out, ">")?;
+            self.parents.push(tag);
+        }
+        Ok(())
+    }
+
+    fn leave(&mut self) -> WalkResult<()> {
+        self.close()?;
+        if let Some(tag) = self.parents.pop() {
+            write!(self.out, "\n{:indent$}</{tag}>", "", indent = self.indent())?;
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self, report: &Report) -> WalkResult<()> {
+        self.close()?;
+        writeln!(
+            self.out,
+            "\n  <report>\n    <directories>{}</directories>\n    <files>{}</files>\n    \
+             <errors>{}</errors>\n  </report>\n</tree>",
+            report.directories, report.files, report.errors
+        )?;
+        self.out.flush()?;
+        Ok(())
+    }
+}
@@ -1,18 +1,38 @@
 use std::{
-    fs::{self, DirEntry},
-    path::Path,
+    cmp::Reverse,
+    ffi::OsString,
+    fmt::Display,
+    fs::{self, DirEntry, Metadata},
+    io,
+    path::{Path, PathBuf},
 };
 
+use rayon::prelude::*;
+
 use crate::prelude::*;
 
 #[derive(Debug, Clone)]
 pub struct Walked<'a> {
     pub name: &'a String,
-    pub depth: u32,
     pub last: bool,
     pub start: bool,
     pub lasts: &'a Vec<bool>,
     pub details: EntryDetails,
+    pub meta: &'a Meta,
+    pub link: Option<&'a Link>,
+    /// Why the entry couldn't be walked, shown next to it like `tree` does.
+    pub error: Option<&'a EntryError>,
+}
+
+impl Walked<'_> {
+    /// The name of the entry's type in structured output.
+    pub fn kind(&self) -> &'static str {
+        if self.link.is_some() {
+            "link"
+        } else {
+            self.details.kind()
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -30,133 +50,398 @@ impl EntryDetails {
             EntryDetails::Executable => name.red().to_string(),
         }
     }
+
+    /// The name of the entry's type in structured output.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            EntryDetails::File => "file",
+            EntryDetails::Dir => "directory",
+            EntryDetails::Executable => "executable",
+        }
+    }
+}
+
+/// Where a symlink points. The entry's details describe the link's target.
+#[derive(Debug, Clone)]
+pub struct Link {
+    pub target: PathBuf,
+}
+
+#[derive(Debug, Clone)]
+pub enum EntryError {
+    BrokenLink,
+    /// A directory that is already being walked above this entry.
+    Recursive,
+    /// Reading the entry or listing the directory failed.
+    Io(io::ErrorKind),
+}
+
+impl EntryError {
+    /// Whether this counts as an error in the report; links that can't be
+    /// followed are only noted.
+    pub fn is_failure(&self) -> bool {
+        matches!(self, Self::Io(_))
+    }
+}
+
+impl Display for EntryError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::BrokenLink => write!(f, "broken link"),
+            Self::Recursive => write!(f, "recursive, not followed"),
+            Self::Io(kind) => write!(f, "{kind}"),
+        }
+    }
+}
+
+impl From<io::Error> for EntryError {
+    fn from(value: io::Error) -> Self {
+        Self::Io(value.kind())
+    }
+}
+
+/// Totals for the entries that were shown, like the last line of `tree`. The
+/// start directory isn't counted.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Report {
+    pub directories: usize,
+    pub files: usize,
+    pub errors: usize,
+}
+
+/// A directory entry with everything it's filtered and shown by, so that each
+/// entry is only stat'd once.
+pub struct Entry {
+    pub path: PathBuf,
+    pub file_name: OsString,
+    /// The entry itself; for a symlink, the link.
+    pub meta: Metadata,
+    /// What the entry resolves to, or `None` for a broken link.
+    pub target: Option<Metadata>,
+}
+
+impl Entry {
+    fn new(entry: &DirEntry) -> io::Result<Self> {
+        let path = entry.path();
+        let meta = entry.metadata()?;
+        let target = if meta.is_symlink() {
+            path.metadata().ok()
+        } else {
+            Some(meta.clone())
+        };
+        Ok(Self {
+            path,
+            file_name: entry.file_name(),
+            meta,
+            target,
+        })
+    }
+
+    pub fn is_dir(&self) -> bool {
+        self.target.as_ref().is_some_and(Metadata::is_dir)
+    }
+
+    pub fn is_file(&self) -> bool {
+        self.target.as_ref().is_some_and(Metadata::is_file)
+    }
+}
+
+/// Identifies a directory regardless of the path it was reached by, to find
+/// cycles when following symlinks.
+#[cfg(unix)]
+type DirId = (u64, u64);
+#[cfg(not(unix))]
+type DirId = PathBuf;
+
+#[cfg(unix)]
+#[allow(clippy::unnecessary_wraps)]
+fn dir_id(_path: &Path, meta: &Metadata) -> io::Result<DirId> {
+    use std::os::unix::fs::MetadataExt;
+    Ok((meta.dev(), meta.ino()))
+}
+
+#[cfg(not(unix))]
+fn dir_id(path: &Path, _meta: &Metadata) -> io::Result<DirId> {
+    path.canonicalize()
+}
+
+/// A walked entry and its children. The whole tree is gathered before anything
+/// is emitted so that directory sizes and sorting can see every subtree.
+struct Node {
+    name: String,
+    details: EntryDetails,
+    meta: Meta,
+    link: Option<Link>,
+    error: Option<EntryError>,
+    children: Vec<Node>,
+    /// Walked but filtered out of the output, so only counted in sizes.
+    hidden: bool,
 }
 
-// walk starts with the current file or dir and then visits each child file and dir
-pub fn walk<F>(args: &Args, mut f: F) -> WalkResult<()>
-where
-    F: Fn(&Walked),
-{
+// walk starts with the current file or dir and then visits each child file and dir.
+// Unless `--jobs 1` is given, directories are walked in parallel on a
+// work-stealing pool; each directory's entries are collected in order, so the
+// result is the same either way. Entries that can't be read are shown with their
+// error rather than ending the walk.
+pub fn walk(args: &Args, renderer: &mut dyn Renderer) -> WalkResult<Report> {
     let start = match &args.dir {
-        Some(dir) => dir.to_string(),
+        Some(dir) => dir.clone(),
         None => ".".to_string(),
     };
     let start = Path::new(&start);
-    walk_path(args, start, 0, &vec![], &mut f)
+    let to_str = start.to_string_lossy().to_string();
+    if !start.exists() {
+        return Err(Error::NotFound(to_str));
+    }
+    if !start.is_dir() {
+        return Err(Error::NotDirectory(to_str));
+    }
+    let filter = Filter::new(args, start)?;
+    let ignores = filter.root_ignores();
+    // the start is always walked, even if it's a symlink
+    let meta = start.metadata()?;
+    let ancestors = vec![dir_id(start, &meta)?];
+    let children = if args.jobs == 1 {
+        walk_path(args, &filter, start, 0, &ignores, &ancestors)
+    } else {
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(args.jobs)
+            .build()?
+            .install(|| walk_path(args, &filter, start, 0, &ignores, &ancestors))
+    };
+    let mut root = Node {
+        name: to_str,
+        details: EntryDetails::Dir,
+        meta: Meta::new(&meta),
+        link: None,
+        error: None,
+        children: vec![],
+        hidden: false,
+    };
+    match children {
+        Ok(children) => root.children = children,
+        Err(e) => root.error = Some(e.into()),
+    }
+    roll_up(&mut root);
+    if !shown(args, 0) {
+        root.children.clear();
+    }
+    let mut report = Report::default();
+    if root.error.as_ref().is_some_and(EntryError::is_failure) {
+        report.errors += 1;
+    }
+    renderer.entry(&Walked {
+        name: &root.name,
+        last: true,
+        start: true,
+        details: root.details.clone(),
+        meta: &root.meta,
+        link: None,
+        error: root.error.as_ref(),
+        lasts: &vec![],
+    })?;
+    if !root.children.is_empty() {
+        renderer.enter()?;
+        emit(&root.children, &vec![], renderer, &mut report)?;
+        renderer.leave()?;
+    }
+    renderer.finish(&report)?;
+    Ok(report)
+}
+
+/// Whether the entries of a directory at `depth` are within `--depth`.
+fn shown(args: &Args, depth: u32) -> bool {
+    args.depth.is_none_or(|max_depth| depth < max_depth)
 }
 
-fn walk_path<F>(
+fn walk_path(
     args: &Args,
+    filter: &Filter,
     path: &Path,
     depth: u32,
-    lasts: &Vec<bool>,
-    f: &mut F,
-) -> WalkResult<()>
-where
-    F: Fn(&Walked),
-{
-    let to_str = path.to_string_lossy().to_string();
-    if !path.exists() {
-        return Err(Error::NotFound(to_str));
+    ignores: &Ignores,
+    ancestors: &[DirId],
+) -> io::Result<Vec<Node>> {
+    if !shown(args, depth) && !args.needs_sizes() {
+        return Ok(vec![]);
     }
-    if !path.is_dir() {
-        return Err(Error::NotDirectory(to_str));
-    }
-    if path.is_symlink() {
-        // we don't follow symlinks for now
-        return Ok(());
-    }
-    if depth == 0 {
-        f(&Walked {
-            name: &to_str,
-            depth,
-            last: true,
-            start: true,
-            details: EntryDetails::Dir,
-            lasts: &vec![],
-        });
-    }
-    let recurse = args.depth.map_or(true, |max_depth| depth < max_depth);
-    if recurse && path.is_dir() {
-        let read_dir = fs::read_dir(path)?;
-        let mut entries = vec![];
-        for entry in read_dir {
-            let entry = entry?;
-            entries.push(entry);
+    let ignores = filter.descend(ignores, path);
+    let read_dir = fs::read_dir(path)?;
+    let mut entries = vec![];
+    let mut unreadable = vec![];
+    for entry in read_dir {
+        match entry {
+            Ok(entry) => entries.push(entry),
+            // an entry that can't be listed has no name either, but the rest
+            // of the directory is still walked
+            Err(e) => unreadable.push(Node {
+                name: "?".to_string(),
+                details: EntryDetails::File,
+                meta: Meta::default(),
+                link: None,
+                error: Some(e.into()),
+                children: vec![],
+                hidden: false,
+            }),
         }
-        entries.sort_by_key(DirEntry::file_name);
-        let mut iter = entries.iter().filter(|s| filter(args, s)).peekable();
-        while let Some(entry) = iter.next() {
-            let last = iter.peek().is_none();
-            let path = entry.path();
-            let name = path_to_file_name(&path)?;
-            let is_executable = is_executable(&path)?;
-            let details = if path.is_dir() {
-                EntryDetails::Dir
-            } else if is_executable {
-                EntryDetails::Executable
-            } else {
-                EntryDetails::File
-            };
-            let walked = Walked {
-                name: &name,
-                start: false,
-                lasts,
-                details,
-                depth,
-                last,
-            };
-            f(&walked);
-            if path.is_dir() {
-                let mut lasts = lasts.clone();
-                lasts.push(last);
-                walk_path(args, &path, depth + 1, &lasts, f)?;
+    }
+    entries.sort_by_key(DirEntry::file_name);
+    let walk_entry = |dir_entry: &DirEntry| -> Option<Node> {
+        let entry = match Entry::new(dir_entry) {
+            Ok(entry) => entry,
+            // without metadata there's nothing to filter by, so it's always shown
+            Err(e) => {
+                return Some(Node {
+                    name: dir_entry.file_name().to_string_lossy().to_string(),
+                    details: EntryDetails::File,
+                    meta: Meta::default(),
+                    link: None,
+                    error: Some(e.into()),
+                    children: vec![],
+                    hidden: false,
+                })
             }
+        };
+        if !filter.walks(&entry, &ignores) {
+            return None;
         }
+        let mut node = walk_entry(args, filter, &entry, depth, &ignores, ancestors);
+        node.hidden = !filter.shows(&entry);
+        Some(node)
+    };
+    let nodes: Vec<Option<Node>> = if args.jobs == 1 {
+        entries.iter().map(walk_entry).collect()
+    } else {
+        entries.par_iter().map(walk_entry).collect()
+    };
+    let mut nodes: Vec<Node> = nodes.into_iter().flatten().chain(unreadable).collect();
+    // the sort is stable, so ties stay in name order
+    match args.sort {
+        Sort::Name => {}
+        Sort::Size => nodes.sort_by_key(|n| Reverse(n.meta.size)),
+        Sort::Mtime => nodes.sort_by_key(|n| Reverse(n.meta.modified)),
     }
-    Ok(())
+    Ok(nodes)
 }
 
-fn is_executable(path: &Path) -> WalkResult<bool> {
-    let meta = path.metadata()?;
-    let is_executable;
-    #[cfg(unix)]
-    {
-        use std::os::unix::fs::PermissionsExt;
-        is_executable = meta.permissions().mode() & 0o111 != 0;
+fn walk_entry(
+    args: &Args,
+    filter: &Filter,
+    entry: &Entry,
+    depth: u32,
+    ignores: &Ignores,
+    ancestors: &[DirId],
+) -> Node {
+    let name = entry.file_name.to_string_lossy().to_string();
+    // links are shown with their own metadata, but typed by their target
+    let mut node = Node {
+        name,
+        details: EntryDetails::File,
+        meta: Meta::new(&entry.meta),
+        link: None,
+        error: None,
+        children: vec![],
+        hidden: false,
+    };
+    if entry.meta.is_symlink() {
+        match fs::read_link(&entry.path) {
+            Ok(target) => node.link = Some(Link { target }),
+            Err(e) => {
+                node.error = Some(e.into());
+                return node;
+            }
+        }
+    }
+    let Some(target_meta) = &entry.target else {
+        node.error = Some(EntryError::BrokenLink);
+        return node;
+    };
+    if !target_meta.is_dir() {
+        if is_executable(&entry.path, target_meta) {
+            node.details = EntryDetails::Executable;
+        }
+        return node;
+    }
+    node.details = EntryDetails::Dir;
+    if node.link.is_some() && !args.follow {
+        return node;
+    }
+    let id = match dir_id(&entry.path, target_meta) {
+        Ok(id) => id,
+        Err(e) => {
+            node.error = Some(e.into());
+            return node;
+        }
+    };
+    if ancestors.contains(&id) {
+        node.error = Some(EntryError::Recursive);
+        return node;
+    }
+    let mut ancestors = ancestors.to_vec();
+    ancestors.push(id);
+    match walk_path(args, filter, &entry.path, depth + 1, ignores, &ancestors) {
+        Ok(children) => node.children = children,
+        Err(e) => node.error = Some(e.into()),
     }
-    #[cfg(not(unix))]
-    {
-        is_executable = name.ends_with(".exe");
+    roll_up(&mut node);
+    // anything below `--depth` was only walked for its size
+    if !shown(args, depth + 1) {
+        node.children.clear();
     }
-    Ok(is_executable)
+    node
 }
 
-fn filter(args: &Args, entry: &DirEntry) -> bool {
-    let path = entry.path();
-    match entry.file_name().to_str() {
-        Some(name) => {
-            // check for dir only
-            if args.dirs_only && !entry.path().is_dir() {
-                return false;
-            }
-            // check for hidden files
-            if !args.show_hidden && name.starts_with('.') {
-                return false;
-            }
-            if args.executables_only && path.is_file() {
-                if let Ok(false) = is_executable(&path) {
-                    return false;
-                }
-            }
-            true
+/// Sets a directory's size to the total of everything walked below it, then
+/// drops the children that were only walked to be counted.
+fn roll_up(node: &mut Node) {
+    node.meta.size = node.children.iter().map(|c| c.meta.size).sum();
+    node.children.retain(|c| !c.hidden);
+}
+
+fn emit(
+    nodes: &[Node],
+    lasts: &Vec<bool>,
+    renderer: &mut dyn Renderer,
+    report: &mut Report,
+) -> WalkResult<()> {
+    let mut iter = nodes.iter().peekable();
+    while let Some(node) = iter.next() {
+        let last = iter.peek().is_none();
+        match node.details {
+            EntryDetails::Dir => report.directories += 1,
+            EntryDetails::File | EntryDetails::Executable => report.files += 1,
+        }
+        if node.error.as_ref().is_some_and(EntryError::is_failure) {
+            report.errors += 1;
+        }
+        renderer.entry(&Walked {
+            name: &node.name,
+            start: false,
+            lasts,
+            details: node.details.clone(),
+            meta: &node.meta,
+            link: node.link.as_ref(),
+            error: node.error.as_ref(),
+            last,
+        })?;
+        if !node.children.is_empty() {
+            let mut lasts = lasts.clone();
+            lasts.push(last);
+            renderer.enter()?;
+            emit(&node.children, &lasts, renderer, report)?;
+            renderer.leave()?;
         }
-        None => false,
     }
+    Ok(())
+}
+
+#[cfg(unix)]
+pub fn is_executable(_path: &Path, meta: &Metadata) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    meta.permissions().mode() & 0o111 != 0
 }
 
-fn path_to_file_name(p: &Path) -> WalkResult<String> {
-    p.file_name()
-        .ok_or(Error::NoFileName)
-        .map(|f| f.to_string_lossy().to_string())
+#[cfg(not(unix))]
+pub fn is_executable(path: &Path, _meta: &Metadata) -> bool {
+    path.extension().is_some_and(|e| e == "exe")
 }
@@ -0,0 +1,46 @@
+//! Helpers for running `tt` over trees made in a temporary directory.
+
+#![allow(dead_code)]
+
+use std::{
+    fs,
+    path::Path,
+    process::{Command, Output},
+};
+
+/// Creates `paths` under `dir` along with their parents. Paths ending in `/`
+/// are directories; files hold `size` bytes.
+pub fn create(dir: &Path, paths: &[(&str, usize)]) {
+    for (path, size) in paths {
+        let path = dir.join(path);
+        if path.to_string_lossy().ends_with('/') {
+            fs::create_dir_all(&path).unwrap();
+        } else {
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(&path, "x".repeat(*size)).unwrap();
+        }
+    }
+}
+
+/// Runs `tt` from `dir` without color and returns what it printed.
+pub fn tt(dir: &Path, args: &[&str]) -> String {
+    let out = run(dir, args);
+    assert!(out.status.success(), "tt failed: {out:?}");
+    String::from_utf8(out.stdout).unwrap()
+}
+
+/// Like `tt`, for walks that find errors and so exit with status 1.
+pub fn tt_failing(dir: &Path, args: &[&str]) -> String {
+    let out = run(dir, args);
+    assert_eq!(out.status.code(), Some(1), "tt did not fail: {out:?}");
+    String::from_utf8(out.stdout).unwrap()
+}
+
+fn run(dir: &Path, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_tt"))
+        .args(args)
+        .current_dir(dir)
+        .env("NO_COLOR", "1")
+        .output()
+        .unwrap()
+}
@@ -0,0 +1,61 @@
+#![cfg(unix)]
+
+mod common;
+
+use std::{
+    fs::{self, Permissions},
+    os::unix::fs::PermissionsExt,
+    process::Command,
+};
+
+use common::{create, tt_failing};
+
+#[test]
+fn unreadable_dirs_are_shown_and_fail_the_walk() {
+    let dir = tempfile::tempdir().unwrap();
+    create(dir.path(), &[("a", 0), ("locked/f", 0), ("z", 0)]);
+    let locked = dir.path().join("locked");
+    fs::set_permissions(&locked, Permissions::from_mode(0o000)).unwrap();
+    if fs::read_dir(&locked).is_ok() {
+        // running as root, which can read it anyway
+        fs::set_permissions(&locked, Permissions::from_mode(0o755)).unwrap();
+        return;
+    }
+    let out = tt_failing(dir.path(), &[]);
+    fs::set_permissions(&locked, Permissions::from_mode(0o755)).unwrap();
+    assert_eq!(
+        out,
+        "\
+.
+├─ a
+├─ locked [permission denied]
+└─ z
+
+1 directory, 2 files, 1 error
+"
+    );
+}
+
+/// Paths longer than the system allows can't be listed even by root.
+#[test]
+fn paths_too_long_to_read_fail_the_walk() {
+    let dir = tempfile::tempdir().unwrap();
+    let name = "d".repeat(200);
+    let made = Command::new("sh")
+        .args([
+            "-c",
+            "for i in $(seq 25); do mkdir \"$0\" && cd -P \"$0\" || exit 1; done",
+        ])
+        .arg(&name)
+        .current_dir(dir.path())
+        .status()
+        .unwrap();
+    assert!(made.success());
+    create(dir.path(), &[("f", 0)]);
+
+    let out = tt_failing(dir.path(), &[]);
+    assert_eq!(out.matches(" [invalid filename]").count(), 1, "{out}");
+    assert!(out.ends_with(" directories, 1 file, 1 error\n"), "{out}");
+    // the entries after the failed directory are still walked
+    assert!(out.contains("└─ f\n"), "{out}");
+}
@@ -0,0 +1,95 @@
+// Windows does not allow `<` or `"` in file names.
+#![cfg(unix)]
+
+mod common;
+
+use common::{create, tt};
+use serde_json::{json, Value};
+
+/// A directory whose name needs escaping in every markup format.
+const NAME: &str = "a<b&\"c";
+
+fn tree() -> tempfile::TempDir {
+    let dir = tempfile::tempdir().unwrap();
+    create(dir.path(), &[(&format!("{NAME}/f"), 2)]);
+    dir
+}
+
+#[test]
+fn json_parses() {
+    let dir = tree();
+    let out: Value = serde_json::from_str(&tt(dir.path(), &["-f", "json"])).unwrap();
+    assert_eq!(
+        out,
+        json!([
+            {"type": "directory", "name": ".", "contents": [
+                {"type": "directory", "name": NAME, "contents": [
+                    {"type": "file", "name": "f"}
+                ]}
+            ]},
+            {"type": "report", "directories": 1, "files": 1, "errors": 0}
+        ])
+    );
+}
+
+#[test]
+fn xml_parses() {
+    let dir = tree();
+    let out = tt(dir.path(), &["-f", "xml"]);
+    let doc = roxmltree::Document::parse(&out).unwrap();
+    let root = doc.root_element();
+    assert_eq!(root.tag_name().name(), "tree");
+
+    let top = root.first_element_child().unwrap();
+    assert_eq!(top.attribute("name"), Some("."));
+    let sub = top.first_element_child().unwrap();
+    assert_eq!(
+        (sub.tag_name().name(), sub.attribute("name")),
+        ("directory", Some(NAME))
+    );
+    let file = sub.first_element_child().unwrap();
+    assert_eq!(
+        (file.tag_name().name(), file.attribute("name")),
+        ("file", Some("f"))
+    );
+
+    let report = top.next_sibling_element().unwrap();
+    let counts: Vec<_> = report
+        .children()
+        .filter(roxmltree::Node::is_element)
+        .map(|n| (n.tag_name().name(), n.text().unwrap()))
+        .collect();
+    assert_eq!(
+        counts,
+        [("directories", "1"), ("files", "1"), ("errors", "0")]
+    );
+}
+
+#[test]
+fn html_escapes_names() {
+    let dir = tree();
+    let out = tt(dir.path(), &["-f", "html"]);
+    assert!(
+        out.contains(r#"<li class="directory">a&lt;b&amp;&quot;c"#),
+        "{out}"
+    );
+    assert!(!out.contains(NAME), "{out}");
+    assert!(out.contains(r#"<p class="report">1 directory, 1 file</p>"#));
+}
+
+#[test]
+fn ascii_shows_names_as_they_are() {
+    let dir = tree();
+    assert_eq!(
+        tt(dir.path(), &["-f", "ascii", "-s"]),
+        format!(
+            "\
+[   2B]  .
+`-- [   2B]  {NAME}
+    `-- [   2B]  f
+
+1 directory, 1 file
+"
+        )
+    );
+}
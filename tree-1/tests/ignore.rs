@@ -0,0 +1,117 @@
+mod common;
+
+use std::fs;
+
+use common::{create, tt};
+
+/// A repository whose top-level `.gitignore` ignores logs and `build/`, with a
+/// nested one that brings one log back.
+fn repo() -> tempfile::TempDir {
+    let dir = tempfile::tempdir().unwrap();
+    create(
+        dir.path(),
+        &[
+            (".git/", 0),
+            ("build/out.o", 0),
+            ("src/main.rs", 0),
+            ("src/debug.log", 0),
+            ("src/keep.log", 0),
+            ("notes.log", 0),
+        ],
+    );
+    fs::write(dir.path().join(".gitignore"), "*.log\nbuild/\n").unwrap();
+    fs::write(dir.path().join("src/.gitignore"), "!keep.log\n").unwrap();
+    dir
+}
+
+#[test]
+fn nested_gitignore_can_re_include() {
+    let dir = repo();
+    assert_eq!(
+        tt(dir.path(), &[]),
+        "\
+.
+└─ src
+   ├─ keep.log
+   └─ main.rs
+
+1 directory, 2 files
+"
+    );
+}
+
+#[test]
+fn ignores_above_the_start_apply_in_a_repository() {
+    let dir = repo();
+    assert_eq!(
+        tt(&dir.path().join("src"), &[]),
+        "\
+.
+├─ keep.log
+└─ main.rs
+
+0 directories, 2 files
+"
+    );
+}
+
+#[test]
+fn no_ignore_shows_everything() {
+    let dir = repo();
+    assert_eq!(
+        tt(dir.path(), &["--no-ignore"]),
+        "\
+.
+├─ build
+│  └─ out.o
+├─ notes.log
+└─ src
+   ├─ debug.log
+   ├─ keep.log
+   └─ main.rs
+
+2 directories, 5 files
+"
+    );
+}
+
+#[test]
+fn include_and_exclude_globs() {
+    let dir = tempfile::tempdir().unwrap();
+    create(
+        dir.path(),
+        &[
+            ("docs/guide.md", 0),
+            ("src/lib.rs", 0),
+            ("src/gen/api.rs", 0),
+            ("README.md", 0),
+        ],
+    );
+    // include only keeps matching files, but still goes into directories.
+    assert_eq!(
+        tt(dir.path(), &["--include", "*.rs"]),
+        "\
+.
+├─ docs
+└─ src
+   ├─ gen
+   │  └─ api.rs
+   └─ lib.rs
+
+3 directories, 2 files
+"
+    );
+    // exclude matches names or paths below the start, and drops directories
+    // with everything in them.
+    assert_eq!(
+        tt(dir.path(), &["--exclude", "src/gen", "--exclude", "*.md"]),
+        "\
+.
+├─ docs
+└─ src
+   └─ lib.rs
+
+2 directories, 1 file
+"
+    );
+}
@@ -0,0 +1,34 @@
+mod common;
+
+use std::{fs, path::Path};
+
+use common::tt;
+
+/// `fanout` directories per level, `depth` levels deep, with a few files of
+/// different sizes in each.
+fn generate(dir: &Path, fanout: usize, depth: usize) {
+    for i in 0..5 {
+        fs::write(dir.join(format!("file-{i}.txt")), "x".repeat(i * 100)).unwrap();
+    }
+    if depth > 0 {
+        for i in 0..fanout {
+            let sub = dir.join(format!("dir-{i}"));
+            fs::create_dir(&sub).unwrap();
+            generate(&sub, fanout, depth - 1);
+        }
+    }
+}
+
+#[test]
+fn parallel_walk_prints_the_same_as_sequential() {
+    let dir = tempfile::tempdir().unwrap();
+    generate(dir.path(), 4, 3);
+    for args in [&["--size"][..], &["--size", "--sort", "size"], &["-D"]] {
+        let sequential = tt(dir.path(), &[args, &["--jobs", "1"]].concat());
+        // a fixed thread count as well, in case there's only one CPU
+        for jobs in ["0", "4"] {
+            let parallel = tt(dir.path(), &[args, &["--jobs", jobs]].concat());
+            assert_eq!(parallel, sequential, "for {args:?} with --jobs {jobs}");
+        }
+    }
+}
@@ -0,0 +1,58 @@
+mod common;
+
+use common::{create, tt};
+
+#[test]
+fn sizes_count_files_that_are_not_shown() {
+    let dir = tempfile::tempdir().unwrap();
+    create(dir.path(), &[("a/f.txt", 2000), ("a/b/g.rs", 1000)]);
+
+    assert_eq!(
+        tt(dir.path(), &["-s", "-D"]),
+        "\
+[ 2.9K]  .
+└─ [ 2.9K]  a
+   └─ [1000B]  b
+
+2 directories
+"
+    );
+    assert_eq!(
+        tt(dir.path(), &["-s", "--include", "*.rs"]),
+        "\
+[ 2.9K]  .
+└─ [ 2.9K]  a
+   └─ [1000B]  b
+      └─ [1000B]  g.rs
+
+2 directories, 1 file
+"
+    );
+}
+
+#[test]
+fn sort_by_size_puts_the_biggest_first() {
+    let dir = tempfile::tempdir().unwrap();
+    create(
+        dir.path(),
+        &[
+            ("small.txt", 10),
+            ("big/a.txt", 600),
+            ("big/b.txt", 600),
+            ("mid.txt", 1000),
+        ],
+    );
+    assert_eq!(
+        tt(dir.path(), &["-s", "--sort", "size"]),
+        "\
+[ 2.2K]  .
+├─ [ 1.2K]  big
+│  ├─ [ 600B]  a.txt
+│  └─ [ 600B]  b.txt
+├─ [1000B]  mid.txt
+└─ [  10B]  small.txt
+
+1 directory, 4 files
+"
+    );
+}
@@ -0,0 +1,60 @@
+#![cfg(unix)]
+
+mod common;
+
+use std::os::unix::fs::symlink;
+
+use common::{create, tt};
+
+/// `d/sub/up` loops back to `d`, `d/dangling` points nowhere and `s` is a
+/// link to `d/sub`.
+fn links() -> tempfile::TempDir {
+    let dir = tempfile::tempdir().unwrap();
+    create(dir.path(), &[("d/sub/f", 0)]);
+    symlink("..", dir.path().join("d/sub/up")).unwrap();
+    symlink("missing", dir.path().join("d/dangling")).unwrap();
+    symlink("d/sub", dir.path().join("s")).unwrap();
+    dir
+}
+
+#[test]
+fn links_are_shown_but_not_followed() {
+    let dir = links();
+    assert_eq!(
+        tt(dir.path(), &[]),
+        "\
+.
+├─ d
+│  ├─ dangling -> missing [broken link]
+│  └─ sub
+│     ├─ f
+│     └─ up -> ..
+└─ s -> d/sub
+
+4 directories, 2 files
+"
+    );
+}
+
+#[test]
+fn follow_stops_at_loops_and_dangling_links() {
+    let dir = links();
+    assert_eq!(
+        tt(dir.path(), &["--follow"]),
+        "\
+.
+├─ d
+│  ├─ dangling -> missing [broken link]
+│  └─ sub
+│     ├─ f
+│     └─ up -> .. [recursive, not followed]
+└─ s -> d/sub
+   ├─ f
+   └─ up -> ..
+      ├─ dangling -> missing [broken link]
+      └─ sub [recursive, not followed]
+
+6 directories, 4 files
+"
+    );
+}